@@ -5,7 +5,7 @@ use tardis::{
 
 use crate::config::gateway_dto::SgGateway;
 
-use self::http_route_dto::SgHttpRoute;
+use self::{http_route_dto::SgHttpRoute, stream_route_dto::SgStreamRoute};
 
 #[cfg(feature = "k8s")]
 pub mod config_by_k8s;
@@ -20,10 +20,11 @@ pub mod k8s_crd;
 #[cfg(feature = "k8s")]
 mod k8s_crd_spaceroute;
 pub mod plugin_filter_dto;
+pub mod stream_route_dto;
 
 #[allow(unreachable_code)]
 #[allow(unused_variables)]
-pub async fn init(k8s_mode: bool, namespace_or_conf_uri: Option<String>, check_interval_sec: Option<u64>) -> TardisResult<Vec<(SgGateway, Vec<SgHttpRoute>)>> {
+pub async fn init(k8s_mode: bool, namespace_or_conf_uri: Option<String>, check_interval_sec: Option<u64>) -> TardisResult<Vec<(SgGateway, Vec<SgHttpRoute>, Vec<SgStreamRoute>)>> {
     log::info!(
         "[SG.Config] Config initialization mode: {}",
         if k8s_mode {
@@ -2,12 +2,13 @@ use std::collections::HashSet;
 use std::{cmp::Ordering, collections::HashMap, sync::Arc};
 
 use itertools::Itertools;
-use k8s_gateway_api::{Gateway, HttpRoute, HttpRouteFilter};
+use k8s_gateway_api::{BackendRef, Gateway, HttpRoute, HttpRouteFilter, ParentReference, TcpRoute, UdpRoute};
 use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
 use kube::{
     api::ListParams,
     runtime::{watcher, WatchStreamExt},
-    Api, Client, ResourceExt,
+    Api, Client, Resource, ResourceExt,
 };
 use tardis::{
     basic::{error::TardisError, result::TardisResult},
@@ -18,27 +19,36 @@ use tardis::{
 };
 
 use crate::{
-    constants::{self, GATEWAY_ANNOTATION_IGNORE_TLS_VERIFICATION},
-    do_startup,
-    functions::http_route,
+    constants::{
+        self, GATEWAY_ANNOTATION_ACCESS_LOG, GATEWAY_ANNOTATION_IGNORE_TLS_VERIFICATION, GATEWAY_ANNOTATION_METRICS_PORT, GATEWAY_ANNOTATION_PROXY_PROTOCOL,
+        TLS_OPTION_CLIENT_AUTH, TLS_OPTION_CLIENT_CA_SECRET, TLS_OPTION_HTTP3,
+    },
+    do_reload,
+    functions::{http_route, stream_route},
     shutdown,
 };
 
 use super::{
-    gateway_dto::{SgGateway, SgListener, SgParameters, SgProtocol, SgTlsConfig, SgTlsMode},
+    gateway_dto::{
+        SgAccessLogConfig, SgGateway, SgListener, SgMetricsConfig, SgParameters, SgProtocol, SgProxyProtocolConfig, SgProxyProtocolVersion, SgTlsCertificate, SgTlsClientAuth,
+        SgTlsConfig, SgTlsMode,
+    },
     http_route_dto::{
         SgBackendRef, SgHttpHeaderMatch, SgHttpHeaderMatchType, SgHttpPathMatch, SgHttpPathMatchType, SgHttpQueryMatch, SgHttpQueryMatchType, SgHttpRoute, SgHttpRouteMatch,
-        SgHttpRouteRule,
+        SgHttpRouteRule, SgLoadBalancePolicy,
     },
     k8s_crd::SgFilter,
     plugin_filter_dto::SgRouteFilter,
+    stream_route_dto::{SgStreamBackendRef, SgStreamRoute},
 };
 use crate::config::k8s_crd_spaceroute::HttpSpaceroute;
 use crate::constants::{
     BANCKEND_KIND_EXTERNAL, BANCKEND_KIND_EXTERNAL_HTTP, BANCKEND_KIND_EXTERNAL_HTTPS, GATEWAY_ANNOTATION_LANGUAGE, GATEWAY_ANNOTATION_LOG_LEVEL, GATEWAY_ANNOTATION_REDIS_URL,
+    HTTP_ROUTE_ANNOTATION_LB_POLICY, STREAM_ROUTE_ANNOTATION_IDLE_TIMEOUT_MS, STREAM_ROUTE_ANNOTATION_MAX_CONNECTIONS, STREAM_ROUTE_ANNOTATION_PROXY_PROTOCOL,
 };
 use crate::helpers::k8s_helper;
 use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
 
 lazy_static! {
     /// see [SgGateway].name
@@ -48,21 +58,29 @@ lazy_static! {
 
 const GATEWAY_CLASS_NAME: &str = "spacegate";
 
-pub async fn init(namespaces: Option<String>) -> TardisResult<Vec<(SgGateway, Vec<SgHttpRoute>)>> {
-    let (gateway_api, http_spaceroute_api, http_route_api, filter_api): (Api<Gateway>, Api<HttpSpaceroute>, Api<HttpRoute>, Api<SgFilter>) = if let Some(namespaces) = namespaces {
-        (
-            Api::namespaced(get_client().await?, &namespaces),
-            Api::namespaced(get_client().await?, &namespaces),
-            Api::namespaced(get_client().await?, &namespaces),
-            Api::namespaced(get_client().await?, &namespaces),
-        )
+pub async fn init(namespaces: Option<String>) -> TardisResult<Vec<(SgGateway, Vec<SgHttpRoute>, Vec<SgStreamRoute>)>> {
+    let (gateway_api, http_spaceroute_api, http_route_api, filter_api, secret_api): (Api<Gateway>, Api<HttpSpaceroute>, Api<HttpRoute>, Api<SgFilter>, Api<Secret>) =
+        if let Some(namespaces) = &namespaces {
+            (
+                Api::namespaced(get_client().await?, namespaces),
+                Api::namespaced(get_client().await?, namespaces),
+                Api::namespaced(get_client().await?, namespaces),
+                Api::namespaced(get_client().await?, namespaces),
+                Api::namespaced(get_client().await?, namespaces),
+            )
+        } else {
+            (
+                Api::all(get_client().await?),
+                Api::all(get_client().await?),
+                Api::all(get_client().await?),
+                Api::all(get_client().await?),
+                Api::all(get_client().await?),
+            )
+        };
+    let (tcp_route_api, udp_route_api): (Api<TcpRoute>, Api<UdpRoute>) = if let Some(namespaces) = &namespaces {
+        (Api::namespaced(get_client().await?, namespaces), Api::namespaced(get_client().await?, namespaces))
     } else {
-        (
-            Api::all(get_client().await?),
-            Api::all(get_client().await?),
-            Api::all(get_client().await?),
-            Api::all(get_client().await?),
-        )
+        (Api::all(get_client().await?), Api::all(get_client().await?))
     };
 
     let gateway_objs = gateway_api
@@ -88,12 +106,27 @@ pub async fn init(namespaces: Option<String>) -> TardisResult<Vec<(SgGateway, Ve
 
     let http_route_configs: Vec<SgHttpRoute> = process_http_route_config(http_route_objs.into_iter().collect()).await?;
 
+    // The experimental CRDs may be missing or not readable, the gateways start without stream routes then
+    let tcp_route_objs = list_stream_routes(&tcp_route_api).await?;
+    let udp_route_objs = list_stream_routes(&udp_route_api).await?;
+    let (tcp_route_available, udp_route_available) = (tcp_route_objs.is_some(), udp_route_objs.is_some());
+    let stream_route_objs = tcp_route_objs
+        .unwrap_or_default()
+        .into_iter()
+        .map(SgStreamRouteObj::from)
+        .chain(udp_route_objs.unwrap_or_default().into_iter().map(SgStreamRouteObj::from))
+        .collect::<Vec<_>>();
+    let stream_route_objs_versions = stream_route_objs.iter().map(|stream_route_obj| (stream_route_obj.uid(), stream_route_obj.version())).collect::<HashMap<String, String>>();
+    let stream_route_configs = process_stream_route_config(&gateway_configs, stream_route_objs)?;
+
     let config = gateway_configs
         .into_iter()
         .map(|gateway_config| {
             let http_route_configs: Vec<SgHttpRoute> =
                 http_route_configs.iter().filter(|http_route_config| http_route_config.gateway_name == gateway_config.name).cloned().collect::<Vec<SgHttpRoute>>();
-            (gateway_config, http_route_configs)
+            let stream_route_configs: Vec<SgStreamRoute> =
+                stream_route_configs.iter().filter(|stream_route_config| stream_route_config.gateway_name == gateway_config.name).cloned().collect::<Vec<SgStreamRoute>>();
+            (gateway_config, http_route_configs, stream_route_configs)
         })
         .collect();
 
@@ -102,8 +135,58 @@ pub async fn init(namespaces: Option<String>) -> TardisResult<Vec<(SgGateway, Ve
         *gateway_uniques_guard = gateway_uniques;
     }
 
+    let secret_objs: Vec<Secret> =
+        secret_api.list(&ListParams::default()).await.map_err(|error| TardisError::wrap(&format!("[SG.Config] Kubernetes error: {error:?}"), ""))?.into_iter().collect();
+    let mut secret_objs_versions = k8s_helper::get_obj_uid_version_map(&secret_objs);
+
+    let gateway_api_clone = gateway_api.clone();
     let http_spaceroute_api_clone = http_spaceroute_api.clone();
     let http_route_api_clone = http_route_api.clone();
+    let tcp_route_api_clone = tcp_route_api.clone();
+    let udp_route_api_clone = udp_route_api.clone();
+
+    // watch secret, reload the gateways referencing it (e.g. certificate rotation)
+    tardis::tokio::spawn(async move {
+        let ew = watcher::watcher(secret_api, watcher::Config::default()).touched_objects();
+        pin_mut!(ew);
+        while let Some(secret_obj) = ew.try_next().await.unwrap_or_default() {
+            let secret_uid = secret_obj.metadata.uid.clone().unwrap_or_default();
+            let secret_version = secret_obj.metadata.resource_version.clone().unwrap_or_default();
+            if secret_objs_versions.get(&secret_uid) == Some(&secret_version) {
+                // ignore the original object
+                continue;
+            }
+            secret_objs_versions.insert(secret_uid, secret_version);
+            let secret_unique = k8s_helper::get_k8s_obj_unique(&secret_obj);
+            let gateway_objs = match gateway_api_clone.list(&ListParams::default()).await {
+                Ok(gateway_objs) => gateway_objs,
+                Err(error) => {
+                    log::warn!("[SG.Config] Secret {secret_unique} change process error:{error}");
+                    continue;
+                }
+            };
+            for gateway_obj in gateway_objs {
+                if gateway_obj.spec.gateway_class_name != GATEWAY_CLASS_NAME || !is_secret_referenced(&gateway_obj, &secret_unique) {
+                    continue;
+                }
+                log::trace!(
+                    "[SG.Config] Secret {secret_unique} of gateway {} change found",
+                    k8s_helper::get_k8s_obj_unique(&gateway_obj)
+                );
+                overload_gateway(
+                    gateway_obj,
+                    (&http_spaceroute_api_clone, &http_route_api_clone),
+                    (&tcp_route_api_clone, &udp_route_api_clone),
+                )
+                .await;
+            }
+        }
+    });
+
+    let http_spaceroute_api_clone = http_spaceroute_api.clone();
+    let http_route_api_clone = http_route_api.clone();
+    let tcp_route_api_clone = tcp_route_api.clone();
+    let udp_route_api_clone = udp_route_api.clone();
 
     // watch gateway
     tardis::tokio::spawn(async move {
@@ -125,7 +208,12 @@ pub async fn init(namespaces: Option<String>) -> TardisResult<Vec<(SgGateway, Ve
 
             log::trace!("[SG.Config] Gateway config change found");
 
-            overload_gateway(gateway_obj, (&http_spaceroute_api_clone, &http_route_api_clone)).await;
+            overload_gateway(
+                gateway_obj,
+                (&http_spaceroute_api_clone, &http_route_api_clone),
+                (&tcp_route_api_clone, &udp_route_api_clone),
+            )
+            .await;
         }
     });
 
@@ -205,6 +293,79 @@ pub async fn init(namespaces: Option<String>) -> TardisResult<Vec<(SgGateway, Ve
             .expect("[SG.Config] Watcher try_for_each error");
     });
 
+    async fn watch_stream_route(stream_route_obj: SgStreamRouteObj, stream_route_objs_versions: &HashMap<String, String>, stream_route_apis: (&Api<TcpRoute>, &Api<UdpRoute>)) {
+        log::trace!("[SG.Config] stream_route config watch tiger. name:{}", stream_route_obj.unique());
+        if stream_route_objs_versions.get(&stream_route_obj.uid()) == Some(&stream_route_obj.version()) {
+            let namespace = stream_route_obj.metadata.namespace.clone().unwrap_or("default".to_string());
+            let name = stream_route_obj.metadata.name.clone().unwrap_or_default();
+            let client = get_client().await.expect("[SG.Config] Failed to get client");
+            let exists = match stream_route_obj.protocol {
+                SgProtocol::Udp => Api::<UdpRoute>::namespaced(client, &namespace).get_opt(&name).await.ok().flatten().is_some(),
+                _ => Api::<TcpRoute>::namespaced(client, &namespace).get_opt(&name).await.ok().flatten().is_some(),
+            };
+            if exists {
+                // ignore the original object
+                // ignore if obj is some(it's means obj is not deleted)
+                return;
+            }
+        }
+        for parent_ref in &stream_route_obj.parent_refs {
+            let gateway_api: Api<Gateway> = Api::namespaced(
+                get_client().await.expect("[SG.Config] Failed to get client"),
+                parent_ref.namespace.as_ref().or(stream_route_obj.metadata.namespace.as_ref()).unwrap_or(&"default".to_string()),
+            );
+            let gateway_obj = if let Ok(Some(gateway_obj)) = gateway_api.get_opt(&parent_ref.name).await {
+                if gateway_obj.spec.gateway_class_name != GATEWAY_CLASS_NAME {
+                    continue;
+                }
+                gateway_obj
+            } else {
+                continue;
+            };
+
+            log::debug!("[SG.Config] Stream route:{} config change found", stream_route_obj.unique());
+
+            overload_stream_route(gateway_obj, stream_route_apis).await;
+        }
+    }
+
+    let stream_route_objs_versions_clone = stream_route_objs_versions.clone();
+    let stream_route_apis = (tcp_route_api.clone(), udp_route_api.clone());
+    let stream_route_apis_clone = stream_route_apis.clone();
+    // watch tcp_route
+    if tcp_route_available {
+        tardis::tokio::spawn(async move {
+            watcher::watcher(tcp_route_api, watcher::Config::default())
+                .touched_objects()
+                .default_backoff()
+                .try_for_each(|tcp_route_obj| async {
+                    watch_stream_route(
+                        tcp_route_obj.into(),
+                        &stream_route_objs_versions_clone,
+                        (&stream_route_apis_clone.0, &stream_route_apis_clone.1),
+                    )
+                    .await;
+                    Ok(())
+                })
+                .await
+                .expect("[SG.Config] Watcher try_for_each error");
+        });
+    }
+    // watch udp_route
+    if udp_route_available {
+        tardis::tokio::spawn(async move {
+            watcher::watcher(udp_route_api, watcher::Config::default())
+                .touched_objects()
+                .default_backoff()
+                .try_for_each(|udp_route_obj| async {
+                    watch_stream_route(udp_route_obj.into(), &stream_route_objs_versions, (&stream_route_apis.0, &stream_route_apis.1)).await;
+                    Ok(())
+                })
+                .await
+                .expect("[SG.Config] Watcher try_for_each error");
+        });
+    }
+
     let sg_filter_objs: Vec<SgFilter> =
         filter_api.list(&ListParams::default()).await.map_err(|error| TardisError::wrap(&format!("[SG.Config] Kubernetes error: {error:?}"), ""))?.into_iter().collect();
 
@@ -327,8 +488,12 @@ pub async fn init(namespaces: Option<String>) -> TardisResult<Vec<(SgGateway, Ve
                 &Api::all(get_client().await.expect("[SG.Config] Failed to get client")),
                 &Api::all(get_client().await.expect("[SG.Config] Failed to get client")),
             );
+            let stream_route_api = (
+                &Api::all(get_client().await.expect("[SG.Config] Failed to get client")),
+                &Api::all(get_client().await.expect("[SG.Config] Failed to get client")),
+            );
             for gateway_obj in gateway_obj_map.into_values() {
-                overload_gateway(gateway_obj, http_route_api).await;
+                overload_gateway(gateway_obj, http_route_api, stream_route_api).await;
             }
 
             for gateway_obj in http_route_rel_gateway_map.into_values() {
@@ -411,7 +576,87 @@ async fn get_http_spaceroute_by_api(
     Ok(http_route_objs)
 }
 
-async fn overload_gateway(gateway_obj: Gateway, http_route_api_refs: (&Api<HttpSpaceroute>, &Api<HttpRoute>)) {
+/// TCPRoute and UDPRoute only differ in the protocol of the listeners they are attached to.
+struct SgStreamRouteObj {
+    protocol: SgProtocol,
+    metadata: ObjectMeta,
+    parent_refs: Vec<ParentReference>,
+    backend_refs: Vec<BackendRef>,
+}
+
+impl SgStreamRouteObj {
+    fn unique(&self) -> String {
+        k8s_helper::format_k8s_obj_unique(self.metadata.namespace.as_ref(), self.metadata.name.as_deref().unwrap_or_default())
+    }
+
+    fn uid(&self) -> String {
+        self.metadata.uid.clone().unwrap_or_default()
+    }
+
+    fn version(&self) -> String {
+        self.metadata.resource_version.clone().unwrap_or_default()
+    }
+}
+
+impl From<TcpRoute> for SgStreamRouteObj {
+    fn from(tcp_route: TcpRoute) -> Self {
+        SgStreamRouteObj {
+            protocol: SgProtocol::Tcp,
+            metadata: tcp_route.meta().clone(),
+            parent_refs: tcp_route.spec.inner.parent_refs.unwrap_or_default(),
+            backend_refs: tcp_route.spec.rules.into_iter().flat_map(|rule| rule.backend_refs).collect(),
+        }
+    }
+}
+
+impl From<UdpRoute> for SgStreamRouteObj {
+    fn from(udp_route: UdpRoute) -> Self {
+        SgStreamRouteObj {
+            protocol: SgProtocol::Udp,
+            metadata: udp_route.meta().clone(),
+            parent_refs: udp_route.spec.inner.parent_refs.unwrap_or_default(),
+            backend_refs: udp_route.spec.rules.into_iter().flat_map(|rule| rule.backend_refs).collect(),
+        }
+    }
+}
+
+async fn get_stream_route_by_api((tcp_route_api, udp_route_api): (&Api<TcpRoute>, &Api<UdpRoute>)) -> TardisResult<Vec<SgStreamRouteObj>> {
+    let tcp_route_objs = list_stream_routes(tcp_route_api).await?.unwrap_or_default();
+    let udp_route_objs = list_stream_routes(udp_route_api).await?.unwrap_or_default();
+    Ok(tcp_route_objs.into_iter().map(SgStreamRouteObj::from).chain(udp_route_objs.into_iter().map(SgStreamRouteObj::from)).collect())
+}
+
+/// List the TCPRoutes or UDPRoutes, `None` if the experimental CRD is not installed (404) or not readable by the gateway (403).
+async fn list_stream_routes<K>(api: &Api<K>) -> TardisResult<Option<Vec<K>>>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + std::fmt::Debug,
+{
+    match api.list(&ListParams::default()).await {
+        Ok(objs) => Ok(Some(objs.items)),
+        Err(kube::Error::Api(error)) if error.code == 404 || error.code == 403 => {
+            log::warn!("[SG.Config] {} is unavailable, stream routes of this kind are ignored: {}", K::kind(&()), error.message);
+            Ok(None)
+        }
+        Err(error) => Err(TardisError::wrap(&format!("[SG.Config] Kubernetes error: {error:?}"), "")),
+    }
+}
+
+/// Whether the listeners of the gateway use the secret, as certificate or client CA.
+fn is_secret_referenced(gateway_obj: &Gateway, secret_unique: &str) -> bool {
+    gateway_obj.spec.listeners.iter().filter_map(|listener| listener.tls.as_ref()).any(|tls| {
+        let certificate_refs = tls.certificate_refs.as_deref().unwrap_or_default();
+        let ref_namespace = |namespace: Option<&String>| namespace.or(gateway_obj.metadata.namespace.as_ref()).cloned();
+        certificate_refs
+            .iter()
+            .any(|certificate_ref| k8s_helper::format_k8s_obj_unique(ref_namespace(certificate_ref.namespace.as_ref()).as_ref(), &certificate_ref.name) == secret_unique)
+            || tls.options.as_ref().and_then(|options| options.get(TLS_OPTION_CLIENT_CA_SECRET)).is_some_and(|client_ca_secret_name| {
+                let namespace = ref_namespace(certificate_refs.first().and_then(|certificate_ref| certificate_ref.namespace.as_ref()));
+                k8s_helper::format_k8s_obj_unique(namespace.as_ref(), client_ca_secret_name) == secret_unique
+            })
+    })
+}
+
+async fn overload_gateway(gateway_obj: Gateway, http_route_api_refs: (&Api<HttpSpaceroute>, &Api<HttpRoute>), stream_route_api_refs: (&Api<TcpRoute>, &Api<UdpRoute>)) {
     let gateway_unique = k8s_helper::get_k8s_obj_unique(&gateway_obj);
     let gateway_api: Api<Gateway> = Api::namespaced(
         get_client().await.expect("[SG.Config] Failed to get client"),
@@ -440,9 +685,14 @@ async fn overload_gateway(gateway_obj: Gateway, http_route_api_refs: (&Api<HttpS
                     .await
                     .map_err(|error| TardisError::wrap(&format!("[SG.Config] Kubernetes error: {error:?}"), ""))
                     .expect("");
-                shutdown(&gateway_config.name).await.expect("[SG.Config] Failed to shutdown gateway");
+                let stream_route_objs = get_stream_route_by_api(stream_route_api_refs)
+                    .await
+                    .map_err(|error| TardisError::wrap(&format!("[SG.Config] Get StreamRoute Kubernetes error: {error:?}"), ""))
+                    .expect("");
+                let stream_route_configs =
+                    process_stream_route_config(std::slice::from_ref(&gateway_config), stream_route_objs).expect("[SG.Config] Failed to process stream_route config");
                 log::trace!("[SG.Config] Gateway config change to:{:?}", gateway_config);
-                do_startup(gateway_config, http_route_configs).await.expect("[SG.Config] Failed to restart gateway");
+                do_reload(gateway_config, http_route_configs, stream_route_configs).await.expect("[SG.Config] Failed to reload gateway");
             } else {
                 {
                     let mut gateway_uniques_guard = GATEWAY_UNIQUES.write().await;
@@ -528,6 +778,22 @@ async fn overload_http_route(gateway_obj: Gateway, http_route_api_refs: (&Api<Ht
     }
 }
 
+async fn overload_stream_route(gateway_obj: Gateway, stream_route_api_refs: (&Api<TcpRoute>, &Api<UdpRoute>)) {
+    let gateway_config = process_gateway_config(vec![gateway_obj])
+        .await
+        .expect("[SG.Config] Failed to process gateway config for stream_route parent ref")
+        .first()
+        .expect("[SG.Config] Gateway config not found for stream_route parent ref")
+        .clone();
+
+    let stream_route_objs = get_stream_route_by_api(stream_route_api_refs)
+        .await
+        .map_err(|error| TardisError::wrap(&format!("[SG.Config] Get StreamRoute Kubernetes error: {error:?}"), ""))
+        .expect("");
+    let stream_route_configs = process_stream_route_config(std::slice::from_ref(&gateway_config), stream_route_objs).expect("[SG.Config] Failed to process stream_route config");
+    stream_route::init(&gateway_config, stream_route_configs).await.expect("[SG.Config] Failed to re-init stream_route");
+}
+
 async fn process_gateway_config(gateway_objs: Vec<Gateway>) -> TardisResult<Vec<SgGateway>> {
     let mut gateway_configs = Vec::new();
 
@@ -536,29 +802,27 @@ async fn process_gateway_config(gateway_objs: Vec<Gateway>) -> TardisResult<Vec<
         if gateway_obj.spec.addresses.is_some() {
             return Err(TardisError::not_implemented("[SG.Config] Gateway [spec.addresses] not supported yet", ""));
         }
-        if gateway_obj
-            .spec
-            .listeners
-            .iter()
-            .any(|listener| !listener.protocol.eq_ignore_ascii_case("https") && !listener.protocol.eq_ignore_ascii_case("http") && !listener.protocol.eq_ignore_ascii_case("ws"))
-        {
-            return Err(TardisError::not_implemented(
-                "[SG.Config] Gateway [spec.listener.protocol!=HTTPS|HTTP|ws] not supported yet",
-                "",
-            ));
-        }
-        if gateway_obj
-            .spec
-            .listeners
-            .iter()
-            .any(|listener| listener.tls.as_ref().map(|tls| tls.mode.as_ref().map(|mode| !mode.eq_ignore_ascii_case("terminate")).unwrap_or(false)).unwrap_or(false))
-        {
+        if gateway_obj.spec.listeners.iter().any(|listener| {
+            !listener.protocol.eq_ignore_ascii_case("https")
+                && !listener.protocol.eq_ignore_ascii_case("http")
+                && !listener.protocol.eq_ignore_ascii_case("ws")
+                && !listener.protocol.eq_ignore_ascii_case("tls")
+                && !listener.protocol.eq_ignore_ascii_case("tcp")
+                && !listener.protocol.eq_ignore_ascii_case("udp")
+        }) {
             return Err(TardisError::not_implemented(
-                "[SG.Config] Gateway [spec.listener.tls.mode!=TERMINATE] not supported yet",
+                "[SG.Config] Gateway [spec.listener.protocol!=HTTPS|HTTP|ws|TLS|TCP|UDP] not supported yet",
                 "",
             ));
         }
-        if gateway_obj.spec.listeners.iter().any(|listener| listener.tls.as_ref().map(|tls| tls.options.is_some()).unwrap_or(false)) {
+        if gateway_obj.spec.listeners.iter().any(|listener| {
+            listener
+                .tls
+                .as_ref()
+                .and_then(|tls| tls.options.as_ref())
+                .map(|options| options.keys().any(|key| key != TLS_OPTION_CLIENT_AUTH && key != TLS_OPTION_CLIENT_CA_SECRET && key != TLS_OPTION_HTTP3))
+                .unwrap_or(false)
+        }) {
             return Err(TardisError::not_implemented("[SG.Config] Gateway [spec.listener.tls.options] not supported yet", ""));
         }
 
@@ -596,6 +860,13 @@ async fn process_gateway_config(gateway_objs: Vec<Gateway>) -> TardisResult<Vec<
         }
         // Generate gateway configuration
         let gateway_name_without_namespace = gateway_obj.metadata.name.as_ref().ok_or_else(|| TardisError::format_error("[SG.Config] Gateway [metadata.name] is required", ""))?;
+        let proxy_protocol = gateway_obj
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|ann| ann.get(GATEWAY_ANNOTATION_PROXY_PROTOCOL).map(|v| TardisFuns::json.str_to_obj::<SgProxyProtocolConfig>(v)))
+            .transpose()?;
+        let proxy_protocol = &proxy_protocol;
         let gateway_config = SgGateway {
             name: k8s_helper::format_k8s_obj_unique(gateway_obj.namespace().as_ref(), gateway_name_without_namespace),
             parameters: SgParameters {
@@ -613,7 +884,19 @@ async fn process_gateway_config(gateway_objs: Vec<Gateway>) -> TardisResult<Vec<
                 ignore_tls_verification: gateway_obj
                     .metadata
                     .annotations
+                    .clone()
                     .and_then(|ann: std::collections::BTreeMap<String, String>| ann.get(GATEWAY_ANNOTATION_IGNORE_TLS_VERIFICATION).and_then(|v| v.parse::<bool>().ok())),
+                metrics: gateway_obj
+                    .metadata
+                    .annotations
+                    .clone()
+                    .and_then(|ann: std::collections::BTreeMap<String, String>| ann.get(GATEWAY_ANNOTATION_METRICS_PORT).and_then(|v| v.parse::<u16>().ok()))
+                    .map(|port| SgMetricsConfig { port, ..Default::default() }),
+                access_log: gateway_obj
+                    .metadata
+                    .annotations
+                    .and_then(|ann: std::collections::BTreeMap<String, String>| ann.get(GATEWAY_ANNOTATION_ACCESS_LOG).map(|v| TardisFuns::json.str_to_obj::<SgAccessLogConfig>(v)))
+                    .transpose()?,
             },
             listeners: join_all(
                 gateway_obj
@@ -621,55 +904,102 @@ async fn process_gateway_config(gateway_objs: Vec<Gateway>) -> TardisResult<Vec<
                     .listeners
                     .into_iter()
                     .map(|listener| async move {
+                        let http3 = listener
+                            .tls
+                            .as_ref()
+                            .and_then(|tls| tls.options.as_ref())
+                            .and_then(|options| options.get(TLS_OPTION_HTTP3))
+                            .is_some_and(|http3| http3.eq_ignore_ascii_case("true"));
                         let tls = match listener.tls {
+                            // Certificates are not used when passing TLS sessions through
+                            Some(tls) if SgTlsMode::from(tls.mode.clone()) == Some(SgTlsMode::Passthrough) => Some(SgTlsConfig {
+                                mode: SgTlsMode::Passthrough,
+                                ..Default::default()
+                            }),
                             Some(tls) => {
-                                let certificate_ref = tls
+                                let certificate_refs = tls
                                     .certificate_refs
                                     .as_ref()
-                                    .ok_or_else(|| TardisError::format_error("[SG.Config] Gateway [spec.listener.tls.certificateRefs] is required", ""))?
-                                    .get(0)
-                                    .ok_or_else(|| TardisError::format_error("[SG.Config] Gateway [spec.listener.tls.certificateRefs] is empty", ""))?;
-                                let secret_api: Api<Secret> = if let Some(namespace) = &certificate_ref.namespace {
+                                    .ok_or_else(|| TardisError::format_error("[SG.Config] Gateway [spec.listener.tls.certificateRefs] is required", ""))?;
+                                let first_certificate_ref =
+                                    certificate_refs.first().ok_or_else(|| TardisError::format_error("[SG.Config] Gateway [spec.listener.tls.certificateRefs] is empty", ""))?;
+                                let secret_api: Api<Secret> = if let Some(namespace) = &first_certificate_ref.namespace {
                                     Api::namespaced(get_client().await?, namespace)
                                 } else {
                                     Api::all(get_client().await?)
                                 };
-                                let secret_obj =
-                                    secret_api.get(&certificate_ref.name).await.map_err(|error| TardisError::wrap(&format!("[SG.Config] Kubernetes error: {error:?}"), ""))?;
-                                let secret_data = secret_obj
-                                    .data
-                                    .ok_or_else(|| TardisError::format_error(&format!("[SG.Config] Gateway tls secret [{}] data is required", certificate_ref.name), ""))?;
-                                let tls_crt = secret_data.get("tls.crt").ok_or_else(|| {
-                                    TardisError::format_error(&format!("[SG.Config] Gateway tls secret [{}] data [tls.crt] is required", certificate_ref.name), "")
-                                })?;
-                                let tls_key = secret_data.get("tls.key").ok_or_else(|| {
-                                    TardisError::format_error(&format!("[SG.Config] Gateway tls secret [{}] data [tls.key] is required", certificate_ref.name), "")
-                                })?;
+                                // The first certificate is the default one, the others are selected by SNI
+                                let mut certs = Vec::with_capacity(certificate_refs.len());
+                                for certificate_ref in certificate_refs {
+                                    let secret_api: Api<Secret> = if let Some(namespace) = &certificate_ref.namespace {
+                                        Api::namespaced(get_client().await?, namespace)
+                                    } else {
+                                        Api::all(get_client().await?)
+                                    };
+                                    let secret_obj =
+                                        secret_api.get(&certificate_ref.name).await.map_err(|error| TardisError::wrap(&format!("[SG.Config] Kubernetes error: {error:?}"), ""))?;
+                                    let secret_data = secret_obj
+                                        .data
+                                        .ok_or_else(|| TardisError::format_error(&format!("[SG.Config] Gateway tls secret [{}] data is required", certificate_ref.name), ""))?;
+                                    let tls_crt = secret_data.get("tls.crt").ok_or_else(|| {
+                                        TardisError::format_error(&format!("[SG.Config] Gateway tls secret [{}] data [tls.crt] is required", certificate_ref.name), "")
+                                    })?;
+                                    let tls_key = secret_data.get("tls.key").ok_or_else(|| {
+                                        TardisError::format_error(&format!("[SG.Config] Gateway tls secret [{}] data [tls.key] is required", certificate_ref.name), "")
+                                    })?;
+                                    certs.push(SgTlsCertificate {
+                                        key: String::from_utf8(tls_key.0.clone()).expect("[SG.Config] Gateway tls secret [tls.key] is not valid utf8"),
+                                        cert: String::from_utf8(tls_crt.0.clone()).expect("[SG.Config] Gateway tls secret [tls.cert] is not valid utf8"),
+                                    });
+                                }
+                                let default_cert = certs.remove(0);
+                                let options = tls.options.unwrap_or_default();
+                                let client_auth = options.get(TLS_OPTION_CLIENT_AUTH).map(|v| v.parse::<SgTlsClientAuth>()).transpose()?.unwrap_or_default();
+                                let client_ca = if let Some(client_ca_secret_name) = options.get(TLS_OPTION_CLIENT_CA_SECRET) {
+                                    let client_ca_secret_obj =
+                                        secret_api.get(client_ca_secret_name).await.map_err(|error| TardisError::wrap(&format!("[SG.Config] Kubernetes error: {error:?}"), ""))?;
+                                    let client_ca = client_ca_secret_obj.data.as_ref().and_then(|data| data.get("ca.crt")).ok_or_else(|| {
+                                        TardisError::format_error(&format!("[SG.Config] Gateway tls secret [{client_ca_secret_name}] data [ca.crt] is required"), "")
+                                    })?;
+                                    Some(String::from_utf8(client_ca.0.clone()).expect("[SG.Config] Gateway tls secret [ca.crt] is not valid utf8"))
+                                } else {
+                                    None
+                                };
                                 Some(SgTlsConfig {
-                                    mode: SgTlsMode::from(tls.mode).unwrap_or_default(),
-                                    key: String::from_utf8(tls_key.0.clone()).expect("[SG.Config] Gateway tls secret [tls.key] is not valid utf8"),
-                                    cert: String::from_utf8(tls_crt.0.clone()).expect("[SG.Config] Gateway tls secret [tls.cert] is not valid utf8"),
+                                    mode: SgTlsMode::Terminate,
+                                    key: default_cert.key,
+                                    cert: default_cert.cert,
+                                    certs,
+                                    client_auth,
+                                    client_ca,
                                 })
                             }
                             None => None,
                         };
+                        let protocol = match listener.protocol.to_lowercase().as_str() {
+                            "http" => SgProtocol::Http,
+                            "https" => SgProtocol::Https,
+                            "ws" => SgProtocol::Ws,
+                            "tls" => SgProtocol::Tls,
+                            "tcp" => SgProtocol::Tcp,
+                            "udp" => SgProtocol::Udp,
+                            _ => {
+                                return Err(TardisError::not_implemented(
+                                    &format!("[SG.Config] Gateway [spec.listener.protocol={}] not supported yet", listener.protocol),
+                                    "",
+                                ))
+                            }
+                        };
                         let sg_listener = SgListener {
                             name: Some(listener.name),
                             ip: None,
                             port: listener.port,
-                            protocol: match listener.protocol.to_lowercase().as_str() {
-                                "http" => SgProtocol::Http,
-                                "https" => SgProtocol::Https,
-                                "ws" => SgProtocol::Ws,
-                                _ => {
-                                    return Err(TardisError::not_implemented(
-                                        &format!("[SG.Config] Gateway [spec.listener.protocol={}] not supported yet", listener.protocol),
-                                        "",
-                                    ))
-                                }
-                            },
+                            // Udp listeners do not support the proxy protocol
+                            proxy_protocol: proxy_protocol.clone().filter(|_| protocol != SgProtocol::Udp),
+                            protocol,
                             tls,
                             hostname: listener.hostname,
+                            http3,
                         };
                         Ok(sg_listener)
                     })
@@ -762,7 +1092,9 @@ async fn process_http_route_config(mut http_route_objs: Vec<HttpSpaceroute>) ->
             },
             http_route_obj.spec.inner.parent_refs.as_ref().ok_or_else(|| TardisError::format_error("[SG.Config] HttpRoute [spec.parentRefs] is required", ""))?[0].name
         );
+        let lb_policy = http_route_obj.annotations().get(HTTP_ROUTE_ANNOTATION_LB_POLICY).map(|v| TardisFuns::json.str_to_obj::<SgLoadBalancePolicy>(v)).transpose()?;
         let http_route_config = SgHttpRoute {
+            name: Some(k8s_helper::get_k8s_obj_unique(&http_route_obj)),
             gateway_name: rel_gateway_name,
             hostnames: http_route_obj.spec.hostnames.clone(),
             filters: if let Some(name) = &http_route_obj.metadata.name {
@@ -780,6 +1112,7 @@ async fn process_http_route_config(mut http_route_objs: Vec<HttpSpaceroute>) ->
                     let sg_rules = rules
                         .into_iter()
                         .map(|rule| SgHttpRouteRule {
+                            name: None,
                             matches: rule.matches.map(|matches| {
                                 matches
                                     .into_iter()
@@ -835,6 +1168,7 @@ async fn process_http_route_config(mut http_route_objs: Vec<HttpSpaceroute>) ->
                                         // ref https://www.rfc-editor.org/rfc/rfc9110.html#name-methods
                                         // Method is case-sensitive and standardized methods are defined in all-uppercase US-ASCII letters
                                         method: a_match.method.map(|method| vec![method]),
+                                        grpc: None,
                                     })
                                     .collect_vec()
                             }),
@@ -869,13 +1203,20 @@ async fn process_http_route_config(mut http_route_objs: Vec<HttpSpaceroute>) ->
                                             port: backend.inner.port.expect("[SG.Config] unexpected none: http_route backend's port"),
                                             timeout_ms: backend.timeout_ms,
                                             protocol,
+                                            http_version: None,
                                             weight: backend.weight,
                                             filters,
+                                            health_check: None,
+                                            tls: None,
+                                            proxy_protocol: None,
                                         }
                                     })
                                     .collect_vec()
                             }),
                             timeout_ms: rule.timeout_ms,
+                            lb_policy: lb_policy.clone(),
+                            panic_mode: None,
+                            outlier_detection: None,
                         })
                         .collect_vec();
                     Some(sg_rules)
@@ -888,6 +1229,77 @@ async fn process_http_route_config(mut http_route_objs: Vec<HttpSpaceroute>) ->
     Ok(http_route_configs)
 }
 
+/// Each route is attached to the listeners of the parent gateways matching the protocol, `sectionName` and `port` of the parent reference.
+fn process_stream_route_config(gateway_configs: &[SgGateway], mut stream_route_objs: Vec<SgStreamRouteObj>) -> TardisResult<Vec<SgStreamRoute>> {
+    let annotation = |stream_route_obj: &SgStreamRouteObj, key: &str| stream_route_obj.metadata.annotations.as_ref().and_then(|ann| ann.get(key).cloned());
+    stream_route_objs.sort_by(|stream_route_a, stream_route_b| {
+        let (a_priority, b_priority) = (
+            annotation(stream_route_a, constants::ANNOTATION_RESOURCE_PRIORITY).and_then(|a| a.parse::<i64>().ok()).unwrap_or(0),
+            annotation(stream_route_b, constants::ANNOTATION_RESOURCE_PRIORITY).and_then(|a| a.parse::<i64>().ok()).unwrap_or(0),
+        );
+        match b_priority.cmp(&a_priority) {
+            Ordering::Equal => stream_route_a.metadata.creation_timestamp.cmp(&stream_route_b.metadata.creation_timestamp),
+            _ => b_priority.cmp(&a_priority),
+        }
+    });
+    let mut stream_route_configs = Vec::new();
+    for stream_route_obj in stream_route_objs {
+        let stream_route_unique = stream_route_obj.unique();
+        let parse_annotation = |key: &str| {
+            annotation(&stream_route_obj, key)
+                .map(|v| v.parse::<u64>().map_err(|_| TardisError::format_error(&format!("[SG.Config] Stream route {stream_route_unique} annotation [{key}] is not a number"), "")))
+                .transpose()
+        };
+        let idle_timeout_ms = parse_annotation(STREAM_ROUTE_ANNOTATION_IDLE_TIMEOUT_MS)?;
+        let max_connections = parse_annotation(STREAM_ROUTE_ANNOTATION_MAX_CONNECTIONS)?;
+        let proxy_protocol = annotation(&stream_route_obj, STREAM_ROUTE_ANNOTATION_PROXY_PROTOCOL).map(|v| v.parse::<SgProxyProtocolVersion>()).transpose()?;
+        let backends = stream_route_obj
+            .backend_refs
+            .iter()
+            .map(|backend| {
+                let namespace = if backend.inner.kind.as_ref().is_some_and(|kind| kind.eq_ignore_ascii_case(BANCKEND_KIND_EXTERNAL)) {
+                    backend.inner.namespace.clone()
+                } else {
+                    // Services are looked up in the namespace of the route by default
+                    Some(backend.inner.namespace.clone().or(stream_route_obj.metadata.namespace.clone()).unwrap_or("default".to_string()))
+                };
+                Ok(SgStreamBackendRef {
+                    name_or_host: backend.inner.name.clone(),
+                    namespace,
+                    port: backend
+                        .inner
+                        .port
+                        .ok_or_else(|| TardisError::format_error(&format!("[SG.Config] Stream route {stream_route_unique} [spec.rules.backendRefs.port] is required"), ""))?,
+                    weight: backend.weight,
+                    proxy_protocol,
+                })
+            })
+            .collect::<TardisResult<Vec<SgStreamBackendRef>>>()?;
+        for parent_ref in &stream_route_obj.parent_refs {
+            let gateway_unique = k8s_helper::format_k8s_obj_unique(parent_ref.namespace.as_ref().or(stream_route_obj.metadata.namespace.as_ref()), &parent_ref.name);
+            let Some(gateway_config) = gateway_configs.iter().find(|gateway_config| gateway_config.name == gateway_unique) else {
+                continue;
+            };
+            for listener in gateway_config.listeners.iter().filter(|listener| {
+                listener.protocol == stream_route_obj.protocol
+                    && parent_ref.section_name.iter().all(|section_name| listener.name.as_ref() == Some(section_name))
+                    && parent_ref.port.iter().all(|port| listener.port == *port)
+            }) {
+                stream_route_configs.push(SgStreamRoute {
+                    name: stream_route_unique.clone(),
+                    gateway_name: gateway_config.name.clone(),
+                    section_name: listener.name.clone(),
+                    port: Some(listener.port),
+                    backends: backends.clone(),
+                    idle_timeout_ms,
+                    max_connections,
+                });
+            }
+        }
+    }
+    Ok(stream_route_configs)
+}
+
 async fn get_filters_from_cdr(kind: &str, name: &str, namespace: &Option<String>) -> TardisResult<Option<Vec<SgRouteFilter>>> {
     let filter_api: Api<SgFilter> = Api::all(get_client().await?);
     let namespace = namespace.clone().unwrap_or("default".to_string());
@@ -8,9 +8,13 @@ use tardis::{
     tokio::{sync::Mutex, time},
 };
 
-use crate::{do_startup, functions::http_route, shutdown};
+use crate::{
+    do_reload,
+    functions::{http_route, stream_route},
+    shutdown,
+};
 
-use super::{gateway_dto::SgGateway, http_route_dto::SgHttpRoute};
+use super::{gateway_dto::SgGateway, http_route_dto::SgHttpRoute, stream_route_dto::SgStreamRoute};
 use lazy_static::lazy_static;
 
 lazy_static! {
@@ -21,10 +25,12 @@ lazy_static! {
 const CONF_GATEWAY_KEY: &str = "sg:conf:gateway";
 // list: {gateway name} -> {vec<http route config>}
 const CONF_HTTP_ROUTE_KEY: &str = "sg:conf:route:http:";
+// list: {gateway name} -> {vec<stream route config>}
+const CONF_STREAM_ROUTE_KEY: &str = "sg:conf:route:stream:";
 // string: {timestamp}##{changed obj}##{changed gateway name} -> None
 const CONF_CHANGE_TRIGGER: &str = "sg:conf:change:trigger:";
 
-pub async fn init(conf_url: &str, check_interval_sec: u64) -> TardisResult<Vec<(SgGateway, Vec<SgHttpRoute>)>> {
+pub async fn init(conf_url: &str, check_interval_sec: u64) -> TardisResult<Vec<(SgGateway, Vec<SgHttpRoute>, Vec<SgStreamRoute>)>> {
     crate::functions::cache_client::init("", conf_url).await?;
     let cache_client = crate::functions::cache_client::get("").await?;
     let mut config = Vec::new();
@@ -42,7 +48,14 @@ pub async fn init(conf_url: &str, check_interval_sec: u64) -> TardisResult<Vec<(
             .into_iter()
             .map(|v| tardis::TardisFuns::json.str_to_obj::<SgHttpRoute>(&v).map_err(|e| TardisError::format_error(&format!("[SG.Config] Http Route Config parse error {}", e), "")))
             .collect::<TardisResult<Vec<SgHttpRoute>>>()?;
-        config.push((gateway_config, http_route_configs));
+        let stream_route_configs = cache_client.lrangeall(&format!("{CONF_STREAM_ROUTE_KEY}{}", gateway_config.name)).await?;
+        let stream_route_configs = stream_route_configs
+            .into_iter()
+            .map(|v| {
+                tardis::TardisFuns::json.str_to_obj::<SgStreamRoute>(&v).map_err(|e| TardisError::format_error(&format!("[SG.Config] Stream Route Config parse error {}", e), ""))
+            })
+            .collect::<TardisResult<Vec<SgStreamRoute>>>()?;
+        config.push((gateway_config, http_route_configs, stream_route_configs));
     }
     tardis::tokio::spawn(async move {
         let mut interval = time::interval(Duration::from_secs(check_interval_sec));
@@ -73,12 +86,16 @@ pub async fn init(conf_url: &str, check_interval_sec: u64) -> TardisResult<Vec<(
                             .into_iter()
                             .map(|v| tardis::TardisFuns::json.str_to_obj::<SgHttpRoute>(&v).expect("[SG.config] Route config parse error"))
                             .collect::<Vec<SgHttpRoute>>();
+                        let stream_route_configs =
+                            cache_client.lrangeall(&format!("{CONF_STREAM_ROUTE_KEY}{}", gateway_config.name)).await.expect("[SG.Config] cache_client lrangeall failed");
+                        let stream_route_configs = stream_route_configs
+                            .into_iter()
+                            .map(|v| tardis::TardisFuns::json.str_to_obj::<SgStreamRoute>(&v).expect("[SG.config] Stream route config parse error"))
+                            .collect::<Vec<SgStreamRoute>>();
                         match changed_obj {
-                            "gateway" => {
-                                shutdown(changed_gateway_name).await.expect("[SG.Config] shutdown failed");
-                                do_startup(gateway_config, http_route_configs).await.expect("[SG.Config] re-startup failed");
-                            }
+                            "gateway" => do_reload(gateway_config, http_route_configs, stream_route_configs).await.expect("[SG.Config] reload failed"),
                             "httproute" => http_route::init(gateway_config, http_route_configs).await.expect("[SG.Config] http_route re-init failed"),
+                            "streamroute" => stream_route::init(&gateway_config, stream_route_configs).await.expect("[SG.Config] stream_route re-init failed"),
                             _ => {}
                         }
                     } else {
@@ -1,4 +1,4 @@
-use std::{fmt::Display, str::FromStr};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 use tardis::basic::error::TardisError;
@@ -33,8 +33,96 @@ pub struct SgParameters {
     pub log_level: Option<String>,
     /// Gateway language
     pub lang: Option<String>,
-    /// Ignore backend tls verification
+    /// Ignore backend tls verification, backends with their own `ca` are still verified against it
     pub ignore_tls_verification: Option<bool>,
+    /// Expose the traffic metrics of the gateway in Prometheus text format, disabled if not set.
+    pub metrics: Option<SgMetricsConfig>,
+    /// Access log of the gateway, disabled if not set.
+    pub access_log: Option<SgAccessLogConfig>,
+}
+
+/// Listener of the Prometheus metrics endpoint.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgMetricsConfig {
+    /// Ip bound to the metrics listener. Default is 0.0.0.0
+    pub ip: String,
+    /// Port of the metrics listener. Default is 9100
+    pub port: u16,
+    /// Path of the metrics endpoint. Default is `/metrics`
+    pub path: String,
+}
+
+impl Default for SgMetricsConfig {
+    fn default() -> Self {
+        Self {
+            ip: "0.0.0.0".to_string(),
+            port: 9100,
+            path: "/metrics".to_string(),
+        }
+    }
+}
+
+/// Access log configuration.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgAccessLogConfig {
+    pub format: SgAccessLogFormat,
+    /// Template of the `text` format, fields are referenced as `{field}`.
+    ///
+    /// Available fields: `time`, `gateway`, `listener`, `remote_addr`, `method`, `path`, `protocol`, `host`, `user_agent`, `route`, `rule`, `backend`,
+    /// `status`, `upstream_latency_ms`, `total_latency_ms`, `request_size`, `response_size`, `request_id`.
+    pub template: String,
+    pub sink: SgAccessLogSink,
+    /// Ratio of logged requests (0.0 ~ 1.0). Default is 1.0
+    pub sample_ratio: f64,
+    /// Sample ratio of specific routes, the key is the name of the route.
+    pub route_sample_ratios: HashMap<String, f64>,
+}
+
+impl Default for SgAccessLogConfig {
+    fn default() -> Self {
+        Self {
+            format: SgAccessLogFormat::Json,
+            template: r#"{remote_addr} [{time}] "{method} {path} {protocol}" {status} {request_size} {response_size} {total_latency_ms}ms route={route} backend={backend} upstream={upstream_latency_ms}ms request_id={request_id}"#
+                .to_string(),
+            sink: SgAccessLogSink::Stdout,
+            sample_ratio: 1.0,
+            route_sample_ratios: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SgAccessLogFormat {
+    /// One JSON object per line.
+    Json,
+    /// Lines rendered by the template.
+    Text,
+}
+
+/// Where the access log is written.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SgAccessLogSink {
+    Stdout,
+    File {
+        path: String,
+    },
+    /// Files named `{path}.{period}` are created per period, the oldest are removed when there are more than `max_files`.
+    RotatingFile {
+        path: String,
+        rotation: SgAccessLogRotation,
+        max_files: usize,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SgAccessLogRotation {
+    Hourly,
+    Daily,
 }
 
 /// Listener embodies the concept of a logical endpoint where a Gateway accepts network connections.
@@ -53,6 +141,49 @@ pub struct SgListener {
     pub tls: Option<SgTlsConfig>,
     /// `HostName` is used to define the host on which the listener accepts requests.
     pub hostname: Option<String>,
+    /// Also accept HTTP/3 requests over QUIC on the same port (UDP) with the certificates of `tls`,
+    /// clients are told by the `Alt-Svc` header of the HTTPS responses.
+    ///
+    /// Only valid if the Protocol field is “HTTPS”, requires the `http3` feature.
+    #[serde(default)]
+    pub http3: bool,
+    /// Read the PROXY protocol header sent by the load balancer in front of the gateway
+    /// to get the address of the client instead of the load balancer.
+    ///
+    /// Only valid if the Protocol field is “HTTP”, “HTTPS”, “TLS” or “TCP”, HTTP/3 requests are not affected.
+    pub proxy_protocol: Option<SgProxyProtocolConfig>,
+}
+
+/// PROXY protocol configuration of a listener, both version 1 (text) and version 2 (binary) headers are accepted.
+///
+/// Reference: [The PROXY protocol](https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SgProxyProtocolConfig {
+    /// IPs or CIDRs of the peers (load balancers) sending the header, e.g. `10.0.0.0/8`, must not be empty.
+    ///
+    /// The header is required from these peers and ignored from others, whose connections are handled as direct connections.
+    pub trusted_sources: Vec<String>,
+}
+
+/// Version of the PROXY protocol header sent to backends.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SgProxyProtocolVersion {
+    /// Human-readable header, e.g. `PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n`.
+    V1,
+    /// Binary header.
+    V2,
+}
+
+impl FromStr for SgProxyProtocolVersion {
+    type Err = TardisError;
+    fn from_str(version: &str) -> Result<SgProxyProtocolVersion, Self::Err> {
+        match version.to_lowercase().as_str() {
+            "v1" => Ok(SgProxyProtocolVersion::V1),
+            "v2" => Ok(SgProxyProtocolVersion::V2),
+            _ => Err(TardisError::bad_request("SgProxyProtocolVersion parse error", "")),
+        }
+    }
 }
 
 /// ProtocolType defines the application protocol accepted by a Listener.
@@ -67,6 +198,12 @@ pub enum SgProtocol {
     Https,
     Ws,
     Wss,
+    /// Accepts TLS sessions over TCP, see [SgTlsMode::Passthrough].
+    Tls,
+    /// Accepts TCP connections, forwarded by [SgStreamRoute](super::stream_route_dto::SgStreamRoute)s.
+    Tcp,
+    /// Accepts UDP datagrams, forwarded by [SgStreamRoute](super::stream_route_dto::SgStreamRoute)s.
+    Udp,
 }
 
 impl Display for SgProtocol {
@@ -76,21 +213,78 @@ impl Display for SgProtocol {
             SgProtocol::Https => write!(f, "https"),
             SgProtocol::Ws => write!(f, "ws"),
             SgProtocol::Wss => write!(f, "wss"),
+            SgProtocol::Tls => write!(f, "tls"),
+            SgProtocol::Tcp => write!(f, "tcp"),
+            SgProtocol::Udp => write!(f, "udp"),
         }
     }
 }
 
 /// GatewayTLSConfig describes a TLS configuration.
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct SgTlsConfig {
     pub mode: SgTlsMode,
+    /// Not used in [SgTlsMode::Passthrough] mode.
+    #[serde(default)]
     pub key: String,
+    #[serde(default)]
     pub cert: String,
+    /// Additional certificates, selected by the server name (SNI) requested by the client.
+    ///
+    /// Each certificate serves the DNS names of its subject alternative names (or its common name if there are none),
+    /// wildcard names like `*.idealworld.group` match a single label.
+    /// `key` and `cert` are the default certificate, used if the client does not send SNI or no certificate matches.
+    #[serde(default)]
+    pub certs: Vec<SgTlsCertificate>,
+    /// Whether clients are asked for a certificate, verified against `client_ca`.
+    #[serde(default)]
+    pub client_auth: SgTlsClientAuth,
+    /// PEM encoded CA certificates used to verify client certificates, required if `client_auth` is not `none`.
+    #[serde(default)]
+    pub client_ca: Option<String>,
+}
+
+/// PEM encoded certificate chain and private key.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SgTlsCertificate {
+    pub key: String,
+    pub cert: String,
+}
+
+/// Client certificate authentication (mutual TLS) of a listener.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SgTlsClientAuth {
+    /// Client certificates are not requested.
+    #[default]
+    None,
+    /// Client certificates are requested and verified if presented, clients without certificate are accepted.
+    Optional,
+    /// Clients without a valid certificate are rejected during the handshake.
+    Required,
+}
+
+impl FromStr for SgTlsClientAuth {
+    type Err = TardisError;
+    fn from_str(mode: &str) -> Result<SgTlsClientAuth, Self::Err> {
+        match mode.to_lowercase().as_str() {
+            "none" => Ok(SgTlsClientAuth::None),
+            "optional" => Ok(SgTlsClientAuth::Optional),
+            "required" => Ok(SgTlsClientAuth::Required),
+            _ => Err(TardisError::bad_request("SgTlsClientAuth parse error", "")),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, PartialEq, Deserialize, Clone, Default)]
 pub enum SgTlsMode {
+    /// The TLS session is terminated by the gateway, requests are routed like plain HTTP requests.
     Terminate,
+    /// The TLS session is not terminated, the gateway reads the server name (SNI) of the ClientHello
+    /// and forwards the connection as it is to a backend of the route whose hostnames match it.
+    ///
+    /// Only the hostnames of routes are matched and only the first rule with backends is used,
+    /// filters are not executed.
     #[default]
     Passthrough,
 }
@@ -2,13 +2,18 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
-use super::{gateway_dto::SgProtocol, plugin_filter_dto::SgRouteFilter};
+use super::{
+    gateway_dto::{SgProtocol, SgProxyProtocolVersion},
+    plugin_filter_dto::SgRouteFilter,
+};
 
 /// HTTPRoute provides a way to route HTTP requests.
 ///
 /// Reference: [Kubernetes Gateway](https://gateway-api.sigs.k8s.io/references/spec/#gateway.networking.k8s.io%2fv1beta1.HTTPRoute)
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct SgHttpRoute {
+    /// Name of the HTTPRoute, used to identify the route in metrics and logs.
+    pub name: Option<String>,
     /// Associated gateway name.
     pub gateway_name: String,
     /// Hostnames defines a set of hostname that should match against the HTTP Host header to select a HTTPRoute to process the request.
@@ -22,6 +27,8 @@ pub struct SgHttpRoute {
 /// HTTPRouteRule defines semantics for matching an HTTP request based on conditions (matches), processing it (filters), and forwarding the request to an API object
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct SgHttpRouteRule {
+    /// Name of the rule, used to identify the rule in metrics and logs. Default is the index of the rule in the route.
+    pub name: Option<String>,
     /// Matches define conditions used for matching the rule against incoming HTTP requests. Each match is independent, i.e. this rule will be matched if any one of the matches is satisfied.
     pub matches: Option<Vec<SgHttpRouteMatch>>,
     /// Filters define the filters that are applied to requests that match this rule.
@@ -30,6 +37,69 @@ pub struct SgHttpRouteRule {
     pub backends: Option<Vec<SgBackendRef>>,
     /// Timeout define the timeout for requests that match this rule.
     pub timeout_ms: Option<u64>,
+    /// LoadBalancePolicy defines how to choose one of the backends, default is weighted random.
+    pub lb_policy: Option<SgLoadBalancePolicy>,
+    /// PanicMode defines what to do when all backends are unhealthy or ejected, default is to route to all backends.
+    pub panic_mode: Option<SgHealthPanicMode>,
+    /// OutlierDetection defines how backends are ejected based on the results of the requests.
+    pub outlier_detection: Option<SgOutlierDetection>,
+}
+
+/// LoadBalancePolicy specifies how a backend is chosen from the backends of a rule.
+///
+/// Backends with weight `0` (or unspecified) receive no traffic, unless all backends have weight `0`, in which case they are treated equally.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum SgLoadBalancePolicy {
+    /// Randomly choose a backend in proportion to its weight.
+    #[default]
+    Random,
+    /// Choose the backends in turn whatever their weight, backends with weight `0` are skipped as with the other policies.
+    RoundRobin,
+    /// Smooth weighted round-robin, as used by nginx.
+    WeightedRoundRobin,
+    /// Choose the backend with the fewest in-flight requests relative to its weight.
+    LeastConnections,
+    /// Randomly pick two backends and choose the one with fewer in-flight requests.
+    RandomOfTwo,
+    /// Choose the backend by consistent hashing on a request attribute, so the same key is routed to the same backend.
+    /// Falls back to weighted random when the key is missing from the request.
+    ConsistentHash { key: SgLoadBalanceHashKey },
+}
+
+impl fmt::Display for SgLoadBalancePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SgLoadBalancePolicy::Random => write!(f, "random"),
+            SgLoadBalancePolicy::RoundRobin => write!(f, "round_robin"),
+            SgLoadBalancePolicy::WeightedRoundRobin => write!(f, "weighted_round_robin"),
+            SgLoadBalancePolicy::LeastConnections => write!(f, "least_connections"),
+            SgLoadBalancePolicy::RandomOfTwo => write!(f, "random_of_two"),
+            SgLoadBalancePolicy::ConsistentHash { key } => write!(f, "consistent_hash({key})"),
+        }
+    }
+}
+
+/// LoadBalanceHashKey specifies the request attribute used by consistent hashing.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum SgLoadBalanceHashKey {
+    /// Value of the HTTP header with this name.
+    Header { name: String },
+    /// Value of the cookie with this name.
+    Cookie { name: String },
+    /// IP address of the client.
+    SourceIp,
+}
+
+impl fmt::Display for SgLoadBalanceHashKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SgLoadBalanceHashKey::Header { name } => write!(f, "header:{name}"),
+            SgLoadBalanceHashKey::Cookie { name } => write!(f, "cookie:{name}"),
+            SgLoadBalanceHashKey::SourceIp => write!(f, "source_ip"),
+        }
+    }
 }
 
 /// HTTPRouteMatch defines the predicate used to match requests to a given action. Multiple match types are ANDed together, i.e. the match will evaluate to true only if all conditions are satisfied.
@@ -43,6 +113,41 @@ pub struct SgHttpRouteMatch {
     pub query: Option<Vec<SgHttpQueryMatch>>,
     /// Method specifies HTTP method matcher. When specified, this route will be matched only if the request has the specified method.
     pub method: Option<Vec<String>>,
+    /// Grpc specifies gRPC method matcher. When specified, this route will be matched only if the request is a gRPC call of the specified service and method.
+    pub grpc: Option<SgGrpcMethodMatch>,
+}
+
+/// GRPCMethodMatch describes how to select a gRPC route by matching the gRPC request service and method.
+///
+/// Reference: [Kubernetes Gateway](https://gateway-api.sigs.k8s.io/references/spec/#gateway.networking.k8s.io/v1alpha2.GRPCMethodMatch)
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct SgGrpcMethodMatch {
+    /// Type specifies how to match against the service and method.
+    pub kind: SgGrpcMethodMatchType,
+    /// Value of the service to match against, e.g. `helloworld.Greeter`. If not specified, all services match.
+    pub service: Option<String>,
+    /// Value of the method to match against, e.g. `SayHello`. If not specified, all methods match.
+    pub method: Option<String>,
+}
+
+/// GRPCMethodMatchType specifies the semantics of how gRPC service and method should be compared.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SgGrpcMethodMatchType {
+    /// Matches the service and method exactly and with case sensitivity.
+    #[default]
+    Exact,
+    /// Matches if the service and method match the given regular expressions with case sensitivity.
+    Regular,
+}
+
+impl fmt::Display for SgGrpcMethodMatchType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SgGrpcMethodMatchType::Exact => write!(f, "exact"),
+            SgGrpcMethodMatchType::Regular => write!(f, "regular"),
+        }
+    }
 }
 
 /// HTTPPathMatch describes how to select a HTTP route by matching the HTTP request path.
@@ -153,6 +258,8 @@ pub struct SgBackendRef {
     pub timeout_ms: Option<u64>,
     // Protocol specifies the protocol used to talk to the referenced backend.
     pub protocol: Option<SgProtocol>,
+    /// HttpVersion specifies the HTTP version used to talk to the referenced backend, default is HTTP/1.1.
+    pub http_version: Option<SgBackendHttpVersion>,
     /// Weight specifies the proportion of requests forwarded to the referenced backend.
     /// This is computed as weight/(sum of all weights in this BackendRefs list).
     /// For non-zero values, there may be some epsilon from the exact proportion defined here depending on the precision an implementation supports.
@@ -160,4 +267,136 @@ pub struct SgBackendRef {
     pub weight: Option<u16>,
     /// Filters define the filters that are applied to backend that match this hostnames.
     pub filters: Option<Vec<SgRouteFilter>>,
+    /// HealthCheck defines the active health check of the backend, unhealthy backends are not chosen for requests.
+    pub health_check: Option<SgHealthCheck>,
+    /// Tls defines the TLS settings towards `https` backends, the native root certificates are trusted if not set.
+    pub tls: Option<SgBackendTlsConfig>,
+    /// Send a PROXY protocol header of this version to the backend, so it gets the address of the client.
+    ///
+    /// The header carries the address of the client connection, so connections to the backend are only reused
+    /// by requests of the same client connection.
+    /// For passthrough (`tls`) listeners the header is sent at the beginning of the forwarded TLS session.
+    pub proxy_protocol: Option<SgProxyProtocolVersion>,
+}
+
+/// BackendTlsConfig describes how the gateway connects to a backend over TLS.
+///
+/// Backends with the same settings share connection pools.
+#[derive(Default, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[serde(default)]
+pub struct SgBackendTlsConfig {
+    /// PEM encoded CA certificates used to verify the backend instead of the native root certificates.
+    pub ca: Option<String>,
+    /// PEM encoded client certificate chain presented to the backend (mutual TLS), requires `key`.
+    pub cert: Option<String>,
+    /// PEM encoded private key of `cert`.
+    pub key: Option<String>,
+    /// Server name sent as SNI and verified against the backend certificate instead of the backend host.
+    pub sni: Option<String>,
+}
+
+/// BackendHttpVersion specifies the HTTP version used towards a backend.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SgBackendHttpVersion {
+    /// HTTP/1.1 only.
+    #[default]
+    Http1,
+    /// HTTP/2 with prior knowledge, i.e. h2c for `http` backends and h2 without fallback for `https` backends.
+    H2c,
+    /// Negotiate HTTP/2 or HTTP/1.1 by ALPN, only takes effect on `https` backends, `http` backends use HTTP/1.1.
+    Alpn,
+}
+
+impl fmt::Display for SgBackendHttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SgBackendHttpVersion::Http1 => write!(f, "http1"),
+            SgBackendHttpVersion::H2c => write!(f, "h2c"),
+            SgBackendHttpVersion::Alpn => write!(f, "alpn"),
+        }
+    }
+}
+
+/// HealthCheck defines how a backend is actively probed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgHealthCheck {
+    /// Kind specifies the probe type.
+    pub kind: SgHealthCheckKind,
+    /// Interval between two probes.
+    pub interval_ms: u64,
+    /// Timeout of a single probe.
+    pub timeout_ms: u64,
+    /// Path of the HTTP probe request.
+    pub path: String,
+    /// Status codes of the HTTP probe response treated as healthy, default is all 2xx.
+    pub expected_status: Option<Vec<u16>>,
+    /// Number of consecutive successful probes before an unhealthy backend becomes healthy.
+    pub healthy_threshold: u16,
+    /// Number of consecutive failed probes before a healthy backend becomes unhealthy.
+    pub unhealthy_threshold: u16,
+}
+
+impl Default for SgHealthCheck {
+    fn default() -> Self {
+        Self {
+            kind: SgHealthCheckKind::default(),
+            interval_ms: 5000,
+            timeout_ms: 1000,
+            path: "/".to_string(),
+            expected_status: None,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+/// HealthCheckKind specifies how a backend is probed.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SgHealthCheckKind {
+    /// Send a `GET` request to `path`, healthy if the response status is expected.
+    #[default]
+    Http,
+    /// Healthy if a TCP connection can be established.
+    Tcp,
+}
+
+/// OutlierDetection ejects a backend from load balancing after consecutive failed requests (5xx responses, timeouts or connection errors).
+///
+/// An ejected backend returns after the ejection time, which grows with each consecutive ejection.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgOutlierDetection {
+    /// Number of consecutive failed requests before a backend is ejected.
+    pub consecutive_errors: u32,
+    /// Base ejection time, the actual ejection time is this value multiplied by the number of times the backend has been ejected.
+    pub base_ejection_time_ms: u64,
+    /// Maximum ejection time.
+    pub max_ejection_time_ms: u64,
+    /// Maximum percentage of the backends of a rule that can be ejected at the same time, at least one backend can always be ejected.
+    pub max_ejection_percent: u8,
+}
+
+impl Default for SgOutlierDetection {
+    fn default() -> Self {
+        Self {
+            consecutive_errors: 5,
+            base_ejection_time_ms: 30000,
+            max_ejection_time_ms: 300000,
+            max_ejection_percent: 10,
+        }
+    }
+}
+
+/// HealthPanicMode specifies what to do when all backends of a rule are unhealthy or ejected.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SgHealthPanicMode {
+    /// Ignore the health status and choose from all backends.
+    #[default]
+    AllBackends,
+    /// Reject the request with `503 Service Unavailable`.
+    Reject,
 }
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+use super::gateway_dto::SgProxyProtocolVersion;
+
+/// StreamRoute forwards the connections of a `tcp` listener or the datagrams of an `udp` listener to backends,
+/// the bytes are not inspected.
+///
+/// The route is attached to the listeners matching both `section_name` and `port`,
+/// if neither is set it is attached to all `tcp` and `udp` listeners of the gateway.
+/// If multiple routes are attached to a listener the first one is used.
+///
+/// Reference: [Kubernetes Gateway TCPRoute](https://gateway-api.sigs.k8s.io/references/spec/#gateway.networking.k8s.io/v1alpha2.TCPRoute)
+/// and [UDPRoute](https://gateway-api.sigs.k8s.io/references/spec/#gateway.networking.k8s.io/v1alpha2.UDPRoute)
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgStreamRoute {
+    /// Name of the route, used to identify the route in metrics and logs.
+    pub name: String,
+    /// Associated gateway name.
+    pub gateway_name: String,
+    /// Name of the listener the route is attached to.
+    pub section_name: Option<String>,
+    /// Port of the listener the route is attached to.
+    pub port: Option<u16>,
+    /// Backends the connections are forwarded to, chosen randomly in proportion to their weight.
+    pub backends: Vec<SgStreamBackendRef>,
+    /// Connections (or UDP sessions, identified by the client address) without traffic in either direction for this time are closed.
+    ///
+    /// Default is no timeout for TCP and 60 seconds for UDP.
+    pub idle_timeout_ms: Option<u64>,
+    /// Maximum number of concurrent connections (or UDP sessions) of the route, further connections are rejected.
+    pub max_connections: Option<u64>,
+}
+
+/// StreamBackendRef defines a backend of a [SgStreamRoute].
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgStreamBackendRef {
+    /// Name is the kubernetes service name OR host.
+    pub name_or_host: String,
+    /// Namespace is the kubernetes namespace
+    pub namespace: Option<String>,
+    pub port: u16,
+    /// Weight specifies the proportion of connections forwarded to the backend, see [SgBackendRef](super::http_route_dto::SgBackendRef).weight.
+    pub weight: Option<u16>,
+    /// Send a PROXY protocol header of this version to the backend, so it gets the address of the client.
+    ///
+    /// Only valid for `tcp` listeners.
+    pub proxy_protocol: Option<SgProxyProtocolVersion>,
+}
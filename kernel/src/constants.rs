@@ -6,6 +6,28 @@ pub const GATEWAY_ANNOTATION_REDIS_URL: &str = "redis_url";
 pub const GATEWAY_ANNOTATION_LOG_LEVEL: &str = "log_level";
 pub const GATEWAY_ANNOTATION_LANGUAGE: &str = "lang";
 pub const GATEWAY_ANNOTATION_IGNORE_TLS_VERIFICATION: &str = "ignore_tls_verification";
+pub const GATEWAY_ANNOTATION_METRICS_PORT: &str = "metrics_port";
+/// Access log configuration in JSON, see [crate::config::gateway_dto::SgAccessLogConfig]
+pub const GATEWAY_ANNOTATION_ACCESS_LOG: &str = "access_log";
+/// PROXY protocol configuration in JSON of all listeners except `udp` ones, see [crate::config::gateway_dto::SgProxyProtocolConfig]
+pub const GATEWAY_ANNOTATION_PROXY_PROTOCOL: &str = "proxy_protocol";
+
+/// HTTPRoute annotation, load balance policy in JSON of all rules, see [crate::config::http_route_dto::SgLoadBalancePolicy]
+pub const HTTP_ROUTE_ANNOTATION_LB_POLICY: &str = "lb_policy";
+
+/// TCPRoute / UDPRoute annotation, see [crate::config::stream_route_dto::SgStreamRoute].idle_timeout_ms
+pub const STREAM_ROUTE_ANNOTATION_IDLE_TIMEOUT_MS: &str = "idle_timeout_ms";
+/// TCPRoute / UDPRoute annotation, see [crate::config::stream_route_dto::SgStreamRoute].max_connections
+pub const STREAM_ROUTE_ANNOTATION_MAX_CONNECTIONS: &str = "max_connections";
+/// TCPRoute annotation, `v1` or `v2` to send PROXY protocol headers to all backends, see [crate::config::stream_route_dto::SgStreamBackendRef].proxy_protocol
+pub const STREAM_ROUTE_ANNOTATION_PROXY_PROTOCOL: &str = "proxy_protocol";
+
+/// Listener tls option, client certificate authentication mode, see [crate::config::gateway_dto::SgTlsClientAuth]
+pub const TLS_OPTION_CLIENT_AUTH: &str = "spacegate.idealworld.group/client-auth";
+/// Listener tls option, name of the secret (in the namespace of the first certificate) containing the client CA bundle in `ca.crt`
+pub const TLS_OPTION_CLIENT_CA_SECRET: &str = "spacegate.idealworld.group/client-ca-secret";
+/// Listener tls option, `true` to also accept HTTP/3 requests over QUIC, see [crate::config::gateway_dto::SgListener].http3
+pub const TLS_OPTION_HTTP3: &str = "spacegate.idealworld.group/http3";
 
 pub const RAW_HTTP_ROUTE_KIND: &str = "raw.http.route.kind";
 pub const RAW_HTTP_ROUTE_KIND_DEFAULT: &str = "HTTPRoute";
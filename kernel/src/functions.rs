@@ -1,7 +1,19 @@
+pub mod access_log;
 #[cfg(feature = "cache")]
 pub mod cache_client;
+pub mod grpc;
+pub mod health_check;
+#[cfg(feature = "http3")]
+pub mod http3;
 pub mod http_client;
 pub mod http_route;
+pub mod load_balancer;
+pub mod metrics;
+pub mod outlier_detection;
+pub mod proxy_protocol;
 pub mod server;
+pub mod stream_route;
+pub mod tls_passthrough;
+pub mod trace;
 #[cfg(feature = "ws")]
 pub mod websocket;
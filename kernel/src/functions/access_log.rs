@@ -0,0 +1,485 @@
+//! Access log of the gateway.
+//!
+//! Every request processed by a gateway with an access log configured is recorded by a [SgAccessLog], the fields are filled in as the request is routed.
+//! Lines are rendered on the request task and written by a dedicated thread, lines are dropped if the writer can't keep up.
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use http::header::{CONTENT_LENGTH, HOST, USER_AGENT};
+use http::{HeaderMap, HeaderValue, Request, StatusCode};
+use hyper::body::HttpBody;
+use hyper::Body;
+use serde_json::{Map, Value};
+use tardis::basic::{error::TardisError, result::TardisResult};
+use tardis::chrono::{DateTime, SecondsFormat, Utc};
+use tardis::log;
+use tardis::rand::{thread_rng, Rng};
+
+use crate::config::gateway_dto::{SgAccessLogConfig, SgAccessLogFormat, SgAccessLogRotation, SgAccessLogSink};
+
+const FIELDS: [&str; 18] = [
+    "time",
+    "gateway",
+    "listener",
+    "remote_addr",
+    "method",
+    "path",
+    "protocol",
+    "host",
+    "user_agent",
+    "route",
+    "rule",
+    "backend",
+    "status",
+    "upstream_latency_ms",
+    "total_latency_ms",
+    "request_size",
+    "response_size",
+    "request_id",
+];
+
+const QUEUE_SIZE: usize = 8192;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(&'static str),
+}
+
+/// Access logger of a gateway.
+pub struct SgAccessLogger {
+    /// `None` for the JSON format.
+    template: Option<Vec<Segment>>,
+    sample_ratio: f64,
+    route_sample_ratios: HashMap<String, f64>,
+    tx: mpsc::SyncSender<String>,
+}
+
+impl SgAccessLogger {
+    /// Create a logger, the writer thread stops once the logger is dropped.
+    pub fn new(config: &SgAccessLogConfig) -> TardisResult<Self> {
+        let template = match config.format {
+            SgAccessLogFormat::Json => None,
+            SgAccessLogFormat::Text => Some(parse_template(&config.template)?),
+        };
+        let mut writer = SgAccessLogWriter::new(&config.sink)?;
+        let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_SIZE);
+        thread::Builder::new()
+            .name("sg-access-log".to_string())
+            .spawn(move || {
+                while let Ok(line) = rx.recv() {
+                    let mut result = writer.write(&line);
+                    // Flush once the queue is drained
+                    while let Ok(line) = rx.try_recv() {
+                        result = result.and(writer.write(&line));
+                    }
+                    if let Err(error) = result.and(writer.flush()) {
+                        log::warn!("[SG.AccessLog] Write access log error: {error}");
+                    }
+                }
+            })
+            .map_err(|e| TardisError::internal_error(&format!("[SG.AccessLog] Start writer error: {e}"), ""))?;
+        Ok(SgAccessLogger {
+            template,
+            sample_ratio: config.sample_ratio,
+            route_sample_ratios: config.route_sample_ratios.clone(),
+            tx,
+        })
+    }
+
+    fn is_sampled(&self, route: Option<&str>) -> bool {
+        let ratio = route.and_then(|route| self.route_sample_ratios.get(route)).copied().unwrap_or(self.sample_ratio);
+        ratio >= 1.0 || thread_rng().gen::<f64>() < ratio
+    }
+
+    fn render(&self, fields: Vec<(&'static str, Value)>) -> String {
+        match &self.template {
+            None => Value::Object(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect::<Map<_, _>>()).to_string(),
+            Some(template) => {
+                let fields = fields.into_iter().collect::<HashMap<_, _>>();
+                template
+                    .iter()
+                    .map(|segment| match segment {
+                        Segment::Literal(literal) => literal.clone(),
+                        Segment::Field(name) => match fields.get(name) {
+                            Some(Value::String(value)) => value.clone(),
+                            Some(Value::Null) | None => "-".to_string(),
+                            Some(value) => value.to_string(),
+                        },
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+fn parse_template(template: &str) -> TardisResult<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| TardisError::format_error(&format!("[SG.AccessLog] Template {template} has an unclosed field"), ""))? + start;
+        let name = &rest[start + 1..end];
+        let field = FIELDS.iter().find(|field| **field == name).ok_or_else(|| TardisError::format_error(&format!("[SG.AccessLog] Unknown field {name} in template"), ""))?;
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+        segments.push(Segment::Field(field));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.to_string()));
+    }
+    Ok(segments)
+}
+
+enum SgAccessLogWriter {
+    Stdout(io::Stdout),
+    File(BufWriter<File>),
+    RotatingFile(RotatingFile),
+}
+
+impl SgAccessLogWriter {
+    fn new(sink: &SgAccessLogSink) -> TardisResult<Self> {
+        let writer = match sink {
+            SgAccessLogSink::Stdout => SgAccessLogWriter::Stdout(io::stdout()),
+            SgAccessLogSink::File { path } => SgAccessLogWriter::File(BufWriter::new(open_file(&PathBuf::from(path))?)),
+            SgAccessLogSink::RotatingFile { path, rotation, max_files } => SgAccessLogWriter::RotatingFile(RotatingFile::new(PathBuf::from(path), *rotation, *max_files)?),
+        };
+        Ok(writer)
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            SgAccessLogWriter::Stdout(stdout) => writeln!(stdout.lock(), "{line}"),
+            SgAccessLogWriter::File(file) => writeln!(file, "{line}"),
+            SgAccessLogWriter::RotatingFile(file) => file.write(line),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SgAccessLogWriter::Stdout(stdout) => stdout.flush(),
+            SgAccessLogWriter::File(file) => file.flush(),
+            SgAccessLogWriter::RotatingFile(file) => file.file.flush(),
+        }
+    }
+}
+
+fn open_file(path: &Path) -> TardisResult<File> {
+    append_file(path).map_err(|e| TardisError::bad_request(&format!("[SG.AccessLog] Open file {} error: {e}", path.display()), ""))
+}
+
+fn append_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    rotation: SgAccessLogRotation,
+    max_files: usize,
+    period: String,
+    file: BufWriter<File>,
+}
+
+impl RotatingFile {
+    fn new(path: PathBuf, rotation: SgAccessLogRotation, max_files: usize) -> TardisResult<Self> {
+        let period = period_of(rotation, Utc::now());
+        let file = BufWriter::new(open_file(&period_path(&path, &period))?);
+        let rotating_file = RotatingFile {
+            path,
+            rotation,
+            max_files,
+            period,
+            file,
+        };
+        rotating_file.remove_old_files();
+        Ok(rotating_file)
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        let period = period_of(self.rotation, Utc::now());
+        if period != self.period {
+            self.file.flush()?;
+            self.file = BufWriter::new(append_file(&period_path(&self.path, &period))?);
+            self.period = period;
+            self.remove_old_files();
+        }
+        writeln!(self.file, "{line}")
+    }
+
+    fn remove_old_files(&self) {
+        if self.max_files == 0 {
+            return;
+        }
+        let Some(file_name) = self.path.file_name().map(|file_name| format!("{}.", file_name.to_string_lossy())) else {
+            return;
+        };
+        let dir = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()).map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+        let Ok(entries) = fs::read_dir(&dir) else {
+            return;
+        };
+        let mut files =
+            entries.filter_map(|entry| entry.ok()).map(|entry| entry.file_name().to_string_lossy().to_string()).filter(|name| name.starts_with(&file_name)).collect::<Vec<_>>();
+        // Periods are sortable as strings
+        files.sort();
+        let remove_count = files.len().saturating_sub(self.max_files);
+        for file in &files[..remove_count] {
+            if let Err(error) = fs::remove_file(dir.join(file)) {
+                log::warn!("[SG.AccessLog] Remove old file {file} error: {error}");
+            }
+        }
+    }
+}
+
+fn period_of(rotation: SgAccessLogRotation, time: DateTime<Utc>) -> String {
+    match rotation {
+        SgAccessLogRotation::Hourly => time.format("%Y-%m-%d-%H").to_string(),
+        SgAccessLogRotation::Daily => time.format("%Y-%m-%d").to_string(),
+    }
+}
+
+fn period_path(path: &Path, period: &str) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(format!(".{period}"));
+    PathBuf::from(path)
+}
+
+/// Access log record of a request, written when the request finishes if the gateway has an access logger.
+pub struct SgAccessLog {
+    logger: Option<Arc<SgAccessLogger>>,
+    start: Instant,
+    time: DateTime<Utc>,
+    gateway: String,
+    listener: Option<String>,
+    remote_addr: SocketAddr,
+    method: String,
+    path: String,
+    protocol: String,
+    host: Option<String>,
+    user_agent: Option<String>,
+    route: Option<String>,
+    rule: Option<String>,
+    backend: Option<String>,
+    upstream_latency: Option<Duration>,
+    request_size: Option<u64>,
+    request_id: Option<String>,
+}
+
+impl SgAccessLog {
+    pub fn new(gateway_name: &str, remote_addr: SocketAddr, request: &Request<Body>) -> Self {
+        let header = |name| request.headers().get(name).and_then(|value: &HeaderValue| value.to_str().ok()).map(|value| value.to_string());
+        SgAccessLog {
+            logger: None,
+            start: Instant::now(),
+            time: Utc::now(),
+            gateway: gateway_name.to_string(),
+            listener: None,
+            remote_addr,
+            method: request.method().to_string(),
+            path: request.uri().path_and_query().map(|path| path.as_str()).unwrap_or("/").to_string(),
+            protocol: format!("{:?}", request.version()),
+            host: request.uri().host().map(|host| host.to_string()).or_else(|| header(HOST)),
+            user_agent: header(USER_AGENT),
+            route: None,
+            rule: None,
+            backend: None,
+            upstream_latency: None,
+            request_size: body_size(request.headers(), request.body()),
+            request_id: None,
+        }
+    }
+
+    pub fn set_logger(&mut self, logger: Option<Arc<SgAccessLogger>>) {
+        self.logger = logger;
+    }
+
+    pub fn set_listener(&mut self, listener: &str) {
+        self.listener = Some(listener.to_string());
+    }
+
+    pub fn set_route(&mut self, route: &str, rule: Option<&str>) {
+        self.route = Some(route.to_string());
+        self.rule = rule.map(|rule| rule.to_string());
+    }
+
+    pub fn set_backend(&mut self, backend: &str) {
+        self.backend = Some(backend.to_string());
+    }
+
+    pub fn set_upstream_latency(&mut self, latency: Duration) {
+        self.upstream_latency = Some(latency);
+    }
+
+    pub fn set_request_id(&mut self, request_id: &str) {
+        self.request_id = Some(request_id.to_string());
+    }
+
+    /// Write the record with the status and the body size (if known) of the response sent to the client.
+    pub fn finish(self, status: StatusCode, response_size: Option<u64>) {
+        let Some(logger) = &self.logger else {
+            return;
+        };
+        if !logger.is_sampled(self.route.as_deref()) {
+            return;
+        }
+        let line = logger.render(self.fields(status, response_size));
+        if logger.tx.try_send(line).is_err() {
+            log::debug!("[SG.AccessLog] Queue is full, access log dropped");
+        }
+    }
+
+    fn fields(&self, status: StatusCode, response_size: Option<u64>) -> Vec<(&'static str, Value)> {
+        let millis = |duration: Duration| Value::from(duration.as_secs_f64() * 1000.0);
+        vec![
+            ("time", Value::from(self.time.to_rfc3339_opts(SecondsFormat::Millis, true))),
+            ("gateway", Value::from(self.gateway.clone())),
+            ("listener", Value::from(self.listener.clone())),
+            ("remote_addr", Value::from(self.remote_addr.to_string())),
+            ("method", Value::from(self.method.clone())),
+            ("path", Value::from(self.path.clone())),
+            ("protocol", Value::from(self.protocol.clone())),
+            ("host", Value::from(self.host.clone())),
+            ("user_agent", Value::from(self.user_agent.clone())),
+            ("route", Value::from(self.route.clone())),
+            ("rule", Value::from(self.rule.clone())),
+            ("backend", Value::from(self.backend.clone())),
+            ("status", Value::from(status.as_u16())),
+            ("upstream_latency_ms", self.upstream_latency.map(millis).unwrap_or(Value::Null)),
+            ("total_latency_ms", millis(self.start.elapsed())),
+            ("request_size", Value::from(self.request_size)),
+            ("response_size", Value::from(response_size)),
+            ("request_id", Value::from(self.request_id.clone())),
+        ]
+    }
+}
+
+/// Size of the body, known if the body is buffered or the `Content-Length` header is set.
+pub fn body_size(headers: &HeaderMap<HeaderValue>, body: &impl HttpBody) -> Option<u64> {
+    body.size_hint().exact().or_else(|| headers.get(CONTENT_LENGTH).and_then(|value| value.to_str().ok()).and_then(|value| value.parse().ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, net::SocketAddr, sync::Arc, time::Duration};
+
+    use http::{Method, Request, StatusCode};
+    use hyper::Body;
+    use serde_json::Value;
+    use tardis::chrono::{TimeZone, Utc};
+
+    use super::{parse_template, period_of, period_path, RotatingFile, Segment, SgAccessLog, SgAccessLogger};
+    use crate::config::gateway_dto::{SgAccessLogConfig, SgAccessLogFormat, SgAccessLogRotation, SgAccessLogSink};
+
+    fn new_access_log(logger: &Arc<SgAccessLogger>) -> SgAccessLog {
+        let request = Request::builder().method(Method::POST).uri("http://sg.idealworld.group/iam?a=1").header("user-agent", "test").body(Body::from("hello")).unwrap();
+        let mut access_log = SgAccessLog::new("test_gw", "127.0.0.1:8080".parse::<SocketAddr>().unwrap(), &request);
+        access_log.set_logger(Some(logger.clone()));
+        access_log.set_listener("http");
+        access_log.set_route("iam", Some("0"));
+        access_log.set_backend("iam-service");
+        access_log.set_upstream_latency(Duration::from_millis(12));
+        access_log.set_request_id("abc");
+        access_log
+    }
+
+    #[test]
+    fn test_parse_template() {
+        assert_eq!(
+            parse_template("{method} {path} -> {status}").unwrap(),
+            vec![
+                Segment::Field("method"),
+                Segment::Literal(" ".to_string()),
+                Segment::Field("path"),
+                Segment::Literal(" -> ".to_string()),
+                Segment::Field("status")
+            ]
+        );
+        assert!(parse_template("{method} {unknown}").is_err());
+        assert!(parse_template("{method").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let logger = Arc::new(
+            SgAccessLogger::new(&SgAccessLogConfig {
+                format: SgAccessLogFormat::Text,
+                template: "{remote_addr} {method} {path} {status} {upstream_latency_ms} {request_size} {response_size} {request_id}".to_string(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let access_log = new_access_log(&logger);
+        assert_eq!(logger.render(access_log.fields(StatusCode::OK, None)), "127.0.0.1:8080 POST /iam?a=1 200 12.0 5 - abc");
+
+        let logger = Arc::new(SgAccessLogger::new(&SgAccessLogConfig::default()).unwrap());
+        let access_log = new_access_log(&logger);
+        let line: Value = serde_json::from_str(&logger.render(access_log.fields(StatusCode::BAD_GATEWAY, Some(10)))).unwrap();
+        assert_eq!(line["gateway"], "test_gw");
+        assert_eq!(line["listener"], "http");
+        assert_eq!(line["host"], "sg.idealworld.group");
+        assert_eq!(line["user_agent"], "test");
+        assert_eq!(line["route"], "iam");
+        assert_eq!(line["rule"], "0");
+        assert_eq!(line["backend"], "iam-service");
+        assert_eq!(line["status"], 502);
+        assert_eq!(line["response_size"], 10);
+        assert!(line["total_latency_ms"].is_f64());
+    }
+
+    #[test]
+    fn test_sample() {
+        let logger = SgAccessLogger::new(&SgAccessLogConfig {
+            sample_ratio: 0.0,
+            route_sample_ratios: HashMap::from([("iam".to_string(), 1.0)]),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(logger.is_sampled(Some("iam")));
+        assert!(!logger.is_sampled(Some("other")));
+        assert!(!logger.is_sampled(None));
+    }
+
+    #[test]
+    fn test_file_sink() {
+        let dir = std::env::temp_dir().join(format!("sg_access_log_{}", tardis::TardisFuns::field.nanoid()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let logger = Arc::new(
+            SgAccessLogger::new(&SgAccessLogConfig {
+                format: SgAccessLogFormat::Text,
+                template: "{method} {status}".to_string(),
+                sink: SgAccessLogSink::File {
+                    path: path.to_string_lossy().to_string(),
+                },
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        new_access_log(&logger).finish(StatusCode::OK, None);
+        new_access_log(&logger).finish(StatusCode::NOT_FOUND, None);
+        drop(logger);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "POST 200\nPOST 404\n");
+
+        // Only the newest files are kept
+        for period in ["2023-01-01", "2023-01-02", "2023-01-03"] {
+            fs::write(period_path(&path, period), "").unwrap();
+        }
+        let mut rotating_file = RotatingFile::new(path.clone(), SgAccessLogRotation::Daily, 2).unwrap();
+        rotating_file.write("GET 200").unwrap();
+        let current_period = period_of(SgAccessLogRotation::Daily, Utc::now());
+        assert!(!period_path(&path, "2023-01-01").exists());
+        assert!(!period_path(&path, "2023-01-02").exists());
+        assert!(period_path(&path, "2023-01-03").exists());
+        assert!(period_path(&path, &current_period).exists());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(period_of(SgAccessLogRotation::Hourly, Utc.with_ymd_and_hms(2023, 1, 2, 3, 4, 5).unwrap()), "2023-01-02-03");
+    }
+}
@@ -0,0 +1,120 @@
+use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, Response, StatusCode};
+use hyper::Body;
+
+pub const GRPC_CONTENT_TYPE: &str = "application/grpc";
+pub const GRPC_STATUS: &str = "grpc-status";
+pub const GRPC_MESSAGE: &str = "grpc-message";
+
+/// gRPC requests are identified by their content type, e.g. `application/grpc` or `application/grpc+proto`.
+pub fn is_grpc_request(headers: &HeaderMap<HeaderValue>) -> bool {
+    headers.get(CONTENT_TYPE).is_some_and(|content_type| {
+        let content_type = content_type.as_bytes();
+        content_type.starts_with(GRPC_CONTENT_TYPE.as_bytes()) && matches!(content_type.get(GRPC_CONTENT_TYPE.len()), None | Some(b'+') | Some(b';'))
+    })
+}
+
+/// Split the request path of a gRPC call `/{package.Service}/{Method}` into service and method.
+pub fn parse_method_path(path: &str) -> Option<(&str, &str)> {
+    let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+    if service.is_empty() || method.is_empty() || method.contains('/') {
+        None
+    } else {
+        Some((service, method))
+    }
+}
+
+/// Map a HTTP status to a gRPC status code.
+///
+/// Reference: [HTTP to gRPC Status Code Mapping](https://github.com/grpc/grpc/blob/master/doc/http-grpc-status-mapping.md)
+pub fn http_status_to_grpc_status(status: StatusCode) -> u16 {
+    match status.as_u16() {
+        200 => 0,
+        // INTERNAL
+        400 => 13,
+        // UNAUTHENTICATED
+        401 => 16,
+        // PERMISSION_DENIED
+        403 => 7,
+        // UNIMPLEMENTED
+        404 => 12,
+        // DEADLINE_EXCEEDED
+        504 => 4,
+        // UNAVAILABLE
+        429 | 502 | 503 => 14,
+        // UNKNOWN
+        _ => 2,
+    }
+}
+
+/// Build a trailers-only gRPC response, the status is carried in the headers with HTTP status 200.
+pub fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_CONTENT_TYPE));
+    headers.insert(GRPC_STATUS, HeaderValue::from(http_status_to_grpc_status(status)));
+    if !message.is_empty() {
+        if let Ok(message) = HeaderValue::from_str(&urlencoding::encode(message)) {
+            headers.insert(GRPC_MESSAGE, message);
+        }
+    }
+    response
+}
+
+/// Convert a response that is not produced by the gRPC backend (e.g. a `404` of the gateway or a `503` of a filter) into a gRPC error response.
+///
+/// Responses already carrying `grpc-status` are returned as is.
+pub fn into_grpc_response<B: From<Body>>(response: Response<B>) -> Response<B> {
+    if response.status() == StatusCode::OK || response.headers().contains_key(GRPC_STATUS) {
+        return response;
+    }
+    let status = response.status();
+    error_response(status, status.canonical_reason().unwrap_or_default()).map(B::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, Response, StatusCode};
+    use hyper::Body;
+
+    use super::*;
+
+    #[test]
+    fn test_is_grpc_request() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_grpc_request(&headers));
+        for (content_type, is_grpc) in [
+            ("application/grpc", true),
+            ("application/grpc+proto", true),
+            ("application/grpc;charset=utf-8", true),
+            ("application/grpc-web", false),
+            ("application/json", false),
+        ] {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            assert_eq!(is_grpc_request(&headers), is_grpc, "{content_type}");
+        }
+    }
+
+    #[test]
+    fn test_parse_method_path() {
+        assert_eq!(parse_method_path("/helloworld.Greeter/SayHello"), Some(("helloworld.Greeter", "SayHello")));
+        assert_eq!(parse_method_path("/helloworld.Greeter/"), None);
+        assert_eq!(parse_method_path("/helloworld.Greeter"), None);
+        assert_eq!(parse_method_path("/a/b/c"), None);
+    }
+
+    #[test]
+    fn test_into_grpc_response() {
+        let response = into_grpc_response(Response::builder().status(StatusCode::SERVICE_UNAVAILABLE).body(Body::from("unavailable")).unwrap());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), GRPC_CONTENT_TYPE);
+        assert_eq!(response.headers().get(GRPC_STATUS).unwrap(), "14");
+        assert_eq!(response.headers().get(GRPC_MESSAGE).unwrap(), "Service%20Unavailable");
+
+        let response = into_grpc_response(Response::builder().status(StatusCode::GATEWAY_TIMEOUT).body(Body::empty()).unwrap());
+        assert_eq!(response.headers().get(GRPC_STATUS).unwrap(), "4");
+
+        let response = into_grpc_response(Response::builder().status(StatusCode::NOT_FOUND).header(GRPC_STATUS, "5").body(Body::empty()).unwrap());
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers().get(GRPC_STATUS).unwrap(), "5");
+    }
+}
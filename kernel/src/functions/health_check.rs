@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use http::{HeaderMap, Method};
+use hyper::{client::connect::Connect, Body, Client};
+use tardis::basic::result::TardisResult;
+use tardis::log;
+use tardis::tokio::{self, net::TcpStream, time::timeout};
+
+use crate::config::http_route_dto::{SgHealthCheck, SgHealthCheckKind};
+use crate::instance::SgBackendInst;
+use crate::plugins::context::AvailableBackendInst;
+
+use super::http_client;
+
+/// Health status of a backend, maintained by the active health check.
+pub struct SgBackendHealth {
+    healthy: AtomicBool,
+}
+
+impl Default for SgBackendHealth {
+    fn default() -> Self {
+        Self { healthy: AtomicBool::new(true) }
+    }
+}
+
+impl SgBackendHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed)
+    }
+}
+
+/// Start probing the backend in the background.
+///
+/// The task only holds a weak reference to the health status, so it stops once the backend instance is dropped (e.g. the routes are reloaded).
+pub(crate) fn start<C: Connect + Clone + Send + Sync + 'static>(backend: &SgBackendInst, health_check: SgHealthCheck, client: Client<C>) {
+    let health = Arc::downgrade(&backend.health);
+    let backend = AvailableBackendInst::cloned_from(backend);
+    tokio::spawn(run(health, backend, health_check, client));
+}
+
+async fn run<C: Connect + Clone + Send + Sync + 'static>(health: Weak<SgBackendHealth>, backend: AvailableBackendInst, health_check: SgHealthCheck, client: Client<C>) {
+    let target = backend.get_base_url();
+    let mut interval = tokio::time::interval(Duration::from_millis(health_check.interval_ms.max(1)));
+    let mut successes = 0;
+    let mut failures = 0;
+    loop {
+        interval.tick().await;
+        if health.strong_count() == 0 {
+            log::trace!("[SG.HealthCheck] Backend {target} is removed, stop health check");
+            break;
+        }
+        let healthy = match probe(&backend, &health_check, &client).await {
+            Ok(healthy) => healthy,
+            Err(error) => {
+                log::trace!("[SG.HealthCheck] Probe backend {target} error: {error}");
+                false
+            }
+        };
+        if healthy {
+            successes += 1;
+            failures = 0;
+        } else {
+            failures += 1;
+            successes = 0;
+        }
+        let Some(health) = health.upgrade() else {
+            break;
+        };
+        if health.is_healthy() && failures >= health_check.unhealthy_threshold.max(1) {
+            log::warn!("[SG.HealthCheck] Backend {target} is unhealthy after {failures} failed probes");
+            health.set_healthy(false);
+        } else if !health.is_healthy() && successes >= health_check.healthy_threshold.max(1) {
+            log::info!("[SG.HealthCheck] Backend {target} is healthy again after {successes} successful probes");
+            health.set_healthy(true);
+        }
+    }
+}
+
+async fn probe<C: Connect + Clone + Send + Sync + 'static>(backend: &AvailableBackendInst, health_check: &SgHealthCheck, client: &Client<C>) -> TardisResult<bool> {
+    match health_check.kind {
+        SgHealthCheckKind::Http => {
+            let path = if health_check.path.starts_with('/') {
+                health_check.path.clone()
+            } else {
+                format!("/{}", health_check.path)
+            };
+            let url = format!("{}{}", backend.get_base_url(), path);
+            let response = http_client::send_request(client, Method::GET, &url, Body::empty(), &HeaderMap::new(), Some(health_check.timeout_ms)).await?;
+            let status = response.status();
+            Ok(match &health_check.expected_status {
+                Some(expected_status) => expected_status.contains(&status.as_u16()),
+                None => status.is_success(),
+            })
+        }
+        SgHealthCheckKind::Tcp => {
+            let host = format!("{}{}", backend.name_or_host, backend.namespace.as_ref().map(|n| format!(".{n}")).unwrap_or("".to_string()));
+            Ok(matches!(
+                timeout(Duration::from_millis(health_check.timeout_ms), TcpStream::connect((host.as_str(), backend.port))).await,
+                Ok(Ok(_))
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tardis::tokio::{self, net::TcpListener};
+
+    use super::start;
+    use crate::config::http_route_dto::{SgHealthCheck, SgHealthCheckKind};
+    use crate::functions::http_client;
+    use crate::instance::SgBackendInst;
+
+    #[tokio::test]
+    async fn test_tcp_health_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let backend = SgBackendInst {
+            name_or_host: "127.0.0.1".to_string(),
+            port,
+            ..Default::default()
+        };
+        let health_check = SgHealthCheck {
+            kind: SgHealthCheckKind::Tcp,
+            interval_ms: 50,
+            timeout_ms: 50,
+            healthy_threshold: 1,
+            unhealthy_threshold: 2,
+            ..Default::default()
+        };
+        start(&backend, health_check, http_client::init().unwrap().clone());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(backend.health.is_healthy());
+
+        drop(listener);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!backend.health.is_healthy());
+
+        let _listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(backend.health.is_healthy());
+
+        // The task stops after the backend is dropped
+        let health = Arc::downgrade(&backend.health);
+        drop(backend);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(health.upgrade().is_none());
+    }
+}
@@ -0,0 +1,156 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use h3::error::ErrorLevel;
+use h3::server::RequestStream;
+use http::header::{CONNECTION, TRANSFER_ENCODING, UPGRADE};
+use http::{HeaderValue, Request, Response, StatusCode};
+use hyper::body::{Buf, Bytes, HttpBody};
+use hyper::Body;
+use quinn::{Connecting, Endpoint};
+use rustls::{Certificate, ServerConfig};
+use tardis::{
+    basic::{error::TardisError, result::TardisResult},
+    log,
+    tokio::{self, sync::watch::Receiver},
+};
+
+use crate::plugins::context::{SgClientCertInfo, SgGuardedBody};
+
+use super::server;
+
+const ALPN_H3: &[u8] = b"h3";
+/// How long clients may remember that HTTP/3 is available, in seconds.
+const ALT_SVC_MAX_AGE: u32 = 86400;
+
+/// Build the QUIC configuration from the TLS configuration of the HTTPS listener,
+/// the certificates and client authentication are shared.
+pub(crate) fn build_server_config(tls_cfg: &ServerConfig) -> quinn::ServerConfig {
+    let mut tls_cfg = tls_cfg.clone();
+    tls_cfg.alpn_protocols = vec![ALPN_H3.to_vec()];
+    quinn::ServerConfig::with_crypto(Arc::new(tls_cfg))
+}
+
+/// `Alt-Svc` header value announcing HTTP/3 on the port of the HTTPS listener.
+pub(crate) fn alt_svc(port: u16) -> HeaderValue {
+    HeaderValue::from_str(&format!("h3=\":{port}\"; ma={ALT_SVC_MAX_AGE}")).expect("[SG.Http3] Unexpected invalid Alt-Svc header")
+}
+
+pub(crate) fn bind(addr: SocketAddr, tls_cfg: &ServerConfig) -> TardisResult<Endpoint> {
+    Endpoint::server(build_server_config(tls_cfg), addr).map_err(|error| TardisError::bad_request(&format!("[SG.Http3] Bind address error: {error}"), ""))
+}
+
+/// Accept QUIC connections until the gateway is shut down,
+/// the requests are processed by the same pipeline as HTTPS requests.
+pub(crate) async fn serve(gateway_name: Arc<String>, endpoint: Endpoint, mut shutdown_rx: Receiver<()>) -> Result<(), hyper::Error> {
+    let local_addr = endpoint.local_addr().expect("[SG.Http3] Unexpected unbound endpoint");
+    loop {
+        tokio::select! {
+            connecting = endpoint.accept() => match connecting {
+                Some(connecting) => {
+                    let gateway_name = gateway_name.clone();
+                    tokio::spawn(async move {
+                        if let Err(error) = process_connection(gateway_name, connecting, local_addr).await {
+                            log::warn!("[SG.Http3] Connection failed: {error}");
+                        }
+                    });
+                }
+                None => return Ok(()),
+            },
+            _ = shutdown_rx.changed() => {
+                endpoint.close(0u32.into(), b"shutdown");
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn process_connection(gateway_name: Arc<String>, connecting: Connecting, local_addr: SocketAddr) -> TardisResult<()> {
+    let connection = connecting.await.map_err(|error| TardisError::bad_request(&format!("[SG.Http3] Handshake failed: {error}"), ""))?;
+    let remote_addr = connection.remote_address();
+    let client_cert = connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<Certificate>>().ok())
+        .and_then(|certs| certs.first().and_then(|cert| server::parse_client_cert(&cert.0)));
+    let mut connection = h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(connection))
+        .await
+        .map_err(|error| TardisError::bad_request(&format!("[SG.Http3] Connection from {remote_addr} failed: {error}"), ""))?;
+    loop {
+        match connection.accept().await {
+            Ok(Some((request, stream))) => {
+                let gateway_name = gateway_name.clone();
+                let client_cert = client_cert.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = process_request(gateway_name, (remote_addr, local_addr), client_cert, request, stream).await {
+                        log::warn!("[SG.Http3] Request from {remote_addr} failed: {error}");
+                    }
+                });
+            }
+            Ok(None) => return Ok(()),
+            Err(error) if matches!(error.get_error_level(), ErrorLevel::StreamError) => {
+                log::debug!("[SG.Http3] Request from {remote_addr} failed: {error}");
+            }
+            Err(error) => {
+                // Also when the client closes the connection
+                log::debug!("[SG.Http3] Connection from {remote_addr} closed: {error}");
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn process_request(
+    gateway_name: Arc<String>,
+    remote_and_local_addr: (SocketAddr, SocketAddr),
+    client_cert: Option<SgClientCertInfo>,
+    request: Request<()>,
+    stream: RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+) -> Result<(), h3::Error> {
+    let (mut send_stream, mut recv_stream) = stream.split();
+    let body = Body::wrap_stream(async_stream::stream! {
+        loop {
+            match recv_stream.recv_data().await {
+                Ok(Some(mut chunk)) => yield Ok(chunk.copy_to_bytes(chunk.remaining())),
+                Ok(None) => break,
+                Err(error) => {
+                    yield Err(error);
+                    break;
+                }
+            }
+        }
+    });
+    let (parts, _) = request.into_parts();
+    let mut request = Request::from_parts(parts, body);
+    if let Some(client_cert) = client_cert {
+        request.extensions_mut().insert(client_cert);
+    }
+    let response = match server::process(gateway_name, Arc::new("https".to_string()), remote_and_local_addr, request).await {
+        Ok(response) => response,
+        Err(error) => {
+            log::warn!("[SG.Http3] Response error: {error}");
+            let mut response = Response::new(SgGuardedBody::default());
+            *response.status_mut() = StatusCode::BAD_GATEWAY;
+            response
+        }
+    };
+    let (mut parts, mut body) = response.into_parts();
+    // Connection-specific headers are not allowed in HTTP/3
+    for name in [CONNECTION, TRANSFER_ENCODING, UPGRADE] {
+        parts.headers.remove(name);
+    }
+    parts.headers.remove("keep-alive");
+    send_stream.send_response(Response::from_parts(parts, ())).await?;
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => send_stream.send_data(chunk).await?,
+            Err(error) => {
+                log::warn!("[SG.Http3] Response body error: {error}");
+                break;
+            }
+        }
+    }
+    if let Ok(Some(trailers)) = body.trailers().await {
+        send_stream.send_trailers(trailers).await?;
+    }
+    send_stream.finish().await
+}
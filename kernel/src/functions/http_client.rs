@@ -1,22 +1,53 @@
 use std::{
-    sync::{Arc, OnceLock},
-    time::Duration,
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock, RwLock,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
-use crate::{config::gateway_dto::SgProtocol, plugins::context::SgRoutePluginContext};
-use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
-use hyper::{client::HttpConnector, Body, Client, Error};
+use crate::{
+    config::{
+        gateway_dto::SgProtocol,
+        http_route_dto::{SgBackendHttpVersion, SgBackendTlsConfig},
+    },
+    functions::{server, trace::SgSpanKind},
+    plugins::context::SgRoutePluginContext,
+};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri};
+use hyper::{
+    client::{connect::Connect, Builder, HttpConnector},
+    service::Service,
+    Body, Client, Error,
+};
 use hyper_rustls::{ConfigBuilderExt, HttpsConnector};
 use tardis::{
     basic::{error::TardisError, result::TardisResult},
     log,
-    tokio::time::timeout,
+    tokio::{io::AsyncWriteExt, net::TcpStream, time::timeout},
 };
 
 const DEFAULT_TIMEOUT_MS: u64 = 5000;
+/// Idle connections and clients sending PROXY protocol headers are released after this duration.
+const PROXY_PROTOCOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// Maximum number of clients sending PROXY protocol headers per gateway, the least recently used ones are released beyond.
+const PROXY_PROTOCOL_MAX_CLIENTS: usize = 10000;
 
 static DEFAULT_CLIENT: OnceLock<Client<HttpsConnector<HttpConnector>>> = OnceLock::new();
 
+/// Clients are shared by the backends of a gateway with the same HTTP version and TLS profile, the profiles are compared by value.
+type ClientKey = (SgBackendHttpVersion, bool, Option<Arc<SgBackendTlsConfig>>);
+
+/// Clients by gateway name.
+fn get_clients() -> &'static RwLock<HashMap<String, HashMap<ClientKey, Client<HttpsConnector<HttpConnector>>>>> {
+    static CLIENTS: OnceLock<RwLock<HashMap<String, HashMap<ClientKey, Client<HttpsConnector<HttpConnector>>>>>> = OnceLock::new();
+    CLIENTS.get_or_init(Default::default)
+}
+
 pub fn init() -> TardisResult<&'static Client<HttpsConnector<HttpConnector>>> {
     if DEFAULT_CLIENT.get().is_none() {
         let _ = DEFAULT_CLIENT.set(do_init(false)?);
@@ -28,19 +59,226 @@ pub fn get_ignore_validation_clint() -> TardisResult<Client<HttpsConnector<HttpC
     do_init(true)
 }
 
+/// Get the client for the HTTP version and TLS profile of a backend of the gateway, clients are created on first use and shared afterwards.
+pub fn get_client(
+    gateway_name: &str,
+    http_version: &SgBackendHttpVersion,
+    ignore_validation: bool,
+    tls: Option<&Arc<SgBackendTlsConfig>>,
+) -> TardisResult<Client<HttpsConnector<HttpConnector>>> {
+    let key = (http_version.clone(), ignore_validation, tls.cloned());
+    if let Some(client) = get_clients().read().unwrap_or_else(|e| e.into_inner()).get(gateway_name).and_then(|clients| clients.get(&key)) {
+        return Ok(client.clone());
+    }
+    let client = do_init_with_profile(http_version, ignore_validation, tls.map(|tls| tls.as_ref()))?;
+    Ok(get_clients().write().unwrap_or_else(|e| e.into_inner()).entry(gateway_name.to_string()).or_default().entry(key).or_insert(client).clone())
+}
+
+/// Drop the shared clients of the gateway, so that the clients of TLS profiles no longer configured are released
+/// when its routes are initialized again or it is removed.
+///
+/// Clients still in use are kept alive by their users, the others are created again on next use.
+pub(crate) fn clear_clients(gateway_name: &str) {
+    get_clients().write().unwrap_or_else(|e| e.into_inner()).remove(gateway_name);
+    get_tls_configs().write().unwrap_or_else(|e| e.into_inner()).remove(gateway_name);
+    get_proxy_protocol_clients().write().unwrap_or_else(|e| e.into_inner()).clients.remove(gateway_name);
+}
+
 fn do_init(ignore_validation: bool) -> TardisResult<Client<HttpsConnector<HttpConnector>>> {
-    fn get_tls_config(ignore: bool) -> rustls::ClientConfig {
-        if ignore {
-            get_rustls_config_dangerous()
-        } else {
-            rustls::ClientConfig::builder().with_safe_defaults().with_native_roots().with_no_client_auth()
+    do_init_with_profile(&SgBackendHttpVersion::Http1, ignore_validation, None)
+}
+
+fn do_init_with_profile(http_version: &SgBackendHttpVersion, ignore_validation: bool, tls: Option<&SgBackendTlsConfig>) -> TardisResult<Client<HttpsConnector<HttpConnector>>> {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    Ok(build_client(http, &mut Client::builder(), http_version, get_tls_config(ignore_validation, tls)?, tls))
+}
+
+fn build_client<C>(
+    connector: C,
+    builder: &mut Builder,
+    http_version: &SgBackendHttpVersion,
+    tls_config: rustls::ClientConfig,
+    tls: Option<&SgBackendTlsConfig>,
+) -> Client<HttpsConnector<C>>
+where
+    HttpsConnector<C>: Connect + Clone + Send + Sync + 'static,
+{
+    let https = hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(tls_config).https_or_http();
+    let https = match tls.and_then(|tls| tls.sni.clone()) {
+        Some(sni) => https.with_server_name(sni),
+        None => https,
+    };
+    match http_version {
+        SgBackendHttpVersion::Http1 => builder.build(https.enable_http1().wrap_connector(connector)),
+        SgBackendHttpVersion::H2c => builder.http2_only(true).build(https.enable_http2().wrap_connector(connector)),
+        // hyper switches to HTTP/2 if the connector reports that h2 is negotiated
+        SgBackendHttpVersion::Alpn => builder.build(https.enable_all_versions().wrap_connector(connector)),
+    }
+}
+
+/// Clients sending PROXY protocol headers, keyed by the profile and the header.
+type ProxyProtocolClientKey = (SgBackendHttpVersion, bool, Option<Arc<SgBackendTlsConfig>>, Vec<u8>);
+
+struct ProxyProtocolClients {
+    /// Clients by gateway name.
+    clients: HashMap<String, HashMap<ProxyProtocolClientKey, ProxyProtocolClient>>,
+    last_purge: Instant,
+}
+
+struct ProxyProtocolClient {
+    client: Client<HttpsConnector<SgProxyProtocolConnector>>,
+    /// Last time the client was used, in milliseconds since [proxy_protocol_clock_origin], updated under the read lock.
+    last_used: AtomicU64,
+}
+
+fn get_proxy_protocol_clients() -> &'static RwLock<ProxyProtocolClients> {
+    static PROXY_PROTOCOL_CLIENTS: OnceLock<RwLock<ProxyProtocolClients>> = OnceLock::new();
+    PROXY_PROTOCOL_CLIENTS.get_or_init(|| {
+        RwLock::new(ProxyProtocolClients {
+            clients: HashMap::new(),
+            last_purge: Instant::now(),
+        })
+    })
+}
+
+fn proxy_protocol_clock_origin() -> Instant {
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    *ORIGIN.get_or_init(Instant::now)
+}
+
+/// Get the client sending the PROXY protocol `header` at the beginning of each connection.
+///
+/// The header differs from client to client, so a client is shared by the requests with the same header,
+/// i.e. from the same client connection, and released once idle or when a gateway has more than [PROXY_PROTOCOL_MAX_CLIENTS] clients.
+pub(crate) fn get_proxy_protocol_client(
+    gateway_name: &str,
+    http_version: &SgBackendHttpVersion,
+    ignore_validation: bool,
+    tls: Option<&Arc<SgBackendTlsConfig>>,
+    header: Vec<u8>,
+) -> TardisResult<Client<HttpsConnector<SgProxyProtocolConnector>>> {
+    let now = proxy_protocol_clock_origin().elapsed().as_millis() as u64;
+    let key = (http_version.clone(), ignore_validation, tls.cloned(), header);
+    if let Some(proxy_protocol_client) = get_proxy_protocol_clients().read().unwrap_or_else(|e| e.into_inner()).clients.get(gateway_name).and_then(|clients| clients.get(&key)) {
+        proxy_protocol_client.last_used.store(now, Ordering::Relaxed);
+        return Ok(proxy_protocol_client.client.clone());
+    }
+    let client = build_proxy_protocol_client(gateway_name, http_version, ignore_validation, tls, key.3.clone())?;
+    let mut proxy_protocol_clients = get_proxy_protocol_clients().write().unwrap_or_else(|e| e.into_inner());
+    let idle_timeout = PROXY_PROTOCOL_IDLE_TIMEOUT.as_millis() as u64;
+    if proxy_protocol_clients.last_purge.elapsed() >= PROXY_PROTOCOL_IDLE_TIMEOUT {
+        for clients in proxy_protocol_clients.clients.values_mut() {
+            clients.retain(|_, client| now.saturating_sub(client.last_used.load(Ordering::Relaxed)) < idle_timeout);
+        }
+        proxy_protocol_clients.last_purge = Instant::now();
+    }
+    let clients = proxy_protocol_clients.clients.entry(gateway_name.to_string()).or_default();
+    if clients.len() >= PROXY_PROTOCOL_MAX_CLIENTS && !clients.contains_key(&key) {
+        // Drop the least recently used eighth at once, so that the clients are not scanned on every insert
+        let mut last_used = clients.values().map(|client| client.last_used.load(Ordering::Relaxed)).collect::<Vec<_>>();
+        let (_, threshold, _) = last_used.select_nth_unstable(clients.len() / 8);
+        let threshold = *threshold;
+        clients.retain(|_, client| client.last_used.load(Ordering::Relaxed) > threshold);
+    }
+    let proxy_protocol_client = clients.entry(key).or_insert(ProxyProtocolClient {
+        client,
+        last_used: AtomicU64::new(now),
+    });
+    Ok(proxy_protocol_client.client.clone())
+}
+
+fn build_proxy_protocol_client(
+    gateway_name: &str,
+    http_version: &SgBackendHttpVersion,
+    ignore_validation: bool,
+    tls: Option<&Arc<SgBackendTlsConfig>>,
+    header: Vec<u8>,
+) -> TardisResult<Client<HttpsConnector<SgProxyProtocolConnector>>> {
+    let key = (ignore_validation, tls.cloned());
+    let cached_tls_config = get_tls_configs().read().unwrap_or_else(|e| e.into_inner()).get(gateway_name).and_then(|tls_configs| tls_configs.get(&key)).cloned();
+    let tls_config = match cached_tls_config {
+        Some(tls_config) => tls_config,
+        None => {
+            let tls_config = get_tls_config(ignore_validation, tls.map(|tls| tls.as_ref()))?;
+            get_tls_configs().write().unwrap_or_else(|e| e.into_inner()).entry(gateway_name.to_string()).or_default().entry(key).or_insert(tls_config).clone()
         }
+    };
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    let connector = SgProxyProtocolConnector { http, header: Arc::new(header) };
+    Ok(build_client(
+        connector,
+        Client::builder().pool_idle_timeout(PROXY_PROTOCOL_IDLE_TIMEOUT),
+        http_version,
+        tls_config,
+        tls.map(|tls| tls.as_ref()),
+    ))
+}
+
+/// TLS configurations of the clients sending PROXY protocol headers by gateway name, loading the root certificates for each client is expensive.
+fn get_tls_configs() -> &'static RwLock<HashMap<String, HashMap<(bool, Option<Arc<SgBackendTlsConfig>>), rustls::ClientConfig>>> {
+    static TLS_CONFIGS: OnceLock<RwLock<HashMap<String, HashMap<(bool, Option<Arc<SgBackendTlsConfig>>), rustls::ClientConfig>>>> = OnceLock::new();
+    TLS_CONFIGS.get_or_init(Default::default)
+}
+
+/// Connector writing a PROXY protocol header once the TCP connection is established, before the TLS handshake if any.
+#[derive(Clone)]
+pub(crate) struct SgProxyProtocolConnector {
+    http: HttpConnector,
+    header: Arc<Vec<u8>>,
+}
+
+impl Service<Uri> for SgProxyProtocolConnector {
+    type Response = TcpStream;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<TcpStream, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
     }
 
-    let https = hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(get_tls_config(ignore_validation)).https_or_http().enable_http1().build();
-    let tls_client = Client::builder().build(https);
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.http.call(uri);
+        let header = self.header.clone();
+        Box::pin(async move {
+            let mut stream = connecting.await?;
+            stream.write_all(&header).await?;
+            Ok(stream)
+        })
+    }
+}
 
-    Ok(tls_client)
+/// A backend with its own `ca` is always verified against it, even if the gateway ignores TLS verification.
+fn get_tls_config(ignore_validation: bool, tls: Option<&SgBackendTlsConfig>) -> TardisResult<rustls::ClientConfig> {
+    let ignore_validation = ignore_validation && tls.and_then(|tls| tls.ca.as_ref()).is_none();
+    if ignore_validation && tls.is_none() {
+        return Ok(get_rustls_config_dangerous());
+    }
+    let builder = rustls::ClientConfig::builder().with_safe_defaults();
+    let builder = match tls.and_then(|tls| tls.ca.as_ref()) {
+        Some(ca) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in server::parse_certs(ca)? {
+                roots.add(&cert).map_err(|error| TardisError::bad_request(&format!("[SG.Client] Backend tls ca not legal: {error}"), ""))?;
+            }
+            builder.with_root_certificates(roots)
+        }
+        None => builder.with_native_roots(),
+    };
+    let mut config = match tls.map(|tls| (&tls.cert, &tls.key)) {
+        Some((Some(cert), Some(key))) => builder
+            .with_client_auth_cert(server::parse_certs(cert)?, server::parse_private_key(key)?)
+            .map_err(|error| TardisError::bad_request(&format!("[SG.Client] Backend tls client certificate not legal: {error}"), ""))?,
+        Some((Some(_), None)) | Some((None, Some(_))) => {
+            return Err(TardisError::bad_request("[SG.Client] Backend tls client certificate and key must be set together", ""));
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    if ignore_validation {
+        config.dangerous().set_certificate_verifier(Arc::new(NoCertificateVerification {}));
+    }
+    Ok(config)
 }
 
 pub fn get_rustls_config_dangerous() -> rustls::ClientConfig {
@@ -74,8 +312,8 @@ fn default_client() -> &'static Client<HttpsConnector<HttpConnector>> {
     DEFAULT_CLIENT.get().expect("DEFAULT_CLIENT not initialized")
 }
 
-pub async fn request(
-    client: &Client<HttpsConnector<HttpConnector>>,
+pub async fn request<C: Connect + Clone + Send + Sync + 'static>(
+    client: &Client<C>,
     rule_timeout_ms: Option<u64>,
     redirect: bool,
     mut ctx: SgRoutePluginContext,
@@ -93,15 +331,30 @@ pub async fn request(
         };
         let url = format!("{}://{}{}{}", scheme, host, port, ctx.request.get_uri().path_and_query().map(|p| p.as_str()).unwrap_or(""));
         let timeout_ms = if let Some(timeout_ms) = backend.timeout_ms { Some(timeout_ms) } else { rule_timeout_ms };
+        let start = Instant::now();
         ctx = do_request(client, &url, timeout_ms, ctx).await?;
+        ctx.set_upstream_latency(start.elapsed());
         ctx.set_chose_backend(backend);
     }
     Ok(ctx)
 }
 
-async fn do_request(client: &Client<HttpsConnector<HttpConnector>>, url: &str, timeout_ms: Option<u64>, mut ctx: SgRoutePluginContext) -> TardisResult<SgRoutePluginContext> {
-    let ctx = match raw_request(
-        Some(client),
+async fn do_request<C: Connect + Clone + Send + Sync + 'static>(
+    client: &Client<C>,
+    url: &str,
+    timeout_ms: Option<u64>,
+    mut ctx: SgRoutePluginContext,
+) -> TardisResult<SgRoutePluginContext> {
+    let trace = ctx.get_trace().cloned();
+    let span = trace.as_ref().map(|trace| {
+        let mut span = trace.start_span("upstream", SgSpanKind::Client);
+        span.set_attribute("http.method", ctx.request.get_method().as_str());
+        span.set_attribute("http.url", url);
+        trace.inject(&span, ctx.request.get_headers_mut());
+        span
+    });
+    let ctx = match send_request(
+        client,
         ctx.request.get_method().clone(),
         url,
         ctx.request.take_body(),
@@ -113,6 +366,14 @@ async fn do_request(client: &Client<HttpsConnector<HttpConnector>>, url: &str, t
         Ok(response) => ctx.resp(response.status(), response.headers().clone(), response.into_body()),
         Err(e) => ctx.resp_from_error(e),
     };
+    if let (Some(trace), Some(mut span)) = (trace, span) {
+        let status_code = ctx.response.get_status_code();
+        span.set_attribute("http.status_code", status_code.as_str());
+        if ctx.is_resp_error() || status_code.is_server_error() {
+            span.set_error();
+        }
+        trace.end_span(span);
+    }
     Ok(ctx)
 }
 
@@ -123,6 +384,21 @@ pub async fn raw_request(
     body: Body,
     headers: &HeaderMap<HeaderValue>,
     timeout_ms: Option<u64>,
+) -> TardisResult<Response<Body>> {
+    match client {
+        Some(client) => send_request(client, method, url, body, headers, timeout_ms).await,
+        None => send_request(init()?, method, url, body, headers, timeout_ms).await,
+    }
+}
+
+/// Same as [raw_request], for clients with other connectors.
+pub(crate) async fn send_request<C: Connect + Clone + Send + Sync + 'static>(
+    client: &Client<C>,
+    method: Method,
+    url: &str,
+    body: Body,
+    headers: &HeaderMap<HeaderValue>,
+    timeout_ms: Option<u64>,
 ) -> TardisResult<Response<Body>> {
     let timeout_ms = timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
     let method_str = method.to_string();
@@ -144,8 +420,7 @@ pub async fn raw_request(
     }
     req = req.uri(url);
     let req = req.body(body).map_err(|error| TardisError::internal_error(&format!("[SG.Route] Build request method {method_str} url {url_str} error:{error}"), ""))?;
-    let req = if let Some(client) = client { client.request(req) } else { init()?.request(req) };
-    let response = match timeout(Duration::from_millis(timeout_ms), req).await {
+    let response = match timeout(Duration::from_millis(timeout_ms), client.request(req)).await {
         Ok(response) => response.map_err(|error: Error| TardisError::custom("502", &format!("[SG.Client] Request method {method_str} url {url_str} error: {error}"), "")),
         Err(_) => {
             Response::builder().status(StatusCode::GATEWAY_TIMEOUT).body(Body::empty()).map_err(|e| TardisError::internal_error(&format!("[SG.Client] timeout error: {e}"), ""))
@@ -156,14 +431,17 @@ pub async fn raw_request(
 
 #[cfg(test)]
 mod tests {
-    use http::{HeaderMap, Method, Uri, Version};
-    use hyper::Body;
-    use tardis::{basic::result::TardisResult, tokio};
+    use http::{HeaderMap, Method, Request, Response, Uri, Version};
+    use hyper::{server::conn::Http, service::service_fn, Body};
+    use tardis::{
+        basic::result::TardisResult,
+        tokio::{self, net::TcpListener},
+    };
 
     use crate::plugins::context::AvailableBackendInst;
     use crate::{
-        config::gateway_dto::SgProtocol,
-        functions::http_client::{init, request},
+        config::{gateway_dto::SgProtocol, http_route_dto::SgBackendHttpVersion},
+        functions::http_client::{clear_clients, get_client, get_proxy_protocol_client, get_proxy_protocol_clients, init, request, PROXY_PROTOCOL_MAX_CLIENTS},
         plugins::context::SgRoutePluginContext,
     };
     use hyper::{client::HttpConnector, Client};
@@ -331,6 +609,48 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_h2c_request() -> TardisResult<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(Http::new().http2_only(true).serve_connection(
+                    stream,
+                    service_fn(|req: Request<Body>| async move { Ok::<_, hyper::Error>(Response::new(Body::from(format!("{:?}", req.version())))) }),
+                ));
+            }
+        });
+        let new_ctx = |http_version| {
+            SgRoutePluginContext::new_http(
+                Method::GET,
+                Uri::from_static("http://sg.idealworld.group/get"),
+                Version::HTTP_11,
+                HeaderMap::new(),
+                Body::empty(),
+                "127.0.0.1:8080".parse().unwrap(),
+                "".to_string(),
+                None,
+                Some(AvailableBackendInst {
+                    name_or_host: "127.0.0.1".to_string(),
+                    port,
+                    http_version: Some(http_version),
+                    ..Default::default()
+                }),
+            )
+        };
+
+        let mut resp = request(&get_client("", &SgBackendHttpVersion::H2c, false, None)?, None, false, new_ctx(SgBackendHttpVersion::H2c)).await?;
+        assert_eq!(resp.response.get_status_code().as_u16(), 200);
+        assert_eq!(resp.response.dump_body().await?, "HTTP/2.0");
+
+        // Without TLS there is nothing to negotiate, so ALPN falls back to HTTP/1.1
+        let resp = request(&get_client("", &SgBackendHttpVersion::Alpn, false, None)?, None, false, new_ctx(SgBackendHttpVersion::Alpn)).await?;
+        assert_eq!(resp.response.get_status_code().as_u16(), 502);
+        Ok(())
+    }
+
     // Because this unit test depends on the external url,
     // it may be due to the failure of the external url, so add retry
     async fn retry_test_request(
@@ -348,4 +668,18 @@ mod tests {
         }
         Ok(result)
     }
+
+    #[test]
+    fn test_proxy_protocol_clients_bounded() {
+        for i in 0..=PROXY_PROTOCOL_MAX_CLIENTS {
+            get_proxy_protocol_client("test_bounded_gw", &SgBackendHttpVersion::Http1, false, None, i.to_le_bytes().to_vec()).unwrap();
+        }
+        let clients = get_proxy_protocol_clients().read().unwrap().clients["test_bounded_gw"].len();
+        assert!(clients > 0 && clients <= PROXY_PROTOCOL_MAX_CLIENTS);
+        // The last one is kept
+        let key = (SgBackendHttpVersion::Http1, false, None, PROXY_PROTOCOL_MAX_CLIENTS.to_le_bytes().to_vec());
+        assert!(get_proxy_protocol_clients().read().unwrap().clients["test_bounded_gw"].contains_key(&key));
+        clear_clients("test_bounded_gw");
+        assert!(!get_proxy_protocol_clients().read().unwrap().clients.contains_key("test_bounded_gw"));
+    }
 }
@@ -1,21 +1,21 @@
-use std::{collections::HashMap, net::SocketAddr};
+use std::{collections::HashMap, net::SocketAddr, time::Instant};
 
-use crate::instance::{SgBackendInst, SgGatewayInst, SgHttpHeaderMatchInst, SgHttpQueryMatchInst};
+use crate::instance::{SgBackendInst, SgGatewayInst, SgGrpcMethodMatchInst, SgHttpHeaderMatchInst, SgHttpQueryMatchInst};
 use crate::{
     config::{
-        gateway_dto::{SgGateway, SgListener},
-        http_route_dto::{SgHttpHeaderMatchType, SgHttpPathMatchType, SgHttpQueryMatchType, SgHttpRoute},
+        gateway_dto::{SgGateway, SgListener, SgProxyProtocolVersion},
+        http_route_dto::{SgBackendHttpVersion, SgGrpcMethodMatchType, SgHealthPanicMode, SgHttpHeaderMatchType, SgHttpPathMatchType, SgHttpQueryMatchType, SgHttpRoute},
     },
     instance::{SgHttpPathMatchInst, SgHttpRouteInst, SgHttpRouteMatchInst, SgHttpRouteRuleInst},
     plugins::{
-        context::{ChosenHttpRouteRuleInst, SgRouteFilterRequestAction, SgRoutePluginContext},
-        filters::{self, BoxSgPluginFilter, SgPluginFilterInitDto},
+        context::{ChosenHttpRouteRuleInst, SgClientCertInfo, SgRouteFilterRequestAction, SgRoutePluginContext},
+        filters::{self, BoxSgPluginFilter, SgPluginFilterInitDto, SgPluginFilterKind},
     },
 };
 use http::{header::UPGRADE, HeaderValue, Request, Response};
-use hyper::{Body, StatusCode};
+use hyper::{body::HttpBody, Body, StatusCode};
 
-use crate::plugins::context::AvailableBackendInst;
+use crate::plugins::context::{AvailableBackendInst, SgGuardedBody};
 use itertools::Itertools;
 use std::sync::{Arc, OnceLock};
 use std::vec::Vec;
@@ -24,11 +24,19 @@ use tardis::{
     basic::{error::TardisError, result::TardisResult},
     futures_util::future::join_all,
     log,
-    rand::{distributions::WeightedIndex, prelude::Distribution, thread_rng},
     regex::Regex,
 };
 
+use super::access_log::{self, SgAccessLog, SgAccessLogger};
+use super::grpc;
+use super::health_check;
 use super::http_client;
+use super::load_balancer::SgLoadBalancer;
+use super::metrics::SgRequestMetrics;
+use super::outlier_detection;
+use super::proxy_protocol;
+use super::server;
+use super::trace::{self, SgSpanKind, SgTrace};
 
 fn get_routes() -> &'static RwLock<HashMap<String, Arc<SgGatewayInst>>> {
     static ROUTES: OnceLock<RwLock<HashMap<String, Arc<SgGatewayInst>>>> = OnceLock::new();
@@ -42,8 +50,16 @@ pub async fn init(gateway_conf: SgGateway, routes: Vec<SgHttpRoute>) -> TardisRe
     } else {
         Vec::new()
     };
+    let ignore_tls_verification = gateway_conf.parameters.ignore_tls_verification.unwrap_or(false);
+    // The backends and their TLS profiles may have changed
+    http_client::clear_clients(&gateway_conf.name);
+    let client = if ignore_tls_verification {
+        http_client::get_ignore_validation_clint()?
+    } else {
+        http_client::init()?.clone()
+    };
     let mut route_insts = Vec::new();
-    for route in routes.clone() {
+    for (route_index, route) in routes.clone().into_iter().enumerate() {
         let route_filters = if let Some(filters) = route.clone().filters {
             filters::init(filters, SgPluginFilterInitDto::from_route(&gateway_conf, &route)).await?
         } else {
@@ -51,7 +67,7 @@ pub async fn init(gateway_conf: SgGateway, routes: Vec<SgHttpRoute>) -> TardisRe
         };
         let rule_insts = if let Some(rules) = route.rules {
             let mut rule_insts = Vec::new();
-            for rule in rules {
+            for (rule_index, rule) in rules.into_iter().enumerate() {
                 let rule_filters = if let Some(filters) = rule.filters.clone() {
                     filters::init(filters, SgPluginFilterInitDto::from_rule(&gateway_conf, &rule)).await?
                 } else {
@@ -133,51 +149,113 @@ pub async fn init(gateway_conf: SgGateway, routes: Vec<SgHttpRoute>) -> TardisRe
                                     })
                                     .transpose()?;
 
+                                let grpc_inst = rule_match
+                                    .grpc
+                                    .map(|grpc| {
+                                        let (service_regular, method_regular) = if grpc.kind == SgGrpcMethodMatchType::Regular {
+                                            let to_regular = |value: &Option<String>| {
+                                                value
+                                                    .as_ref()
+                                                    .map(|value| {
+                                                        Regex::new(value).map_err(|_| TardisError::format_error(&format!("[SG.Route] Grpc Regular {} format error", value), ""))
+                                                    })
+                                                    .transpose()
+                                            };
+                                            (to_regular(&grpc.service)?, to_regular(&grpc.method)?)
+                                        } else {
+                                            (None, None)
+                                        };
+                                        Ok::<_, TardisError>(SgGrpcMethodMatchInst {
+                                            kind: grpc.kind,
+                                            service: grpc.service,
+                                            method: grpc.method,
+                                            service_regular,
+                                            method_regular,
+                                        })
+                                    })
+                                    .transpose()?;
+
                                 Ok(SgHttpRouteMatchInst {
                                     path: path_inst,
                                     header: header_inst,
                                     query: query_inst,
                                     method: rule_match.method.map(|m| m.into_iter().filter_map(|m| m.parse().ok()).collect_vec()),
+                                    grpc: grpc_inst,
                                 })
                             })
                             .collect::<TardisResult<Vec<SgHttpRouteMatchInst>>>()
                     })
                     .transpose()?;
                 let gateway_conf_clone = Arc::new(gateway_conf.clone());
+                let backend_insts = if let Some(backend_refs) = rule.clone().backends {
+                    let backends = join_all(
+                        backend_refs
+                            .into_iter()
+                            .map(|backend_ref| (backend_ref, &rule))
+                            .map(move |(backend_ref, read_only_route)| {
+                                let gateway_conf_clone = gateway_conf_clone.clone();
+                                async move {
+                                    let filters = if let Some(filters) = backend_ref.clone().filters {
+                                        filters::init(filters, SgPluginFilterInitDto::from_backend(&gateway_conf_clone, read_only_route, &backend_ref)).await?
+                                    } else {
+                                        Vec::new()
+                                    };
+                                    Ok::<_, TardisError>(SgBackendInst {
+                                        name_or_host: backend_ref.name_or_host,
+                                        namespace: backend_ref.namespace,
+                                        port: backend_ref.port,
+                                        timeout_ms: backend_ref.timeout_ms,
+                                        protocol: backend_ref.protocol,
+                                        http_version: backend_ref.http_version,
+                                        tls: backend_ref.tls.map(Arc::new),
+                                        proxy_protocol: backend_ref.proxy_protocol,
+                                        weight: backend_ref.weight,
+                                        filters,
+                                        ..Default::default()
+                                    })
+                                }
+                            })
+                            .collect_vec(),
+                    )
+                    .await;
+                    let backends = backends.into_iter().collect::<Result<Vec<_>, _>>()?;
+                    for (backend, backend_ref) in backends.iter().zip(rule.backends.iter().flatten()) {
+                        if let Some(health_check) = backend_ref.health_check.clone() {
+                            let http_version = backend.http_version.clone().unwrap_or_default();
+                            match (&backend.http_version, &backend.tls, backend.proxy_protocol) {
+                                (None, None, None) => health_check::start(backend, health_check, client.clone()),
+                                (_, tls, None) => health_check::start(
+                                    backend,
+                                    health_check,
+                                    http_client::get_client(&gateway_conf.name, &http_version, ignore_tls_verification, tls.as_ref())?,
+                                ),
+                                // Probes are sent on behalf of the gateway
+                                (_, tls, Some(proxy_protocol)) => health_check::start(
+                                    backend,
+                                    health_check,
+                                    http_client::get_proxy_protocol_client(
+                                        &gateway_conf.name,
+                                        &http_version,
+                                        ignore_tls_verification,
+                                        tls.as_ref(),
+                                        proxy_protocol::encode(proxy_protocol, None),
+                                    )?,
+                                ),
+                            }
+                        }
+                    }
+                    Some(backends)
+                } else {
+                    None
+                };
                 rule_insts.push(SgHttpRouteRuleInst {
+                    name: rule.name.clone().unwrap_or_else(|| rule_index.to_string()),
                     filters: rule_filters,
                     matches: rule_matches_insts,
-                    backends: if let Some(backend_refs) = rule.clone().backends {
-                        let backends = join_all(
-                            backend_refs
-                                .into_iter()
-                                .map(|backend_ref| (backend_ref, &rule))
-                                .map(move |(backend_ref, read_only_route)| {
-                                    let gateway_conf_clone = gateway_conf_clone.clone();
-                                    async move {
-                                        let filters = if let Some(filters) = backend_ref.clone().filters {
-                                            filters::init(filters, SgPluginFilterInitDto::from_backend(&gateway_conf_clone, read_only_route, &backend_ref)).await?
-                                        } else {
-                                            Vec::new()
-                                        };
-                                        Ok::<_, TardisError>(SgBackendInst {
-                                            name_or_host: backend_ref.name_or_host,
-                                            namespace: backend_ref.namespace,
-                                            port: backend_ref.port,
-                                            timeout_ms: backend_ref.timeout_ms,
-                                            protocol: backend_ref.protocol,
-                                            weight: backend_ref.weight,
-                                            filters,
-                                        })
-                                    }
-                                })
-                                .collect_vec(),
-                        )
-                        .await;
-                        Some(backends.into_iter().collect::<Result<Vec<_>, _>>()?)
-                    } else {
-                        None
-                    },
+                    load_balancer: SgLoadBalancer::new(rule.lb_policy.clone().unwrap_or_default(), backend_insts.as_deref().unwrap_or_default()),
+                    panic_mode: rule.panic_mode.clone().unwrap_or_default(),
+                    outlier_detection: rule.outlier_detection.clone(),
+                    backends: backend_insts,
                     timeout_ms: rule.timeout_ms,
                 })
             }
@@ -186,6 +264,7 @@ pub async fn init(gateway_conf: SgGateway, routes: Vec<SgHttpRoute>) -> TardisRe
             Ok(None)
         }?;
         route_insts.push(SgHttpRouteInst {
+            name: route.name.unwrap_or_else(|| route_index.to_string()),
             hostnames: route.hostnames.map(|hostnames| hostnames.into_iter().map(|hostname| hostname.to_lowercase()).collect_vec()),
             filters: route_filters,
             rules: rule_insts,
@@ -201,11 +280,11 @@ pub async fn init(gateway_conf: SgGateway, routes: Vec<SgHttpRoute>) -> TardisRe
     let route_inst = SgGatewayInst {
         filters: global_filters,
         routes: route_insts,
-        client: if gateway_conf.parameters.ignore_tls_verification.unwrap_or(false) {
-            http_client::get_ignore_validation_clint()?
-        } else {
-            http_client::init()?.clone()
-        },
+        client,
+        ignore_tls_verification,
+        metrics: gateway_conf.parameters.metrics.is_some(),
+        tracer: trace::get(&gateway_conf.name),
+        access_log: gateway_conf.parameters.access_log.as_ref().map(SgAccessLogger::new).transpose()?.map(Arc::new),
         listeners: gateway_conf.listeners,
     };
     {
@@ -220,6 +299,7 @@ pub async fn remove(name: &str) -> TardisResult<()> {
         let mut routes_write = get_routes().write().await;
         routes_write.remove(name)
     };
+    http_client::clear_clients(name);
     if let Some(gateway_inst) = route {
         for (_, filter) in &gateway_inst.filters {
             filter.destroy().await?;
@@ -252,7 +332,51 @@ async fn get(name: &str) -> TardisResult<Arc<SgGatewayInst>> {
     }
 }
 
-pub async fn process(gateway_name: Arc<String>, req_scheme: &str, (remote_addr, local_addr): (SocketAddr, SocketAddr), mut request: Request<Body>) -> TardisResult<Response<Body>> {
+pub async fn process(gateway_name: Arc<String>, req_scheme: &str, addrs: (SocketAddr, SocketAddr), request: Request<Body>) -> TardisResult<Response<SgGuardedBody>> {
+    let mut request_metrics = SgRequestMetrics::new(&gateway_name);
+    let mut access_log = SgAccessLog::new(&gateway_name, addrs.0, &request);
+    let mut trace = None;
+    let mut request_kind = SgPluginFilterKind::Http;
+    let response = do_process(
+        gateway_name,
+        req_scheme,
+        addrs,
+        request,
+        &mut request_metrics,
+        &mut access_log,
+        &mut trace,
+        &mut request_kind,
+    )
+    .await;
+    let (status, response_size) = match &response {
+        Ok(response) => (response.status(), access_log::body_size(response.headers(), response.body())),
+        Err(error) => (server::error_status_code(error), None),
+    };
+    if let Some(trace) = trace {
+        trace.finish(status.as_u16());
+    }
+    request_metrics.finish(status);
+    access_log.finish(status, response_size);
+    // Errors are only converted for requests routed to gRPC, the content type alone is chosen by the client
+    if request_kind != SgPluginFilterKind::Grpc {
+        return response;
+    }
+    Ok(match response {
+        Ok(response) => grpc::into_grpc_response(response),
+        Err(error) => grpc::error_response(server::error_status_code(&error), &error.message).map(SgGuardedBody::from),
+    })
+}
+
+async fn do_process(
+    gateway_name: Arc<String>,
+    req_scheme: &str,
+    (remote_addr, local_addr): (SocketAddr, SocketAddr),
+    mut request: Request<Body>,
+    request_metrics: &mut SgRequestMetrics,
+    access_log: &mut SgAccessLog,
+    trace: &mut Option<SgTrace>,
+    request_kind: &mut SgPluginFilterKind,
+) -> TardisResult<Response<SgGuardedBody>> {
     if request.uri().host().is_none() && request.headers().contains_key("Host") {
         *request.uri_mut() = format!(
             "{}://{}{}",
@@ -294,8 +418,24 @@ pub async fn process(gateway_name: Arc<String>, req_scheme: &str, (remote_addr,
     }
 
     process_request_headers(&mut request, remote_addr)?;
+    // Until a route is matched the gateway answers by itself, so gRPC clients get gRPC errors
+    if grpc::is_grpc_request(request.headers()) {
+        *request_kind = SgPluginFilterKind::Grpc;
+    }
 
     let gateway_inst = get(&gateway_name).await?;
+    request_metrics.set_enabled(gateway_inst.metrics);
+    access_log.set_logger(gateway_inst.access_log.clone());
+    access_log.set_listener(
+        gateway_inst
+            .listeners
+            .iter()
+            .find(|listener| listener.port == local_addr.port())
+            .and_then(|listener| listener.name.clone())
+            .unwrap_or_else(|| local_addr.to_string())
+            .as_str(),
+    );
+    *trace = gateway_inst.tracer.as_ref().map(|tracer| tracer.start(request.method(), &request.uri().to_string(), request.headers(), &remote_addr.ip().to_string()));
     if !match_listeners_hostname_and_port(request.uri().host(), local_addr.port(), &gateway_inst.listeners) {
         log::trace!("[SG.Route] Request hostname {} not match", request.uri().host().expect(""));
         let mut not_found = Response::default();
@@ -303,7 +443,17 @@ pub async fn process(gateway_name: Arc<String>, req_scheme: &str, (remote_addr,
         return Ok(not_found);
     }
 
+    let route_match_span = trace.as_ref().map(|trace| trace.start_span("route_match", SgSpanKind::Internal));
     let (matched_route_inst, matched_rule_inst, matched_match_inst) = match_route_process(&request, &gateway_inst.routes);
+    if let (Some(trace), Some(mut span)) = (trace.as_ref(), route_match_span) {
+        if let Some(matched_route_inst) = matched_route_inst {
+            span.set_attribute("sg.route", &matched_route_inst.name);
+        }
+        if let Some(matched_rule_inst) = matched_rule_inst {
+            span.set_attribute("sg.rule", &matched_rule_inst.name);
+        }
+        trace.end_span(span);
+    }
 
     log::trace!(
         "[SG.Route] {}",
@@ -321,12 +471,27 @@ pub async fn process(gateway_name: Arc<String>, req_scheme: &str, (remote_addr,
     };
 
     let matched_route_inst = matched_route_inst.expect("Unreachable code");
-
-    let backend = if let Some(Some(backends)) = matched_rule_inst.map(|rule| &rule.backends) {
-        choose_backend(backends)
-    } else {
-        None
-    };
+    request_metrics.set_route(&matched_route_inst.name, matched_rule_inst.map(|rule| rule.name.as_str()));
+    access_log.set_route(&matched_route_inst.name, matched_rule_inst.map(|rule| rule.name.as_str()));
+
+    let backend = matched_rule_inst.and_then(|rule| choose_backend(rule, &request, remote_addr));
+    // Same decision as the request kind of the context, which is only built once the request filters are reached
+    let grpc_expected = matched_match_inst.is_some_and(|matched_match| matched_match.grpc.is_some())
+        || backend.is_some_and(|backend| matches!(backend.http_version, Some(SgBackendHttpVersion::H2c | SgBackendHttpVersion::Alpn)));
+    if !grpc_expected {
+        *request_kind = SgPluginFilterKind::Http;
+    }
+    if backend.is_none() && matched_rule_inst.is_some_and(|rule| rule.backends.as_ref().is_some_and(|backends| !backends.is_empty())) {
+        log::warn!("[SG.Route] All backends are unavailable, reject the request, from {remote_addr} @ {gateway_name}");
+        let mut unavailable = Response::default();
+        *unavailable.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        return Ok(unavailable);
+    }
+    let in_flight_guard = backend.map(|backend| backend.begin_request());
+    if let Some(backend) = backend {
+        request_metrics.set_backend(&backend.name_or_host);
+        access_log.set_backend(&backend.name_or_host);
+    }
 
     let backend_filters = backend.map(|backend| backend.filters.as_slice());
     let rule_filters = matched_rule_inst.map(|rule| rule.filters.as_slice());
@@ -349,7 +514,7 @@ pub async fn process(gateway_name: Arc<String>, req_scheme: &str, (remote_addr,
                 )
                 .await?;
                 *request.uri_mut() = ctx.request.get_uri().clone();
-                return crate::functions::websocket::process(gateway_name, remote_addr, backend, request).await;
+                return crate::functions::websocket::process(gateway_name, remote_addr, backend, request).await.map(|response| response.map(SgGuardedBody::from));
             } else {
                 return Err(TardisError::bad_request(
                     &format!("[SG.Websocket] No backend found , from {remote_addr} @ {gateway_name}"),
@@ -366,7 +531,7 @@ pub async fn process(gateway_name: Arc<String>, req_scheme: &str, (remote_addr,
         }
     }
 
-    let ctx = process_req_filters_http(
+    let mut ctx = process_req_filters_http(
         gateway_name.to_string(),
         remote_addr,
         request,
@@ -377,8 +542,14 @@ pub async fn process(gateway_name: Arc<String>, req_scheme: &str, (remote_addr,
         matched_rule_inst,
         matched_match_inst,
         backend,
+        trace.clone(),
     )
     .await?;
+    // The response body is streamed after returning, the request is in flight until it is sent
+    if let Some(in_flight_guard) = in_flight_guard {
+        ctx.add_guard(in_flight_guard);
+    }
+    access_log.set_request_id(ctx.get_request_id());
 
     let mut ctx = if ctx.get_action() == &SgRouteFilterRequestAction::Response {
         ctx
@@ -394,7 +565,37 @@ pub async fn process(gateway_name: Arc<String>, req_scheme: &str, (remote_addr,
             None => log::info!("[SG.Request] matched no backend"),
         }
 
-        http_client::request(&gateway_inst.client, rule_timeout, ctx.get_action() == &SgRouteFilterRequestAction::Redirect, ctx).await?
+        let (http_version, backend_tls, proxy_protocol) = ctx.get_chose_backend().map(|backend| (backend.http_version, backend.tls, backend.proxy_protocol)).unwrap_or_default();
+        // gRPC requires HTTP/2 end-to-end
+        let http_version = if ctx.get_request_kind() == &SgPluginFilterKind::Grpc && !matches!(http_version, Some(SgBackendHttpVersion::H2c | SgBackendHttpVersion::Alpn)) {
+            Some(SgBackendHttpVersion::H2c)
+        } else {
+            http_version
+        };
+        let redirect = ctx.get_action() == &SgRouteFilterRequestAction::Redirect;
+        let upstream_start = Instant::now();
+        let ctx = match (http_version, backend_tls, proxy_protocol) {
+            (None, None, None) => http_client::request(&gateway_inst.client, rule_timeout, redirect, ctx).await?,
+            (http_version, backend_tls, None) => {
+                let client = http_client::get_client(&gateway_name, &http_version.unwrap_or_default(), gateway_inst.ignore_tls_verification, backend_tls.as_ref())?;
+                http_client::request(&client, rule_timeout, redirect, ctx).await?
+            }
+            (http_version, backend_tls, Some(proxy_protocol)) => {
+                let header = proxy_protocol::encode(proxy_protocol, Some((ctx.get_remote_addr(), local_addr)));
+                let client = http_client::get_proxy_protocol_client(
+                    &gateway_name,
+                    &http_version.unwrap_or_default(),
+                    gateway_inst.ignore_tls_verification,
+                    backend_tls.as_ref(),
+                    header,
+                )?;
+                http_client::request(&client, rule_timeout, redirect, ctx).await?
+            }
+        };
+        access_log.set_upstream_latency(upstream_start.elapsed());
+        record_outlier(matched_rule_inst, backend, &ctx);
+        request_metrics.record_upstream(&ctx);
+        ctx
     };
 
     if log::level_enabled!(log::Level::TRACE) {
@@ -422,19 +623,18 @@ pub async fn process(gateway_name: Arc<String>, req_scheme: &str, (remote_addr,
     process_response_headers(ctx).await?.build_response().await
 }
 
-fn process_request_headers(request: &mut Request<Body>, remote_addr: SocketAddr) -> TardisResult<()> {
+/// Appends the socket peer to `X-Forwarded-For`, multiple `X-Forwarded-For` headers are merged into one.
+pub(crate) fn process_request_headers(request: &mut Request<Body>, remote_addr: SocketAddr) -> TardisResult<()> {
     const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+    let mut forwarded_for = request
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .map(|forwarded| forwarded.to_str().map_err(|e| TardisError::bad_gateway(&format!("[SG.ProcessRequestHeaders] X-Forwarded-For header value parse err {e}"), "")))
+        .collect::<TardisResult<Vec<_>>>()?;
     let real_ip = remote_addr.ip().to_string();
-    let forwarded_for = match request.headers().get(X_FORWARDED_FOR) {
-        Some(forwarded) => {
-            format!(
-                "{},{}",
-                forwarded.to_str().map_err(|e| TardisError::bad_gateway(&format!("[SG.ProcessRequestHeaders] X-Forwarded-For header value parse err {e}"), ""))?,
-                real_ip
-            )
-        }
-        None => real_ip,
-    };
+    forwarded_for.push(&real_ip);
+    let forwarded_for = forwarded_for.join(",");
     request.headers_mut().insert(
         X_FORWARDED_FOR,
         HeaderValue::from_str(&forwarded_for).map_err(|e| TardisError::bad_gateway(&format!("[SG.ProcessRequestHeaders] X-Forwarded-For header value parse err {e}"), ""))?,
@@ -442,18 +642,24 @@ fn process_request_headers(request: &mut Request<Body>, remote_addr: SocketAddr)
     Ok(())
 }
 
+/// The response body is streamed to the client, `Content-Length` is only recalculated when the length of the body is known,
+/// e.g. a filter buffered or replaced the body, otherwise the header of the upstream is kept.
 async fn process_response_headers(mut ctx: SgRoutePluginContext) -> TardisResult<SgRoutePluginContext> {
-    let is_chunked = if let Some(encoding) = ctx.response.get_headers().get(hyper::header::TRANSFER_ENCODING) {
-        encoding.to_str().map_err(|e| TardisError::bad_gateway(&format!("[SG.ProcessResponseHeaders] Transfer-Encoding header value parse err {e}"), ""))?.contains("chunked")
-    } else {
-        false
-    };
-    if !is_chunked {
-        let response_body: Vec<u8> = ctx.response.take_body_into_bytes().await?.into();
-        ctx.response.set_header(http::header::CONTENT_LENGTH, response_body.len().to_string().as_str())?;
-        ctx.response.set_body(response_body);
+    // gRPC responses are streamed and end with trailers, buffering the body would drop them
+    if ctx.get_request_kind() == &SgPluginFilterKind::Grpc {
+        return Ok(ctx);
     }
-
+    // The body of these responses is always empty, while `Content-Length` describes the body of the corresponding GET request
+    let status_code = ctx.response.get_status_code();
+    if ctx.request.get_method() == http::Method::HEAD || status_code.is_informational() || status_code == &StatusCode::NO_CONTENT || status_code == &StatusCode::NOT_MODIFIED {
+        return Ok(ctx);
+    }
+    let body = ctx.response.take_body();
+    if let Some(content_length) = body.size_hint().exact() {
+        ctx.response.set_header(http::header::CONTENT_LENGTH, content_length.to_string().as_str())?;
+        ctx.response.get_headers_mut().remove(http::header::TRANSFER_ENCODING);
+    }
+    ctx.response.set_body(body);
     Ok(ctx)
 }
 
@@ -594,6 +800,11 @@ fn match_rule_inst<'a>(req: &Request<Body>, rule_matches: Option<&'a Vec<SgHttpR
                     continue;
                 }
             }
+            if let Some(grpc) = &rule_match.grpc {
+                if !match_grpc_method(req, grpc) {
+                    continue;
+                }
+            }
             if let Some(path) = &rule_match.path {
                 let req_path = req.uri().path();
                 match path.kind {
@@ -674,6 +885,20 @@ fn match_rule_inst<'a>(req: &Request<Body>, rule_matches: Option<&'a Vec<SgHttpR
     }
 }
 
+fn match_grpc_method(req: &Request<Body>, grpc: &SgGrpcMethodMatchInst) -> bool {
+    if !grpc::is_grpc_request(req.headers()) {
+        return false;
+    }
+    let Some((req_service, req_method)) = grpc::parse_method_path(req.uri().path()) else {
+        return false;
+    };
+    let matched = |value: &Option<String>, regular: &Option<Regex>, req_value: &str| match grpc.kind {
+        SgGrpcMethodMatchType::Exact => value.iter().all(|value| value == req_value),
+        SgGrpcMethodMatchType::Regular => regular.iter().all(|regular| regular.is_match(req_value)),
+    };
+    matched(&grpc.service, &grpc.service_regular, req_service) && matched(&grpc.method, &grpc.method_regular, req_method)
+}
+
 fn match_listeners_hostname_and_port(hostname: Option<&str>, port: u16, listeners: &[SgListener]) -> bool {
     if let Some(hostname) = hostname {
         listeners.iter().any(|listener| {
@@ -705,8 +930,10 @@ async fn process_req_filters_http(
     matched_rule_inst: Option<&SgHttpRouteRuleInst>,
     matched_match_inst: Option<&SgHttpRouteMatchInst>,
     matched_backend_inst: Option<&SgBackendInst>,
+    trace: Option<SgTrace>,
 ) -> TardisResult<SgRoutePluginContext> {
-    let ctx = SgRoutePluginContext::new_http(
+    let client_cert = request.extensions().get::<SgClientCertInfo>().cloned();
+    let mut ctx = SgRoutePluginContext::new_http(
         request.method().clone(),
         request.uri().clone(),
         request.version(),
@@ -717,6 +944,12 @@ async fn process_req_filters_http(
         matched_rule_inst.map(|m| ChosenHttpRouteRuleInst::cloned_from(m, matched_match_inst)),
         matched_backend_inst.map(|b| AvailableBackendInst::cloned_from(b)),
     );
+    if let Some(trace) = trace {
+        ctx.set_trace(trace);
+    }
+    if let Some(client_cert) = client_cert {
+        ctx.set_client_cert(client_cert);
+    }
     process_req_filters(ctx, backend_filters, rule_filters, route_filters, global_filters).await
 }
 
@@ -732,7 +965,7 @@ async fn process_req_filters_ws(
     matched_rule_inst: Option<&SgHttpRouteRuleInst>,
     matched_match_inst: Option<&SgHttpRouteMatchInst>,
 ) -> TardisResult<SgRoutePluginContext> {
-    let ctx = SgRoutePluginContext::new_ws(
+    let mut ctx = SgRoutePluginContext::new_ws(
         request.method().clone(),
         request.uri().clone(),
         request.version(),
@@ -741,6 +974,9 @@ async fn process_req_filters_ws(
         gateway_name,
         matched_rule_inst.map(|m| ChosenHttpRouteRuleInst::cloned_from(m, matched_match_inst)),
     );
+    if let Some(client_cert) = request.extensions().get::<SgClientCertInfo>() {
+        ctx.set_client_cert(client_cert.clone());
+    }
     process_req_filters(ctx, backend_filters, rule_filters, route_filters, global_filters).await
 }
 
@@ -757,7 +993,7 @@ async fn process_req_filters(
         for (id, filter) in backend_filters {
             if !executed_filters.contains(&id) && filter.before_resp_filter_check(&ctx) {
                 log::trace!("[SG.Plugin.Filter] Hit id {id} in request");
-                (is_continue, ctx) = filter.req_filter(id, ctx).await?;
+                (is_continue, ctx) = call_req_filter(id, filter, ctx).await?;
                 if !is_continue {
                     return Ok(ctx);
                 }
@@ -769,7 +1005,7 @@ async fn process_req_filters(
         for (id, filter) in rule_filters {
             if !executed_filters.contains(&id) && filter.before_resp_filter_check(&ctx) {
                 log::trace!("[SG.Plugin.Filter] Hit id {id} in request");
-                (is_continue, ctx) = filter.req_filter(id, ctx).await?;
+                (is_continue, ctx) = call_req_filter(id, filter, ctx).await?;
                 if !is_continue {
                     return Ok(ctx);
                 }
@@ -780,7 +1016,7 @@ async fn process_req_filters(
     for (id, filter) in route_filters {
         if !executed_filters.contains(&id) && filter.before_resp_filter_check(&ctx) {
             log::trace!("[SG.Plugin.Filter] Hit id {id} in request");
-            (is_continue, ctx) = filter.req_filter(id, ctx).await?;
+            (is_continue, ctx) = call_req_filter(id, filter, ctx).await?;
             if !is_continue {
                 return Ok(ctx);
             }
@@ -790,7 +1026,7 @@ async fn process_req_filters(
     for (id, filter) in global_filters {
         if !executed_filters.contains(&id) && filter.before_resp_filter_check(&ctx) {
             log::trace!("[SG.Plugin.Filter] Hit id {id} in request");
-            (is_continue, ctx) = filter.req_filter(id, ctx).await?;
+            (is_continue, ctx) = call_req_filter(id, filter, ctx).await?;
             if !is_continue {
                 return Ok(ctx);
             }
@@ -814,7 +1050,7 @@ async fn process_resp_filters(
         for (id, filter) in backend_filters {
             if !executed_filters.contains(&id) && filter.before_resp_filter_check(&ctx) {
                 log::trace!("[SG.Plugin.Filter] Hit id {id} in response");
-                (is_continue, ctx) = filter.resp_filter(id, ctx).await?;
+                (is_continue, ctx) = call_resp_filter(id, filter, ctx).await?;
                 if !is_continue {
                     return Ok(ctx);
                 }
@@ -826,7 +1062,7 @@ async fn process_resp_filters(
         for (id, filter) in rule_filters {
             if !executed_filters.contains(&id) && filter.before_resp_filter_check(&ctx) {
                 log::trace!("[SG.Plugin.Filter] Hit id {id} in response");
-                (is_continue, ctx) = filter.resp_filter(id, ctx).await?;
+                (is_continue, ctx) = call_resp_filter(id, filter, ctx).await?;
                 if !is_continue {
                     return Ok(ctx);
                 }
@@ -837,7 +1073,7 @@ async fn process_resp_filters(
     for (id, filter) in route_filters {
         if !executed_filters.contains(&id) && filter.before_resp_filter_check(&ctx) {
             log::trace!("[SG.Plugin.Filter] Hit id {id} in response");
-            (is_continue, ctx) = filter.resp_filter(id, ctx).await?;
+            (is_continue, ctx) = call_resp_filter(id, filter, ctx).await?;
             if !is_continue {
                 return Ok(ctx);
             }
@@ -847,7 +1083,7 @@ async fn process_resp_filters(
     for (id, filter) in global_filters {
         if !executed_filters.contains(&id) && filter.before_resp_filter_check(&ctx) {
             log::trace!("[SG.Plugin.Filter] Hit id {id} in response");
-            (is_continue, ctx) = filter.resp_filter(id, ctx).await?;
+            (is_continue, ctx) = call_resp_filter(id, filter, ctx).await?;
             if !is_continue {
                 return Ok(ctx);
             }
@@ -857,17 +1093,99 @@ async fn process_resp_filters(
     Ok(ctx)
 }
 
-fn choose_backend(backends: &[SgBackendInst]) -> Option<&SgBackendInst> {
-    if backends.is_empty() {
-        None
-    } else if backends.len() == 1 {
-        backends.get(0)
-    } else {
-        let weights = backends.iter().map(|backend| backend.weight.unwrap_or(0)).collect_vec();
-        let dist = WeightedIndex::new(weights).expect("Unreachable code");
-        let mut rng = thread_rng();
-        backends.get(dist.sample(&mut rng))
+async fn call_req_filter(id: &str, filter: &BoxSgPluginFilter, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+    let Some(trace) = ctx.get_trace().cloned() else {
+        return filter.req_filter(id, ctx).await;
+    };
+    let mut span = trace.start_span(format!("req_filter {id}"), SgSpanKind::Internal);
+    let result = filter.req_filter(id, ctx).await;
+    if result.is_err() {
+        span.set_error();
     }
+    trace.end_span(span);
+    result
+}
+
+async fn call_resp_filter(id: &str, filter: &BoxSgPluginFilter, mut ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+    if filter.accept().need_body {
+        // Buffering is done once, the body is kept in memory for the following filters
+        ctx.response.dump_body().await?;
+    }
+    let Some(trace) = ctx.get_trace().cloned() else {
+        return filter.resp_filter(id, ctx).await;
+    };
+    let mut span = trace.start_span(format!("resp_filter {id}"), SgSpanKind::Internal);
+    let result = filter.resp_filter(id, ctx).await;
+    if result.is_err() {
+        span.set_error();
+    }
+    trace.end_span(span);
+    result
+}
+
+/// Feed the upstream result into the outlier detection of the rule, if the request was sent to the chosen backend.
+fn record_outlier(rule: Option<&SgHttpRouteRuleInst>, backend: Option<&SgBackendInst>, ctx: &SgRoutePluginContext) {
+    let (Some(rule), Some(backend)) = (rule, backend) else {
+        return;
+    };
+    let (Some(outlier_detection), Some(backends)) = (&rule.outlier_detection, &rule.backends) else {
+        return;
+    };
+    if !ctx.get_chose_backend().is_some_and(|chosen| chosen.name_or_host == backend.name_or_host && chosen.namespace == backend.namespace && chosen.port == backend.port) {
+        return;
+    }
+    let success = !ctx.is_resp_error() && !ctx.response.get_status_code().is_server_error();
+    outlier_detection::record(outlier_detection, backends, backend, success);
+}
+
+fn choose_backend<'a>(rule: &'a SgHttpRouteRuleInst, request: &Request<Body>, remote_addr: SocketAddr) -> Option<&'a SgBackendInst> {
+    let backends = rule.backends.as_ref()?;
+    rule.load_balancer.choose(backends, request, remote_addr, false).or_else(|| {
+        if backends.is_empty() {
+            return None;
+        }
+        match rule.panic_mode {
+            SgHealthPanicMode::AllBackends => {
+                log::warn!("[SG.Route] All backends are unavailable, choose from all backends");
+                rule.load_balancer.choose(backends, request, remote_addr, true)
+            }
+            SgHealthPanicMode::Reject => None,
+        }
+    })
+}
+
+/// Choose the backend of a TLS passthrough connection by the server name (SNI) requested by the client.
+///
+/// Routes are matched by their hostnames only (exact names first, then wildcards, then routes without hostnames),
+/// the first rule with backends of the matched route is used.
+///
+/// Returns the host and port of the backend with the version of the PROXY protocol header to send if any, or `None` if no backend is available.
+pub(crate) async fn choose_passthrough_backend(
+    gateway_name: &str,
+    server_name: Option<&str>,
+    local_port: u16,
+    remote_addr: SocketAddr,
+) -> TardisResult<Option<(String, u16, Option<SgProxyProtocolVersion>)>> {
+    let gateway_inst = get(gateway_name).await?;
+    if !match_listeners_hostname_and_port(server_name, local_port, &gateway_inst.listeners) {
+        return Ok(None);
+    }
+    let (highest, second, lowest) = match_route_insts_with_hostname_priority(server_name, &gateway_inst.routes);
+    let Some(rule) = highest
+        .into_iter()
+        .chain(second)
+        .chain(lowest)
+        .find_map(|route| route.rules.as_ref()?.iter().find(|rule| rule.backends.as_ref().is_some_and(|backends| !backends.is_empty())))
+    else {
+        return Ok(None);
+    };
+    // Only used by load balancing policies hashing the client ip
+    let request = Request::new(Body::empty());
+    Ok(choose_backend(rule, &request, remote_addr).map(|backend| {
+        let host = format!("{}{}", backend.name_or_host, backend.namespace.as_ref().map(|n| format!(".{n}")).unwrap_or("".to_string()));
+        let port = if backend.port == 0 { 443 } else { backend.port };
+        (host, port, backend.proxy_protocol)
+    }))
 }
 
 #[cfg(test)]
@@ -875,17 +1193,21 @@ fn choose_backend(backends: &[SgBackendInst]) -> Option<&SgBackendInst> {
 mod tests {
     use std::collections::HashMap;
 
-    use http::{Method, Request};
-    use hyper::Body;
-    use tardis::regex::Regex;
+    use http::{
+        header::{CONTENT_LENGTH, TRANSFER_ENCODING},
+        HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri, Version,
+    };
+    use hyper::{body::HttpBody, Body};
+    use tardis::{futures::stream, regex::Regex, tokio};
 
     use crate::{
-        config::http_route_dto::{SgHttpHeaderMatchType, SgHttpPathMatchType, SgHttpQueryMatchType},
+        config::http_route_dto::{SgHealthPanicMode, SgHttpHeaderMatchType, SgHttpPathMatchType, SgHttpQueryMatchType},
         functions::http_route::{choose_backend, match_route_insts_with_hostname_priority},
         instance::{SgBackendInst, SgHttpHeaderMatchInst, SgHttpPathMatchInst, SgHttpQueryMatchInst, SgHttpRouteInst, SgHttpRouteMatchInst, SgHttpRouteRuleInst},
+        plugins::context::SgRoutePluginContext,
     };
 
-    use super::{match_route_process, match_rule_inst};
+    use super::{match_route_process, match_rule_inst, process_response_headers};
 
     #[test]
     fn test_match_rule_inst() {
@@ -1604,37 +1926,117 @@ mod tests {
 
     #[test]
     fn test_choose_backend() {
+        let request = Request::builder().uri("https://sg.idealworld.group/").body(Body::empty()).unwrap();
+        let remote_addr = "127.0.0.1:8080".parse().unwrap();
+
+        // No backend
+        assert!(choose_backend(&SgHttpRouteRuleInst::default(), &request, remote_addr).is_none());
+
         // Only one backend
         assert!(
-            choose_backend(&[SgBackendInst {
-                name_or_host: "iam1".to_string(),
-                weight: None,
-                ..Default::default()
-            }])
+            choose_backend(
+                &SgHttpRouteRuleInst {
+                    backends: Some(vec![SgBackendInst {
+                        name_or_host: "iam1".to_string(),
+                        weight: None,
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                },
+                &request,
+                remote_addr
+            )
             .unwrap()
             .name_or_host
                 == "iam1"
         );
 
         // Check weight
-        let backends = vec![
-            SgBackendInst {
-                name_or_host: "iam1".to_string(),
-                weight: Some(30),
-                ..Default::default()
-            },
-            SgBackendInst {
-                name_or_host: "iam2".to_string(),
-                weight: Some(70),
-                ..Default::default()
-            },
-        ];
+        let rule = SgHttpRouteRuleInst {
+            backends: Some(vec![
+                SgBackendInst {
+                    name_or_host: "iam1".to_string(),
+                    weight: Some(30),
+                    ..Default::default()
+                },
+                SgBackendInst {
+                    name_or_host: "iam2".to_string(),
+                    weight: Some(70),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
         let mut backend_counts = HashMap::new();
         for _ in 0..1000 {
-            let backend = choose_backend(&backends);
+            let backend = choose_backend(&rule, &request, remote_addr);
             *backend_counts.entry(backend.as_ref().unwrap().name_or_host.clone()).or_insert(0) += 1;
         }
         println!("backend_counts: {:?}", backend_counts);
         assert!(backend_counts.get("iam1").unwrap() < backend_counts.get("iam2").unwrap());
+
+        // All weights are zero
+        let rule = SgHttpRouteRuleInst {
+            backends: Some(vec![
+                SgBackendInst {
+                    name_or_host: "iam1".to_string(),
+                    ..Default::default()
+                },
+                SgBackendInst {
+                    name_or_host: "iam2".to_string(),
+                    weight: Some(0),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+        assert!(choose_backend(&rule, &request, remote_addr).is_some());
+
+        // All backends are unhealthy
+        rule.backends.as_ref().unwrap().iter().for_each(|backend| backend.health.set_healthy(false));
+        assert!(choose_backend(&rule, &request, remote_addr).is_some());
+        let rule = SgHttpRouteRuleInst {
+            panic_mode: SgHealthPanicMode::Reject,
+            ..rule
+        };
+        assert!(choose_backend(&rule, &request, remote_addr).is_none());
+        rule.backends.as_ref().unwrap()[1].health.set_healthy(true);
+        assert_eq!(choose_backend(&rule, &request, remote_addr).unwrap().name_or_host, "iam2");
+    }
+
+    #[tokio::test]
+    async fn test_process_response_headers() {
+        let new_ctx = |method: Method, headers: Vec<(HeaderName, &str)>, body: Body| {
+            let ctx = SgRoutePluginContext::new_http(
+                method,
+                Uri::from_static("http://sg.idealworld.group/download"),
+                Version::HTTP_11,
+                HeaderMap::new(),
+                Body::empty(),
+                "127.0.0.1:8080".parse().unwrap(),
+                "".to_string(),
+                None,
+                None,
+            );
+            let headers = headers.into_iter().map(|(name, value)| (name, HeaderValue::from_str(value).unwrap())).collect();
+            ctx.resp(StatusCode::OK, headers, body)
+        };
+        let stream_body = || Body::wrap_stream(stream::iter(vec![Ok::<_, std::io::Error>("hello "), Ok("world")]));
+
+        // A streamed body is passed through with the upstream Content-Length
+        let mut ctx = process_response_headers(new_ctx(Method::GET, vec![(CONTENT_LENGTH, "11")], stream_body())).await.unwrap();
+        assert_eq!(ctx.response.get_headers().get(CONTENT_LENGTH).unwrap(), "11");
+        let body = ctx.response.take_body();
+        assert!(body.size_hint().exact().is_none());
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "hello world");
+
+        // A body replaced by a filter gets its own length
+        let ctx = process_response_headers(new_ctx(Method::GET, vec![(CONTENT_LENGTH, "11"), (TRANSFER_ENCODING, "chunked")], Body::from("hello"))).await.unwrap();
+        assert_eq!(ctx.response.get_headers().get(CONTENT_LENGTH).unwrap(), "5");
+        assert!(ctx.response.get_headers().get(TRANSFER_ENCODING).is_none());
+
+        // Responses to HEAD requests have no body
+        let ctx = process_response_headers(new_ctx(Method::HEAD, vec![(CONTENT_LENGTH, "11")], Body::empty())).await.unwrap();
+        assert_eq!(ctx.response.get_headers().get(CONTENT_LENGTH).unwrap(), "11");
     }
 }
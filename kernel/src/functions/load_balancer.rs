@@ -0,0 +1,388 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use http::{header::COOKIE, Request};
+use hyper::Body;
+use tardis::rand::{distributions::WeightedIndex, prelude::Distribution, thread_rng, Rng};
+
+use crate::config::http_route_dto::{SgLoadBalanceHashKey, SgLoadBalancePolicy};
+use crate::instance::SgBackendInst;
+
+/// Number of virtual nodes of the backend with the largest weight on the hash ring.
+const VIRTUAL_NODES: u64 = 160;
+
+/// Chooses a backend of a rule according to [SgLoadBalancePolicy], holds the state the policy needs between requests.
+#[derive(Default)]
+pub struct SgLoadBalancer {
+    policy: SgLoadBalancePolicy,
+    round_robin_index: AtomicUsize,
+    current_weights: Mutex<Vec<i64>>,
+    hash_ring: Vec<(u64, usize)>,
+}
+
+impl SgLoadBalancer {
+    pub fn new(policy: SgLoadBalancePolicy, backends: &[SgBackendInst]) -> Self {
+        let hash_ring = if matches!(policy, SgLoadBalancePolicy::ConsistentHash { .. }) {
+            build_hash_ring(backends)
+        } else {
+            Vec::new()
+        };
+        Self {
+            policy,
+            round_robin_index: AtomicUsize::new(0),
+            current_weights: Mutex::new(vec![0; backends.len()]),
+            hash_ring,
+        }
+    }
+
+    pub fn get_policy(&self) -> &SgLoadBalancePolicy {
+        &self.policy
+    }
+
+    /// Choose a backend, unavailable (unhealthy or ejected) backends are skipped unless `ignore_health` is set.
+    ///
+    /// Returns `None` if there is no backend available.
+    pub fn choose<'a>(&self, backends: &'a [SgBackendInst], request: &Request<Body>, remote_addr: SocketAddr, ignore_health: bool) -> Option<&'a SgBackendInst> {
+        let weights = effective_weights(backends, ignore_health)?;
+        if backends.len() == 1 {
+            return backends.first();
+        }
+        let index = match &self.policy {
+            SgLoadBalancePolicy::Random => weighted_random(&weights),
+            SgLoadBalancePolicy::RoundRobin => {
+                let candidates = candidates(&weights);
+                candidates[self.round_robin_index.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+            }
+            SgLoadBalancePolicy::WeightedRoundRobin => self.smooth_weighted_round_robin(&weights),
+            SgLoadBalancePolicy::LeastConnections => least_connections(backends, &weights),
+            SgLoadBalancePolicy::RandomOfTwo => random_of_two(backends, &weights),
+            SgLoadBalancePolicy::ConsistentHash { key } => {
+                get_hash_key(key, request, remote_addr).and_then(|key| self.lookup_hash_ring(&key, &weights)).unwrap_or_else(|| weighted_random(&weights))
+            }
+        };
+        backends.get(index)
+    }
+
+    fn smooth_weighted_round_robin(&self, weights: &[u64]) -> usize {
+        let mut current_weights = self.current_weights.lock().unwrap_or_else(|e| e.into_inner());
+        current_weights.resize(weights.len(), 0);
+        let total = weights.iter().sum::<u64>() as i64;
+        let mut best = 0;
+        for (i, weight) in weights.iter().enumerate() {
+            current_weights[i] += *weight as i64;
+            if current_weights[i] > current_weights[best] {
+                best = i;
+            }
+        }
+        current_weights[best] -= total;
+        best
+    }
+
+    /// Find the first node clockwise from the key whose backend is available, so only the keys of unavailable backends are moved.
+    fn lookup_hash_ring(&self, key: &str, weights: &[u64]) -> Option<usize> {
+        let hash = hash(key.as_bytes());
+        let pos = self.hash_ring.partition_point(|(node_hash, _)| *node_hash < hash);
+        self.hash_ring.iter().cycle().skip(pos).take(self.hash_ring.len()).map(|(_, index)| *index).find(|index| weights.get(*index).is_some_and(|weight| *weight > 0))
+    }
+}
+
+/// Unavailable backends and backends without weight get `0`, if all available backends have weight `0` each of them is treated as weight `1`.
+fn effective_weights(backends: &[SgBackendInst], ignore_health: bool) -> Option<Vec<u64>> {
+    let available = backends.iter().map(|backend| ignore_health || backend.is_available()).collect::<Vec<_>>();
+    if !available.contains(&true) {
+        return None;
+    }
+    let weights = backends.iter().zip(&available).map(|(backend, available)| if *available { backend.weight.unwrap_or(0) as u64 } else { 0 }).collect::<Vec<_>>();
+    if weights.iter().all(|weight| *weight == 0) {
+        Some(available.into_iter().map(|available| available as u64).collect())
+    } else {
+        Some(weights)
+    }
+}
+
+fn candidates(weights: &[u64]) -> Vec<usize> {
+    weights.iter().enumerate().filter(|(_, weight)| **weight > 0).map(|(i, _)| i).collect()
+}
+
+fn weighted_random(weights: &[u64]) -> usize {
+    WeightedIndex::new(weights).map(|dist| dist.sample(&mut thread_rng())).unwrap_or(0)
+}
+
+fn least_connections(backends: &[SgBackendInst], weights: &[u64]) -> usize {
+    let candidates = candidates(weights);
+    // Start from a random offset so that ties are not always won by the first backend
+    let offset = thread_rng().gen_range(0..candidates.len());
+    let mut best = candidates[offset];
+    for i in candidates.iter().cycle().skip(offset + 1).take(candidates.len() - 1) {
+        // in_flight(i) / weight(i) < in_flight(best) / weight(best)
+        let load = backends[*i].get_in_flight() as u128 * weights[best] as u128;
+        let best_load = backends[best].get_in_flight() as u128 * weights[*i] as u128;
+        if load < best_load {
+            best = *i;
+        }
+    }
+    best
+}
+
+fn random_of_two(backends: &[SgBackendInst], weights: &[u64]) -> usize {
+    let candidates = candidates(weights);
+    if candidates.len() == 1 {
+        return candidates[0];
+    }
+    let mut rng = thread_rng();
+    let first = rng.gen_range(0..candidates.len());
+    let second = (first + rng.gen_range(1..candidates.len())) % candidates.len();
+    let (first, second) = (candidates[first], candidates[second]);
+    if backends[second].get_in_flight() < backends[first].get_in_flight() {
+        second
+    } else {
+        first
+    }
+}
+
+fn build_hash_ring(backends: &[SgBackendInst]) -> Vec<(u64, usize)> {
+    let Some(weights) = effective_weights(backends, true) else {
+        return Vec::new();
+    };
+    let max_weight = weights.iter().max().copied().unwrap_or(0);
+    let mut ring = Vec::new();
+    for (i, backend) in backends.iter().enumerate() {
+        if weights[i] == 0 {
+            continue;
+        }
+        let virtual_nodes = (VIRTUAL_NODES * weights[i] / max_weight).max(1);
+        for node in 0..virtual_nodes {
+            let node_key = format!("{}.{}:{}#{}", backend.name_or_host, backend.namespace.as_deref().unwrap_or(""), backend.port, node);
+            ring.push((hash(node_key.as_bytes()), i));
+        }
+    }
+    ring.sort_unstable();
+    ring
+}
+
+fn get_hash_key(key: &SgLoadBalanceHashKey, request: &Request<Body>, remote_addr: SocketAddr) -> Option<String> {
+    match key {
+        SgLoadBalanceHashKey::Header { name } => request.headers().get(name).and_then(|value| value.to_str().ok()).map(|value| value.to_string()),
+        SgLoadBalanceHashKey::Cookie { name } => request
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(cookie_name, _)| cookie_name == name)
+            .map(|(_, value)| value.to_string()),
+        SgLoadBalanceHashKey::SourceIp => Some(remote_addr.ip().to_string()),
+    }
+}
+
+/// FNV-1a followed by a 64-bit finalizer, stable across processes so that all gateway replicas build the same ring.
+fn hash(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+
+    use http::Request;
+    use hyper::Body;
+
+    use super::SgLoadBalancer;
+    use crate::config::http_route_dto::{SgLoadBalanceHashKey, SgLoadBalancePolicy};
+    use crate::instance::SgBackendInst;
+
+    fn backends(weights: &[Option<u16>]) -> Vec<SgBackendInst> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| SgBackendInst {
+                name_or_host: format!("iam{}", i + 1),
+                port: 8080,
+                weight: *weight,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn count(lb: &SgLoadBalancer, backends: &[SgBackendInst], times: usize) -> HashMap<String, usize> {
+        let request = Request::builder().body(Body::empty()).unwrap();
+        let remote_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let mut counts = HashMap::new();
+        for _ in 0..times {
+            let backend = lb.choose(backends, &request, remote_addr, false).unwrap();
+            *counts.entry(backend.name_or_host.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_zero_weights() {
+        let backends = backends(&[None, Some(0), None]);
+        let lb = SgLoadBalancer::new(SgLoadBalancePolicy::Random, &backends);
+        assert_eq!(count(&lb, &backends, 300).len(), 3);
+
+        let backends = self::backends(&[Some(0), Some(1), None]);
+        let lb = SgLoadBalancer::new(SgLoadBalancePolicy::Random, &backends);
+        assert_eq!(count(&lb, &backends, 100).get("iam2"), Some(&100));
+    }
+
+    #[test]
+    fn test_unhealthy_backends() {
+        let backends = backends(&[Some(1), Some(0), Some(1)]);
+        backends[0].health.set_healthy(false);
+        let lb = SgLoadBalancer::new(SgLoadBalancePolicy::RoundRobin, &backends);
+        assert_eq!(count(&lb, &backends, 100).get("iam3"), Some(&100));
+
+        // Only the zero weight backend is healthy
+        backends[2].health.set_healthy(false);
+        assert_eq!(count(&lb, &backends, 100).get("iam2"), Some(&100));
+
+        backends[1].health.set_healthy(false);
+        let request = Request::builder().body(Body::empty()).unwrap();
+        let remote_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert!(lb.choose(&backends, &request, remote_addr, false).is_none());
+        assert!(lb.choose(&backends, &request, remote_addr, true).is_some());
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let backends = backends(&[Some(1), Some(10), Some(1)]);
+        let lb = SgLoadBalancer::new(SgLoadBalancePolicy::RoundRobin, &backends);
+        let counts = count(&lb, &backends, 300);
+        assert!(counts.values().all(|count| *count == 100));
+
+        let backends = self::backends(&[Some(1), Some(0), Some(10)]);
+        let lb = SgLoadBalancer::new(SgLoadBalancePolicy::RoundRobin, &backends);
+        let counts = count(&lb, &backends, 200);
+        assert_eq!(counts.get("iam2"), None);
+        assert!(counts.values().all(|count| *count == 100));
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let backends = backends(&[Some(5), Some(1), Some(1)]);
+        let lb = SgLoadBalancer::new(SgLoadBalancePolicy::WeightedRoundRobin, &backends);
+        let request = Request::builder().body(Body::empty()).unwrap();
+        let remote_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let sequence = (0..7).map(|_| lb.choose(&backends, &request, remote_addr, false).unwrap().name_or_host.clone()).collect::<Vec<_>>();
+        assert_eq!(sequence, vec!["iam1", "iam1", "iam2", "iam1", "iam3", "iam1", "iam1"]);
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let backends = backends(&[Some(1), Some(1), Some(2)]);
+        let lb = SgLoadBalancer::new(SgLoadBalancePolicy::LeastConnections, &backends);
+        let request = Request::builder().body(Body::empty()).unwrap();
+        let remote_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let guard1 = backends[0].begin_request();
+        let _guard2 = backends[1].begin_request();
+        let _guard3 = backends[2].begin_request();
+        assert_eq!(lb.choose(&backends, &request, remote_addr, false).unwrap().name_or_host, "iam3");
+        let _guard4 = backends[2].begin_request();
+        let _guard5 = backends[2].begin_request();
+        let backend = lb.choose(&backends, &request, remote_addr, false).unwrap();
+        assert!(backend.name_or_host == "iam1" || backend.name_or_host == "iam2");
+        drop(guard1);
+        assert_eq!(backends[0].get_in_flight(), 0);
+        assert_eq!(lb.choose(&backends, &request, remote_addr, false).unwrap().name_or_host, "iam1");
+    }
+
+    #[test]
+    fn test_random_of_two() {
+        let backends = backends(&[Some(1), Some(1)]);
+        let lb = SgLoadBalancer::new(SgLoadBalancePolicy::RandomOfTwo, &backends);
+        let _guard = backends[0].begin_request();
+        assert_eq!(count(&lb, &backends, 100).get("iam2"), Some(&100));
+    }
+
+    #[test]
+    fn test_consistent_hash() {
+        let backends = backends(&[Some(1), Some(1), Some(1)]);
+        let lb = SgLoadBalancer::new(
+            SgLoadBalancePolicy::ConsistentHash {
+                key: SgLoadBalanceHashKey::Header { name: "X-User".to_string() },
+            },
+            &backends,
+        );
+        let remote_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        let mut counts = HashMap::new();
+        for i in 0..300 {
+            let request = Request::builder().header("X-User", format!("user{i}")).body(Body::empty()).unwrap();
+            let backend = lb.choose(&backends, &request, remote_addr, false).unwrap();
+            for _ in 0..5 {
+                assert_eq!(lb.choose(&backends, &request, remote_addr, false).unwrap().name_or_host, backend.name_or_host);
+            }
+            *counts.entry(backend.name_or_host.clone()).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 3);
+        assert!(counts.values().all(|count| *count > 50));
+
+        // Removing a backend only moves the keys that were on it
+        let less_backends = self::backends(&[Some(1), Some(1)]);
+        let less_lb = SgLoadBalancer::new(lb.get_policy().clone(), &less_backends);
+        for i in 0..300 {
+            let request = Request::builder().header("X-User", format!("user{i}")).body(Body::empty()).unwrap();
+            let backend = lb.choose(&backends, &request, remote_addr, false).unwrap();
+            if backend.name_or_host != "iam3" {
+                assert_eq!(less_lb.choose(&less_backends, &request, remote_addr, false).unwrap().name_or_host, backend.name_or_host);
+            }
+        }
+
+        // So does an unhealthy backend
+        let keys = (0..300)
+            .map(|i| {
+                let request = Request::builder().header("X-User", format!("user{i}")).body(Body::empty()).unwrap();
+                lb.choose(&backends, &request, remote_addr, false).unwrap().name_or_host.clone()
+            })
+            .collect::<Vec<_>>();
+        backends[2].health.set_healthy(false);
+        for (i, key) in keys.iter().enumerate() {
+            let request = Request::builder().header("X-User", format!("user{i}")).body(Body::empty()).unwrap();
+            let backend = lb.choose(&backends, &request, remote_addr, false).unwrap();
+            assert_ne!(backend.name_or_host, "iam3");
+            if key != "iam3" {
+                assert_eq!(&backend.name_or_host, key);
+            }
+        }
+        backends[2].health.set_healthy(true);
+
+        let lb = SgLoadBalancer::new(
+            SgLoadBalancePolicy::ConsistentHash {
+                key: SgLoadBalanceHashKey::Cookie { name: "session".to_string() },
+            },
+            &backends,
+        );
+        let request = Request::builder().header("Cookie", "a=1; session=abc; b=2").body(Body::empty()).unwrap();
+        let backend = lb.choose(&backends, &request, remote_addr, false).unwrap();
+        for _ in 0..10 {
+            assert_eq!(lb.choose(&backends, &request, remote_addr, false).unwrap().name_or_host, backend.name_or_host);
+        }
+
+        let lb = SgLoadBalancer::new(
+            SgLoadBalancePolicy::ConsistentHash {
+                key: SgLoadBalanceHashKey::SourceIp,
+            },
+            &backends,
+        );
+        let request = Request::builder().body(Body::empty()).unwrap();
+        let backend = lb.choose(&backends, &request, "10.0.0.1:1234".parse().unwrap(), false).unwrap();
+        for port in 0..10 {
+            assert_eq!(
+                lb.choose(&backends, &request, format!("10.0.0.1:{port}").parse().unwrap(), false).unwrap().name_or_host,
+                backend.name_or_host
+            );
+        }
+    }
+}
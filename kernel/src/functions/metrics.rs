@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
+
+use http::{header::CONTENT_TYPE, HeaderValue, Request, Response, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Server};
+use lazy_static::lazy_static;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use tardis::basic::{error::TardisError, result::TardisResult};
+use tardis::log;
+use tardis::tokio::{
+    self,
+    sync::{watch::Sender, Mutex},
+    task::JoinHandle,
+};
+
+use crate::config::gateway_dto::SgMetricsConfig;
+use crate::plugins::context::SgRoutePluginContext;
+
+const LABEL_GATEWAY: &str = "gateway";
+const LABELS: [&str; 4] = [LABEL_GATEWAY, "route", "rule", "backend"];
+const LABELS_WITH_STATUS: [&str; 5] = [LABEL_GATEWAY, "route", "rule", "backend", "status_class"];
+const STREAM_LABELS: [&str; 4] = [LABEL_GATEWAY, "route", "backend", "protocol"];
+const STREAM_REJECTED_LABELS: [&str; 3] = [LABEL_GATEWAY, "route", "protocol"];
+
+struct SgMetrics {
+    registry: Registry,
+    requests_total: IntCounterVec,
+    request_duration_seconds: HistogramVec,
+    requests_in_flight: IntGaugeVec,
+    upstream_errors_total: IntCounterVec,
+    upstream_timeouts_total: IntCounterVec,
+    stream_connections_total: IntCounterVec,
+    stream_connections_active: IntGaugeVec,
+    stream_rejected_connections_total: IntCounterVec,
+    stream_received_bytes_total: IntCounterVec,
+    stream_sent_bytes_total: IntCounterVec,
+}
+
+impl SgMetrics {
+    fn new() -> prometheus::Result<Self> {
+        let metrics = SgMetrics {
+            registry: Registry::new(),
+            requests_total: IntCounterVec::new(Opts::new("spacegate_requests_total", "Total number of requests"), &LABELS_WITH_STATUS)?,
+            request_duration_seconds: HistogramVec::new(
+                HistogramOpts::new("spacegate_request_duration_seconds", "Time from receiving the request to sending the response headers"),
+                &LABELS_WITH_STATUS,
+            )?,
+            requests_in_flight: IntGaugeVec::new(Opts::new("spacegate_requests_in_flight", "Number of requests being processed"), &LABELS)?,
+            upstream_errors_total: IntCounterVec::new(
+                Opts::new("spacegate_upstream_errors_total", "Total number of requests that failed to get a response from the backend"),
+                &LABELS_WITH_STATUS,
+            )?,
+            upstream_timeouts_total: IntCounterVec::new(
+                Opts::new("spacegate_upstream_timeouts_total", "Total number of requests that timed out waiting for the backend"),
+                &LABELS,
+            )?,
+            stream_connections_total: IntCounterVec::new(
+                Opts::new("spacegate_stream_connections_total", "Total number of forwarded TCP connections and UDP sessions"),
+                &STREAM_LABELS,
+            )?,
+            stream_connections_active: IntGaugeVec::new(
+                Opts::new("spacegate_stream_connections_active", "Number of TCP connections and UDP sessions being forwarded"),
+                &STREAM_LABELS,
+            )?,
+            stream_rejected_connections_total: IntCounterVec::new(
+                Opts::new(
+                    "spacegate_stream_rejected_connections_total",
+                    "Total number of TCP connections and UDP sessions rejected because the route reached its connection limit",
+                ),
+                &STREAM_REJECTED_LABELS,
+            )?,
+            stream_received_bytes_total: IntCounterVec::new(
+                Opts::new("spacegate_stream_received_bytes_total", "Total number of bytes received from clients"),
+                &STREAM_LABELS,
+            )?,
+            stream_sent_bytes_total: IntCounterVec::new(Opts::new("spacegate_stream_sent_bytes_total", "Total number of bytes sent to clients"), &STREAM_LABELS)?,
+        };
+        metrics.registry.register(Box::new(metrics.requests_total.clone()))?;
+        metrics.registry.register(Box::new(metrics.request_duration_seconds.clone()))?;
+        metrics.registry.register(Box::new(metrics.requests_in_flight.clone()))?;
+        metrics.registry.register(Box::new(metrics.upstream_errors_total.clone()))?;
+        metrics.registry.register(Box::new(metrics.upstream_timeouts_total.clone()))?;
+        metrics.registry.register(Box::new(metrics.stream_connections_total.clone()))?;
+        metrics.registry.register(Box::new(metrics.stream_connections_active.clone()))?;
+        metrics.registry.register(Box::new(metrics.stream_rejected_connections_total.clone()))?;
+        metrics.registry.register(Box::new(metrics.stream_received_bytes_total.clone()))?;
+        metrics.registry.register(Box::new(metrics.stream_sent_bytes_total.clone()))?;
+        Ok(metrics)
+    }
+}
+
+lazy_static! {
+    static ref METRICS: SgMetrics = SgMetrics::new().expect("[SG.Metrics] Register metrics failed");
+    static ref SHUTDOWN_TX: Mutex<HashMap<String, (Sender<()>, JoinHandle<Result<(), hyper::Error>>)>> = Default::default();
+}
+
+/// Metrics of a request, the labels are filled in as the request is routed and the request is counted when it finishes.
+pub struct SgRequestMetrics {
+    enabled: bool,
+    start: Instant,
+    gateway: String,
+    route: String,
+    rule: String,
+    backend: String,
+    in_flight: Option<IntGauge>,
+}
+
+impl SgRequestMetrics {
+    pub fn new(gateway_name: &str) -> Self {
+        SgRequestMetrics {
+            enabled: false,
+            start: Instant::now(),
+            gateway: gateway_name.to_string(),
+            route: "".to_string(),
+            rule: "".to_string(),
+            backend: "".to_string(),
+            in_flight: None,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn set_route(&mut self, route: &str, rule: Option<&str>) {
+        self.route = route.to_string();
+        self.rule = rule.unwrap_or_default().to_string();
+    }
+
+    /// Set the chosen backend, the request is in flight from now on.
+    pub fn set_backend(&mut self, backend: &str) {
+        self.backend = backend.to_string();
+        if self.enabled && self.in_flight.is_none() {
+            let in_flight = METRICS.requests_in_flight.with_label_values(&self.labels());
+            in_flight.inc();
+            self.in_flight = Some(in_flight);
+        }
+    }
+
+    /// Record the result of the request sent to the backend.
+    ///
+    /// The gateway answers `504` when the backend doesn't respond in time, so a `504` is counted as a timeout.
+    pub fn record_upstream(&self, ctx: &SgRoutePluginContext) {
+        if !self.enabled {
+            return;
+        }
+        if ctx.is_resp_error() {
+            let status_class = status_class(*ctx.response.get_status_code());
+            let [gateway, route, rule, backend] = self.labels();
+            METRICS.upstream_errors_total.with_label_values(&[gateway, route, rule, backend, status_class]).inc();
+        } else if ctx.response.get_status_code() == &StatusCode::GATEWAY_TIMEOUT {
+            METRICS.upstream_timeouts_total.with_label_values(&self.labels()).inc();
+        }
+    }
+
+    /// Count the request with the status code sent to the client.
+    pub fn finish(self, status: StatusCode) {
+        if !self.enabled {
+            return;
+        }
+        let [gateway, route, rule, backend] = self.labels();
+        let labels = [gateway, route, rule, backend, status_class(status)];
+        METRICS.requests_total.with_label_values(&labels).inc();
+        METRICS.request_duration_seconds.with_label_values(&labels).observe(self.start.elapsed().as_secs_f64());
+    }
+
+    fn labels(&self) -> [&str; 4] {
+        [&self.gateway, &self.route, &self.rule, &self.backend]
+    }
+}
+
+impl Drop for SgRequestMetrics {
+    fn drop(&mut self) {
+        if let Some(in_flight) = &self.in_flight {
+            in_flight.dec();
+        }
+    }
+}
+
+/// Metrics of a TCP connection or UDP session forwarded by a stream route, the connection is active until this is dropped.
+pub struct SgStreamMetrics {
+    active: Option<IntGauge>,
+    received_bytes: Option<IntCounter>,
+    sent_bytes: Option<IntCounter>,
+}
+
+impl SgStreamMetrics {
+    pub fn new(enabled: bool, gateway_name: &str, route: &str, backend: &str, protocol: &str) -> Self {
+        if !enabled {
+            return SgStreamMetrics {
+                active: None,
+                received_bytes: None,
+                sent_bytes: None,
+            };
+        }
+        let labels = [gateway_name, route, backend, protocol];
+        METRICS.stream_connections_total.with_label_values(&labels).inc();
+        let active = METRICS.stream_connections_active.with_label_values(&labels);
+        active.inc();
+        SgStreamMetrics {
+            active: Some(active),
+            received_bytes: Some(METRICS.stream_received_bytes_total.with_label_values(&labels)),
+            sent_bytes: Some(METRICS.stream_sent_bytes_total.with_label_values(&labels)),
+        }
+    }
+
+    /// Count a connection rejected by the connection limit of the route.
+    pub fn reject(enabled: bool, gateway_name: &str, route: &str, protocol: &str) {
+        if enabled {
+            METRICS.stream_rejected_connections_total.with_label_values(&[gateway_name, route, protocol]).inc();
+        }
+    }
+
+    pub fn add_received_bytes(&self, bytes: usize) {
+        if let Some(received_bytes) = &self.received_bytes {
+            received_bytes.inc_by(bytes as u64);
+        }
+    }
+
+    pub fn add_sent_bytes(&self, bytes: usize) {
+        if let Some(sent_bytes) = &self.sent_bytes {
+            sent_bytes.inc_by(bytes as u64);
+        }
+    }
+}
+
+impl Drop for SgStreamMetrics {
+    fn drop(&mut self) {
+        if let Some(active) = &self.active {
+            active.dec();
+        }
+    }
+}
+
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+/// Start the metrics listener of the gateway, returns the bound address.
+pub async fn init(gateway_name: &str, config: &SgMetricsConfig) -> TardisResult<SocketAddr> {
+    remove(gateway_name).await?;
+    let ip: IpAddr = config.ip.parse().map_err(|e| TardisError::format_error(&format!("[SG.Metrics] ip {} parse error: {e}", config.ip), ""))?;
+    let addr = SocketAddr::new(ip, config.port);
+    let name = Arc::new(gateway_name.to_string());
+    let path = Arc::new(config.path.clone());
+    let make_svc = make_service_fn(move |_conn| {
+        let name = name.clone();
+        let path = path.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request: Request<Body>| serve(request, name.clone(), path.clone()))) }
+    });
+    let server = match Server::try_bind(&addr) {
+        Ok(server) => server.serve(make_svc),
+        Err(e) => return Err(TardisError::conflict(&format!("[SG.Metrics] bind {addr} error: {e}"), "")),
+    };
+    let addr = server.local_addr();
+    let (shutdown_tx, mut shutdown_rx) = tokio::sync::watch::channel(());
+    let join = tokio::spawn(async move {
+        log::info!("[SG.Metrics] Listening on http://{addr}");
+        server
+            .with_graceful_shutdown(async move {
+                shutdown_rx.changed().await.ok();
+            })
+            .await
+    });
+    SHUTDOWN_TX.lock().await.insert(gateway_name.to_string(), (shutdown_tx, join));
+    Ok(addr)
+}
+
+/// Stop the metrics listener of the gateway.
+pub async fn remove(gateway_name: &str) -> TardisResult<()> {
+    let shutdown = SHUTDOWN_TX.lock().await.remove(gateway_name);
+    if let Some((shutdown_tx, join)) = shutdown {
+        shutdown_tx.send(()).ok();
+        let _ = join.await;
+        log::info!("[SG.Metrics] Listener of {gateway_name} stopped");
+    }
+    Ok(())
+}
+
+async fn serve(request: Request<Body>, gateway_name: Arc<String>, path: Arc<String>) -> Result<Response<Body>, Infallible> {
+    if request.uri().path() != path.as_str() {
+        let mut response = Response::default();
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+    let response = match encode(&gateway_name) {
+        Ok(metrics) => {
+            let mut response = Response::new(Body::from(metrics));
+            response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(prometheus::TEXT_FORMAT));
+            response
+        }
+        Err(error) => {
+            log::warn!("[SG.Metrics] Encode metrics error: {}", error.message);
+            let mut response = Response::default();
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    };
+    Ok(response)
+}
+
+/// Encode the metrics of the gateway in Prometheus text format.
+fn encode(gateway_name: &str) -> TardisResult<String> {
+    let mut families = METRICS.registry.gather();
+    for family in &mut families {
+        family.mut_metric().retain(|metric| metric.get_label().iter().any(|label| label.get_name() == LABEL_GATEWAY && label.get_value() == gateway_name));
+    }
+    families.retain(|family| !family.get_metric().is_empty());
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&families, &mut buffer).map_err(|e| TardisError::internal_error(&format!("[SG.Metrics] Encode error: {e}"), ""))?;
+    String::from_utf8(buffer).map_err(|e| TardisError::internal_error(&format!("[SG.Metrics] Encode error: {e}"), ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Method, StatusCode, Uri, Version};
+    use hyper::{Body, Client, HeaderMap};
+    use tardis::{basic::error::TardisError, tokio};
+
+    use super::{encode, init, remove, SgRequestMetrics};
+    use crate::config::gateway_dto::SgMetricsConfig;
+    use crate::plugins::context::SgRoutePluginContext;
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let ctx = SgRoutePluginContext::new_http(
+            Method::GET,
+            Uri::from_static("http://sg.idealworld.group/iam"),
+            Version::HTTP_11,
+            HeaderMap::new(),
+            Body::empty(),
+            "127.0.0.1:8080".parse().unwrap(),
+            "".to_string(),
+            None,
+            None,
+        );
+
+        let mut request_metrics = SgRequestMetrics::new("test_metrics_gw");
+        request_metrics.set_enabled(true);
+        request_metrics.set_route("iam", Some("0"));
+        request_metrics.set_backend("iam-backend");
+        let metrics = encode("test_metrics_gw").unwrap();
+        assert!(metrics.contains(r#"spacegate_requests_in_flight{backend="iam-backend",gateway="test_metrics_gw",route="iam",rule="0"} 1"#));
+        request_metrics.record_upstream(&ctx.resp_from_error(TardisError::custom("502", "connection refused", "")));
+        request_metrics.finish(StatusCode::BAD_GATEWAY);
+
+        let metrics = encode("test_metrics_gw").unwrap();
+        assert!(metrics.contains(r#"spacegate_requests_in_flight{backend="iam-backend",gateway="test_metrics_gw",route="iam",rule="0"} 0"#));
+        assert!(metrics.contains(r#"spacegate_requests_total{backend="iam-backend",gateway="test_metrics_gw",route="iam",rule="0",status_class="5xx"} 1"#));
+        assert!(metrics.contains(r#"spacegate_upstream_errors_total{backend="iam-backend",gateway="test_metrics_gw",route="iam",rule="0",status_class="5xx"} 1"#));
+        assert!(metrics.contains(r#"spacegate_request_duration_seconds_count{backend="iam-backend",gateway="test_metrics_gw",route="iam",rule="0",status_class="5xx"} 1"#));
+
+        // Disabled gateways and other gateways are not exposed
+        let mut request_metrics = SgRequestMetrics::new("test_metrics_disabled_gw");
+        request_metrics.set_backend("iam-backend");
+        request_metrics.finish(StatusCode::OK);
+        assert!(encode("test_metrics_disabled_gw").unwrap().is_empty());
+        assert!(!encode("test_metrics_gw").unwrap().contains("test_metrics_disabled_gw"));
+
+        let addr = init(
+            "test_metrics_gw",
+            &SgMetricsConfig {
+                ip: "127.0.0.1".to_string(),
+                port: 0,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let client = Client::new();
+        let response = client.get(format!("http://{addr}/metrics").parse().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("spacegate_requests_total"));
+        let response = client.get(format!("http://{addr}/other").parse().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        remove("test_metrics_gw").await.unwrap();
+        assert!(client.get(format!("http://{addr}/metrics").parse().unwrap()).await.is_err());
+    }
+}
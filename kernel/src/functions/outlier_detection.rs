@@ -0,0 +1,141 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tardis::log;
+
+use crate::config::http_route_dto::SgOutlierDetection;
+use crate::instance::SgBackendInst;
+
+/// Outlier detection state of a backend, updated with the result of every request sent to it.
+#[derive(Default)]
+pub struct SgBackendOutlier {
+    state: Mutex<OutlierState>,
+}
+
+#[derive(Default)]
+struct OutlierState {
+    consecutive_errors: u32,
+    ejected_until: Option<Instant>,
+    ejection_multiplier: u32,
+}
+
+impl SgBackendOutlier {
+    pub fn is_ejected(&self) -> bool {
+        self.is_ejected_at(Instant::now())
+    }
+
+    fn is_ejected_at(&self, now: Instant) -> bool {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).ejected_until.is_some_and(|ejected_until| now < ejected_until)
+    }
+}
+
+/// Record the result of a request sent to `backend`, one of the `backends` of a rule.
+pub(crate) fn record(config: &SgOutlierDetection, backends: &[SgBackendInst], backend: &SgBackendInst, success: bool) {
+    record_at(config, backends, backend, success, Instant::now())
+}
+
+fn record_at(config: &SgOutlierDetection, backends: &[SgBackendInst], backend: &SgBackendInst, success: bool, now: Instant) {
+    let base_ejection_time = Duration::from_millis(config.base_ejection_time_ms);
+    if success {
+        let mut state = backend.outlier.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.consecutive_errors = 0;
+        // Each base ejection time without being ejected again lowers the next ejection time
+        if let Some(ejected_until) = state.ejected_until {
+            if state.ejection_multiplier > 0 && now >= ejected_until + base_ejection_time {
+                state.ejection_multiplier -= 1;
+                state.ejected_until = Some(now);
+            }
+        }
+        return;
+    }
+    // Count before locking the state of this backend, each state is only locked on its own
+    let ejected = backends.iter().filter(|backend| backend.outlier.is_ejected_at(now)).count();
+    let mut state = backend.outlier.state.lock().unwrap_or_else(|e| e.into_inner());
+    if state.ejected_until.is_some_and(|ejected_until| now < ejected_until) {
+        return;
+    }
+    state.consecutive_errors += 1;
+    if state.consecutive_errors < config.consecutive_errors.max(1) {
+        return;
+    }
+    if ejected > 0 && (ejected + 1) * 100 > config.max_ejection_percent as usize * backends.len() {
+        log::debug!(
+            "[SG.OutlierDetection] Backend {backend} reaches {} consecutive errors, but {ejected} of {} backends are already ejected",
+            state.consecutive_errors,
+            backends.len()
+        );
+        return;
+    }
+    state.ejection_multiplier += 1;
+    let ejection_time = base_ejection_time.saturating_mul(state.ejection_multiplier).min(Duration::from_millis(config.max_ejection_time_ms));
+    state.ejected_until = Some(now + ejection_time);
+    state.consecutive_errors = 0;
+    log::warn!(
+        "[SG.OutlierDetection] Backend {backend} is ejected for {} ms after {} consecutive errors",
+        ejection_time.as_millis(),
+        config.consecutive_errors
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::record_at;
+    use crate::config::http_route_dto::SgOutlierDetection;
+    use crate::instance::SgBackendInst;
+
+    #[test]
+    fn test_outlier_detection() {
+        let config = SgOutlierDetection {
+            consecutive_errors: 2,
+            base_ejection_time_ms: 1000,
+            max_ejection_time_ms: 2500,
+            max_ejection_percent: 50,
+        };
+        let backends = (1..=4)
+            .map(|i| SgBackendInst {
+                name_or_host: format!("iam{i}"),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let now = Instant::now();
+
+        // A success resets the consecutive errors
+        record_at(&config, &backends, &backends[0], false, now);
+        record_at(&config, &backends, &backends[0], true, now);
+        record_at(&config, &backends, &backends[0], false, now);
+        assert!(!backends[0].outlier.is_ejected());
+        record_at(&config, &backends, &backends[0], false, now);
+        assert!(backends[0].outlier.is_ejected());
+        assert!(!backends[0].is_available());
+        assert_eq!(backends[0].outlier.state.lock().unwrap().ejected_until, Some(now + Duration::from_millis(1000)));
+
+        // At most half of the backends are ejected
+        for _ in 0..2 {
+            record_at(&config, &backends, &backends[1], false, now);
+            record_at(&config, &backends, &backends[2], false, now);
+        }
+        assert!(backends[1].outlier.is_ejected());
+        assert!(!backends[2].outlier.is_ejected());
+
+        // The ejection time grows and is capped
+        let later = now + Duration::from_millis(1000);
+        record_at(&config, &backends, &backends[3], false, later);
+        record_at(&config, &backends, &backends[3], false, later);
+        assert_eq!(backends[3].outlier.state.lock().unwrap().ejected_until, Some(later + Duration::from_millis(1000)));
+        let later = later + Duration::from_millis(1000);
+        record_at(&config, &backends, &backends[3], false, later);
+        record_at(&config, &backends, &backends[3], false, later);
+        assert_eq!(backends[3].outlier.state.lock().unwrap().ejected_until, Some(later + Duration::from_millis(2000)));
+        let later = later + Duration::from_millis(2000);
+        record_at(&config, &backends, &backends[3], false, later);
+        record_at(&config, &backends, &backends[3], false, later);
+        assert_eq!(backends[3].outlier.state.lock().unwrap().ejected_until, Some(later + Duration::from_millis(2500)));
+
+        // Staying healthy lowers the next ejection time
+        let later = later + Duration::from_millis(2500 + 1000);
+        record_at(&config, &backends, &backends[3], true, later);
+        assert_eq!(backends[3].outlier.state.lock().unwrap().ejection_multiplier, 2);
+    }
+}
@@ -0,0 +1,419 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::server::accept::Accept;
+use ipnet::IpNet;
+use tardis::{
+    basic::{error::TardisError, result::TardisResult},
+    futures_util::{stream::FuturesUnordered, StreamExt},
+    log,
+    tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+        net::{TcpListener, TcpStream},
+        time::{sleep, timeout, Sleep},
+    },
+};
+
+use crate::config::gateway_dto::{SgProxyProtocolConfig, SgProxyProtocolVersion};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Maximum length of a version 1 header, including the CRLF.
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+const V2_COMMAND_LOCAL: u8 = 0x20;
+const V2_COMMAND_PROXY: u8 = 0x21;
+const V2_FAMILY_TCP4: u8 = 0x11;
+const V2_FAMILY_TCP6: u8 = 0x21;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Trusted peers of a listener, see [SgProxyProtocolConfig].
+#[derive(Debug, Clone)]
+pub(crate) struct SgProxyProtocolInst {
+    trusted_sources: Vec<IpNet>,
+}
+
+impl SgProxyProtocolInst {
+    pub(crate) fn new(config: &SgProxyProtocolConfig) -> TardisResult<SgProxyProtocolInst> {
+        // Trusting any peer would let every client spoof its address
+        if config.trusted_sources.is_empty() {
+            return Err(TardisError::bad_request("[SG.ProxyProtocol] Trusted sources are required", ""));
+        }
+        let trusted_sources = config
+            .trusted_sources
+            .iter()
+            .map(|source| {
+                source
+                    .parse::<IpNet>()
+                    .or(source.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| TardisError::bad_request(&format!("[SG.ProxyProtocol] Trusted source {source} is not a legal IP or CIDR"), ""))
+            })
+            .collect::<TardisResult<Vec<_>>>()?;
+        Ok(SgProxyProtocolInst { trusted_sources })
+    }
+
+    pub(crate) fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(IpAddr::V6(ip)),
+            ip => ip,
+        };
+        self.trusted_sources.iter().any(|source| source.contains(&ip))
+    }
+}
+
+/// Read the PROXY protocol header at the beginning of a connection from a trusted peer,
+/// no byte after the header is consumed.
+///
+/// Returns the address of the client, which is the peer itself for untrusted peers and for health checks of the load balancer.
+pub(crate) async fn accept<S: AsyncRead + Unpin>(proxy_protocol: Option<&SgProxyProtocolInst>, stream: &mut S, peer_addr: SocketAddr) -> TardisResult<SocketAddr> {
+    match proxy_protocol {
+        Some(proxy_protocol) if proxy_protocol.is_trusted(peer_addr.ip()) => {
+            let source = timeout(HEADER_TIMEOUT, read_header(stream)).await.map_err(|_| TardisError::timeout("[SG.ProxyProtocol] Wait header timeout", ""))??;
+            Ok(source.unwrap_or(peer_addr))
+        }
+        _ => Ok(peer_addr),
+    }
+}
+
+async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> TardisResult<Option<SocketAddr>> {
+    let mut header = vec![0; V1_PREFIX.len()];
+    stream.read_exact(&mut header).await?;
+    if header == V1_PREFIX {
+        // The length is unknown, read byte by byte to leave the following bytes in the stream
+        while !header.ends_with(b"\r\n") {
+            if header.len() >= V1_MAX_LEN {
+                return Err(TardisError::bad_request("[SG.ProxyProtocol] Header v1 is too long", ""));
+            }
+            header.push(stream.read_u8().await?);
+        }
+        return parse_v1(&header);
+    }
+    header.resize(V2_HEADER_LEN, 0);
+    stream.read_exact(&mut header[V1_PREFIX.len()..]).await?;
+    if &header[..V2_SIGNATURE.len()] != V2_SIGNATURE {
+        return Err(TardisError::bad_request("[SG.ProxyProtocol] Header is required from trusted sources", ""));
+    }
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    header.resize(V2_HEADER_LEN + len, 0);
+    stream.read_exact(&mut header[V2_HEADER_LEN..]).await?;
+    parse_v2(&header)
+}
+
+/// Incoming connections of a HTTP(S) listener, the PROXY protocol headers of trusted peers are read
+/// concurrently so that a slow peer does not block the accept loop.
+pub(crate) struct SgIncoming {
+    listener: TcpListener,
+    proxy_protocol: Option<Arc<SgProxyProtocolInst>>,
+    handshakes: FuturesUnordered<Pin<Box<dyn Future<Output = TardisResult<SgStream>> + Send>>>,
+    /// Back off after accept errors, e.g. too many open files.
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl SgIncoming {
+    pub(crate) async fn bind(addr: SocketAddr, proxy_protocol: Option<&SgProxyProtocolConfig>) -> TardisResult<SgIncoming> {
+        let proxy_protocol = proxy_protocol.map(SgProxyProtocolInst::new).transpose()?.map(Arc::new);
+        let listener = TcpListener::bind(addr).await.map_err(|error| TardisError::bad_request(&format!("[SG.Server] Bind address error: {error}"), ""))?;
+        Ok(SgIncoming {
+            listener,
+            proxy_protocol,
+            handshakes: FuturesUnordered::new(),
+            sleep: None,
+        })
+    }
+}
+
+impl Accept for SgIncoming {
+    type Conn = SgStream;
+    type Error = io::Error;
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let pin = self.get_mut();
+        // Accept all pending connections first, so that the new handshakes are polled below
+        loop {
+            if let Some(sleep) = &mut pin.sleep {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    break;
+                }
+                pin.sleep = None;
+            }
+            match pin.listener.poll_accept(cx) {
+                Poll::Ready(Ok((stream, peer_addr))) => {
+                    let local_addr = match stream.local_addr() {
+                        Ok(local_addr) => local_addr,
+                        Err(error) => {
+                            log::debug!("[SG.Server] Connection from {peer_addr} closed: {error}");
+                            continue;
+                        }
+                    };
+                    match &pin.proxy_protocol {
+                        Some(proxy_protocol) if proxy_protocol.is_trusted(peer_addr.ip()) => {
+                            let proxy_protocol = proxy_protocol.clone();
+                            pin.handshakes.push(Box::pin(async move {
+                                let mut stream = stream;
+                                let remote_addr = accept(Some(&proxy_protocol), &mut stream, peer_addr).await?;
+                                Ok(SgStream { stream, remote_addr, local_addr })
+                            }));
+                        }
+                        _ => {
+                            return Poll::Ready(Some(Ok(SgStream {
+                                stream,
+                                remote_addr: peer_addr,
+                                local_addr,
+                            })))
+                        }
+                    }
+                }
+                Poll::Ready(Err(error)) => {
+                    log::warn!("[SG.Server] Accept failed: {error}");
+                    pin.sleep = Some(Box::pin(sleep(Duration::from_millis(100))));
+                }
+                Poll::Pending => break,
+            }
+        }
+        while let Poll::Ready(Some(handshake)) = pin.handshakes.poll_next_unpin(cx) {
+            match handshake {
+                Ok(stream) => return Poll::Ready(Some(Ok(stream))),
+                Err(error) => log::warn!("[SG.ProxyProtocol] Read header failed: {error}"),
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Connection of a HTTP(S) listener, the remote address is the client address from the PROXY protocol header if any.
+pub(crate) struct SgStream {
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+    local_addr: SocketAddr,
+}
+
+impl SgStream {
+    pub(crate) fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl AsyncRead for SgStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SgStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+/// Parse a version 1 header, e.g. `PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n`.
+pub(crate) fn parse_v1(header: &[u8]) -> TardisResult<Option<SocketAddr>> {
+    let invalid = || TardisError::bad_request("[SG.ProxyProtocol] Header v1 is not legal", "");
+    let header = std::str::from_utf8(header).map_err(|_| invalid())?.strip_suffix("\r\n").ok_or_else(invalid)?;
+    let mut fields = header.split(' ');
+    if fields.next() != Some("PROXY") {
+        return Err(invalid());
+    }
+    match fields.next() {
+        Some("TCP4") | Some("TCP6") => {}
+        // The load balancer connects on its own behalf, or the protocol is not supported
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid()),
+    }
+    let fields = fields.collect::<Vec<_>>();
+    let [source_ip, _, source_port, _] = fields.as_slice() else {
+        return Err(invalid());
+    };
+    let source_ip = source_ip.parse::<IpAddr>().map_err(|_| invalid())?;
+    let source_port = source_port.parse::<u16>().map_err(|_| invalid())?;
+    Ok(Some(SocketAddr::new(source_ip, source_port)))
+}
+
+/// Parse a version 2 header, including the signature.
+pub(crate) fn parse_v2(header: &[u8]) -> TardisResult<Option<SocketAddr>> {
+    let invalid = || TardisError::bad_request("[SG.ProxyProtocol] Header v2 is not legal", "");
+    if header.len() < V2_HEADER_LEN || &header[..V2_SIGNATURE.len()] != V2_SIGNATURE {
+        return Err(invalid());
+    }
+    let addresses = &header[V2_HEADER_LEN..];
+    match header[12] {
+        V2_COMMAND_LOCAL => return Ok(None),
+        V2_COMMAND_PROXY => {}
+        _ => return Err(invalid()),
+    }
+    // Type-length-value vectors after the addresses are ignored
+    match header[13] {
+        V2_FAMILY_TCP4 => {
+            let addresses: &[u8; 12] = addresses.get(..12).and_then(|addresses| addresses.try_into().ok()).ok_or_else(invalid)?;
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), u16::from_be_bytes([addresses[8], addresses[9]]))))
+        }
+        V2_FAMILY_TCP6 => {
+            let addresses: &[u8; 36] = addresses.get(..36).and_then(|addresses| addresses.try_into().ok()).ok_or_else(invalid)?;
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16]).map_err(|_| invalid())?);
+            Ok(Some(SocketAddr::new(IpAddr::V6(ip), u16::from_be_bytes([addresses[32], addresses[33]]))))
+        }
+        // UDP, unix sockets or unspecified, the addresses are not usable
+        _ => Ok(None),
+    }
+}
+
+/// Build the header sent to a backend for a connection from `source` to `destination`,
+/// without addresses the header tells that the gateway connects on its own behalf (e.g. health checks).
+pub(crate) fn encode(version: SgProxyProtocolVersion, addresses: Option<(SocketAddr, SocketAddr)>) -> Vec<u8> {
+    // Both addresses must be of the same family
+    let addresses = addresses.map(|(source, destination)| match (source, destination) {
+        (SocketAddr::V4(_), SocketAddr::V6(_)) => (SocketAddr::new(to_ipv6(source.ip()), source.port()), destination),
+        (SocketAddr::V6(_), SocketAddr::V4(_)) => (source, SocketAddr::new(to_ipv6(destination.ip()), destination.port())),
+        _ => (source, destination),
+    });
+    match version {
+        SgProxyProtocolVersion::V1 => match addresses {
+            Some((source, destination)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if source.is_ipv4() { "TCP4" } else { "TCP6" },
+                source.ip(),
+                destination.ip(),
+                source.port(),
+                destination.port()
+            )
+            .into_bytes(),
+            None => b"PROXY UNKNOWN\r\n".to_vec(),
+        },
+        SgProxyProtocolVersion::V2 => {
+            let mut header = V2_SIGNATURE.to_vec();
+            match addresses {
+                Some((source, destination)) => {
+                    let mut addresses = Vec::with_capacity(36);
+                    let family = match (source.ip(), destination.ip()) {
+                        (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+                            addresses.extend_from_slice(&source_ip.octets());
+                            addresses.extend_from_slice(&destination_ip.octets());
+                            V2_FAMILY_TCP4
+                        }
+                        (source_ip, destination_ip) => {
+                            addresses.extend_from_slice(&to_ipv6_octets(source_ip));
+                            addresses.extend_from_slice(&to_ipv6_octets(destination_ip));
+                            V2_FAMILY_TCP6
+                        }
+                    };
+                    addresses.extend_from_slice(&source.port().to_be_bytes());
+                    addresses.extend_from_slice(&destination.port().to_be_bytes());
+                    header.extend_from_slice(&[V2_COMMAND_PROXY, family]);
+                    header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+                    header.extend_from_slice(&addresses);
+                }
+                None => header.extend_from_slice(&[V2_COMMAND_LOCAL, 0x00, 0x00, 0x00]),
+            }
+            header
+        }
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => IpAddr::V6(ip.to_ipv6_mapped()),
+        ip => ip,
+    }
+}
+
+fn to_ipv6_octets(ip: IpAddr) -> [u8; 16] {
+    match to_ipv6(ip) {
+        IpAddr::V6(ip) => ip.octets(),
+        IpAddr::V4(_) => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tardis::{basic::result::TardisResult, tokio};
+
+    use crate::config::gateway_dto::{SgProxyProtocolConfig, SgProxyProtocolVersion};
+
+    use super::{accept, encode, parse_v1, SgProxyProtocolInst};
+
+    #[tokio::test]
+    async fn test_accept() -> TardisResult<()> {
+        let peer_addr: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let source: SocketAddr = "192.168.0.1:56324".parse().unwrap();
+        let destination: SocketAddr = "192.168.0.11:443".parse().unwrap();
+        let proxy_protocol = SgProxyProtocolInst::new(&SgProxyProtocolConfig {
+            trusted_sources: vec!["10.0.0.0/8".to_string(), "::1".to_string()],
+        })?;
+        for version in [SgProxyProtocolVersion::V1, SgProxyProtocolVersion::V2] {
+            let mut stream = encode(version, Some((source, destination)));
+            stream.extend_from_slice(b"GET / HTTP/1.1\r\n");
+            let mut reader = stream.as_slice();
+            assert_eq!(accept(Some(&proxy_protocol), &mut reader, peer_addr).await?, source);
+            // The following bytes are left in the stream
+            assert_eq!(reader, b"GET / HTTP/1.1\r\n");
+
+            let stream = encode(version, None);
+            assert_eq!(accept(Some(&proxy_protocol), &mut stream.as_slice(), peer_addr).await?, peer_addr);
+
+            let ipv6_source: SocketAddr = "[2001:db8::1]:56324".parse().unwrap();
+            let stream = encode(version, Some((ipv6_source, destination)));
+            assert_eq!(accept(Some(&proxy_protocol), &mut stream.as_slice(), peer_addr).await?, ipv6_source);
+        }
+
+        // Untrusted peers are handled as direct connections
+        let untrusted_peer_addr: SocketAddr = "172.16.0.1:40000".parse().unwrap();
+        let mut reader = &b"GET / HTTP/1.1\r\n"[..];
+        assert_eq!(accept(Some(&proxy_protocol), &mut reader, untrusted_peer_addr).await?, untrusted_peer_addr);
+        assert_eq!(reader, b"GET / HTTP/1.1\r\n");
+        assert_eq!(accept(None, &mut &b"GET / HTTP/1.1\r\n"[..], peer_addr).await?, peer_addr);
+
+        // Trusted peers must send the header
+        assert!(accept(Some(&proxy_protocol), &mut &b"GET / HTTP/1.1\r\n"[..], peer_addr).await.is_err());
+        assert!(accept(Some(&proxy_protocol), &mut &b"PROXY TCP4 192.168.0.1\r\n"[..], peer_addr).await.is_err());
+        assert!(accept(Some(&proxy_protocol), &mut [&b"PROXY "[..], &[b'0'; 200]].concat().as_slice(), peer_addr).await.is_err());
+        assert!(proxy_protocol.is_trusted("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(SgProxyProtocolInst::new(&SgProxyProtocolConfig { trusted_sources: vec![] }).is_err());
+        assert!(SgProxyProtocolInst::new(&SgProxyProtocolConfig {
+            trusted_sources: vec!["10.0.0".to_string()]
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode() {
+        let source: SocketAddr = "192.168.0.1:56324".parse().unwrap();
+        let destination: SocketAddr = "[::1]:443".parse().unwrap();
+        assert_eq!(
+            encode(SgProxyProtocolVersion::V1, Some((source, destination))),
+            b"PROXY TCP6 ::ffff:192.168.0.1 ::1 56324 443\r\n"
+        );
+        assert_eq!(
+            parse_v1(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n").unwrap(),
+            Some("192.168.0.1:56324".parse().unwrap())
+        );
+        assert_eq!(encode(SgProxyProtocolVersion::V2, None), b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00");
+    }
+}
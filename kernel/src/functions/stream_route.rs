@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use tardis::{
+    basic::{error::TardisError, result::TardisResult},
+    log,
+    rand::{distributions::WeightedIndex, prelude::Distribution, thread_rng},
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream, UdpSocket},
+        sync::{mpsc, watch::Receiver, RwLock},
+        task::JoinHandle,
+        time::{sleep, timeout, Instant},
+    },
+};
+
+use crate::config::{
+    gateway_dto::{SgGateway, SgListener, SgProxyProtocolVersion},
+    stream_route_dto::SgStreamRoute,
+};
+use crate::instance::{SgStreamBackendInst, SgStreamRouteInst};
+
+use super::metrics::SgStreamMetrics;
+use super::proxy_protocol::{self, SgProxyProtocolInst};
+
+const TCP_BUFFER_SIZE: usize = 16 * 1024;
+const UDP_BUFFER_SIZE: usize = 64 * 1024;
+const DEFAULT_UDP_IDLE_TIMEOUT_MS: u64 = 60_000;
+/// Maximum number of datagrams of a client waiting to be sent to the backend, further ones are dropped.
+const UDP_QUEUE_SIZE: usize = 256;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct SgStreamGatewayInst {
+    routes: Vec<Arc<SgStreamRouteInst>>,
+    metrics: bool,
+}
+
+fn get_routes() -> &'static RwLock<HashMap<String, Arc<SgStreamGatewayInst>>> {
+    static ROUTES: OnceLock<RwLock<HashMap<String, Arc<SgStreamGatewayInst>>>> = OnceLock::new();
+    ROUTES.get_or_init(Default::default)
+}
+
+/// Initialize (or replace) the stream routes of the gateway, established connections keep their backend.
+pub async fn init(gateway_conf: &SgGateway, routes: Vec<SgStreamRoute>) -> TardisResult<()> {
+    let mut routes_write = get_routes().write().await;
+    let old_routes = routes_write.get(&gateway_conf.name).map(|gateway_inst| gateway_inst.routes.clone()).unwrap_or_default();
+    let route_insts = routes
+        .into_iter()
+        .map(|route| {
+            // Keep counting the connections of the route, so the connection limit holds across re-initializations
+            let connections = old_routes
+                .iter()
+                .find(|old_route| old_route.name == route.name && old_route.section_name == route.section_name && old_route.port == route.port)
+                .map(|old_route| old_route.connections.clone())
+                .unwrap_or_default();
+            Arc::new(SgStreamRouteInst {
+                name: route.name,
+                section_name: route.section_name,
+                port: route.port,
+                backends: route
+                    .backends
+                    .into_iter()
+                    .map(|backend| SgStreamBackendInst {
+                        name_or_host: backend.name_or_host,
+                        namespace: backend.namespace,
+                        port: backend.port,
+                        weight: backend.weight.unwrap_or(0),
+                        proxy_protocol: backend.proxy_protocol,
+                    })
+                    .collect(),
+                idle_timeout_ms: route.idle_timeout_ms,
+                max_connections: route.max_connections,
+                connections,
+            })
+        })
+        .collect();
+    routes_write.insert(
+        gateway_conf.name.clone(),
+        Arc::new(SgStreamGatewayInst {
+            routes: route_insts,
+            metrics: gateway_conf.parameters.metrics.is_some(),
+        }),
+    );
+    Ok(())
+}
+
+pub async fn remove(gateway_name: &str) -> TardisResult<()> {
+    get_routes().write().await.remove(gateway_name);
+    Ok(())
+}
+
+/// Get the first route attached to the listener and whether metrics are enabled.
+async fn get_route(gateway_name: &str, listener: &SgListener) -> TardisResult<(Arc<SgStreamRouteInst>, bool)> {
+    let routes_read = get_routes().read().await;
+    routes_read
+        .get(gateway_name)
+        .and_then(|gateway_inst| gateway_inst.routes.iter().find(|route| route.is_attached(listener)).map(|route| (route.clone(), gateway_inst.metrics)))
+        .ok_or_else(|| {
+            TardisError::not_found(
+                &format!(
+                    "[SG.Stream] No route attached to listener {}",
+                    listener.name.as_deref().unwrap_or(&listener.port.to_string())
+                ),
+                "",
+            )
+        })
+}
+
+/// Randomly choose a backend in proportion to its weight, backends are treated equally if all weights are `0`.
+fn choose_backend(backends: &[SgStreamBackendInst]) -> Option<&SgStreamBackendInst> {
+    let mut weights = backends.iter().map(|backend| backend.weight as u64).collect::<Vec<_>>();
+    if weights.iter().all(|weight| *weight == 0) {
+        weights.iter_mut().for_each(|weight| *weight = 1);
+    }
+    let index = WeightedIndex::new(&weights).ok()?.sample(&mut thread_rng());
+    backends.get(index)
+}
+
+/// A connection counted against the connection limit of the route until dropped.
+struct SgStreamConnection(Arc<SgStreamRouteInst>);
+
+impl SgStreamConnection {
+    fn acquire(route: Arc<SgStreamRouteInst>) -> Option<Self> {
+        let connections = route.connections.fetch_add(1, Ordering::Relaxed);
+        if route.max_connections.is_some_and(|max_connections| connections >= max_connections) {
+            route.connections.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(SgStreamConnection(route))
+    }
+}
+
+impl Drop for SgStreamConnection {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Backend chosen for a connection (or UDP session).
+struct SgStreamBackendAddr {
+    addr: SocketAddr,
+    proxy_protocol: Option<SgProxyProtocolVersion>,
+}
+
+/// Route a new connection (or UDP session) of the listener, returns the chosen backend and the metrics of the connection.
+async fn connect(gateway_name: &str, listener: &SgListener, protocol: &str) -> TardisResult<(SgStreamConnection, SgStreamBackendAddr, SgStreamMetrics)> {
+    let (route, metrics) = get_route(gateway_name, listener).await?;
+    let Some(connection) = SgStreamConnection::acquire(route.clone()) else {
+        SgStreamMetrics::reject(metrics, gateway_name, &route.name, protocol);
+        return Err(TardisError::custom("503", &format!("[SG.Stream] Route {} reached the connection limit", route.name), ""));
+    };
+    let backend = choose_backend(&route.backends).ok_or_else(|| TardisError::not_found(&format!("[SG.Stream] Route {} has no backend", route.name), ""))?;
+    let backend_addr = tokio::net::lookup_host((backend.host().as_str(), backend.port))
+        .await
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| TardisError::bad_gateway(&format!("[SG.Stream] Resolve backend {backend} failed"), ""))?;
+    let stream_metrics = SgStreamMetrics::new(metrics, gateway_name, &route.name, &backend.to_string(), protocol);
+    Ok((
+        connection,
+        SgStreamBackendAddr {
+            addr: backend_addr,
+            proxy_protocol: backend.proxy_protocol,
+        },
+        stream_metrics,
+    ))
+}
+
+/// Accept connections of a `tcp` listener until the gateway is shut down.
+pub(crate) async fn serve_tcp(
+    gateway_name: Arc<String>,
+    listener_conf: SgListener,
+    listener: TcpListener,
+    proxy_protocol: Option<SgProxyProtocolInst>,
+    mut shutdown_rx: Receiver<()>,
+) -> Result<(), hyper::Error> {
+    let proxy_protocol = proxy_protocol.map(Arc::new);
+    let listener_conf = Arc::new(listener_conf);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, remote_addr)) => {
+                    let gateway_name = gateway_name.clone();
+                    let listener_conf = listener_conf.clone();
+                    let proxy_protocol = proxy_protocol.clone();
+                    tokio::spawn(async move {
+                        if let Err(error) = process_tcp(&gateway_name, &listener_conf, stream, remote_addr, proxy_protocol.as_deref()).await {
+                            log::warn!("[SG.Stream] Tcp connection from {remote_addr} failed: {error}");
+                        }
+                    });
+                }
+                Err(error) => {
+                    // e.g. too many open files, wait for connections to be closed
+                    log::warn!("[SG.Stream] Accept failed: {error}");
+                    sleep(Duration::from_millis(100)).await;
+                }
+            },
+            _ = shutdown_rx.changed() => return Ok(()),
+        }
+    }
+}
+
+async fn process_tcp(
+    gateway_name: &str,
+    listener_conf: &SgListener,
+    mut client: TcpStream,
+    peer_addr: SocketAddr,
+    proxy_protocol: Option<&SgProxyProtocolInst>,
+) -> TardisResult<()> {
+    let remote_addr = proxy_protocol::accept(proxy_protocol, &mut client, peer_addr).await?;
+    let (connection, backend, metrics) = connect(gateway_name, listener_conf, "tcp").await?;
+    let backend_addr = backend.addr;
+    let mut upstream = timeout(CONNECT_TIMEOUT, TcpStream::connect(backend_addr))
+        .await
+        .map_err(|_| TardisError::timeout(&format!("[SG.Stream] Connect to {backend_addr} timeout"), ""))?
+        .map_err(|error| TardisError::bad_gateway(&format!("[SG.Stream] Connect to {backend_addr} failed: {error}"), ""))?;
+    if let Some(version) = backend.proxy_protocol {
+        upstream.write_all(&proxy_protocol::encode(version, Some((remote_addr, client.local_addr()?)))).await?;
+    }
+    let idle_timeout = connection.0.idle_timeout_ms.map(Duration::from_millis);
+    let (mut client_read, mut client_write) = client.into_split();
+    let (mut upstream_read, mut upstream_write) = upstream.into_split();
+    let mut client_buf = vec![0; TCP_BUFFER_SIZE];
+    let mut upstream_buf = vec![0; TCP_BUFFER_SIZE];
+    let (mut client_closed, mut upstream_closed) = (false, false);
+    let idle = sleep(idle_timeout.unwrap_or(Duration::MAX));
+    tokio::pin!(idle);
+    // Half closes are forwarded, the connection is finished when both sides are closed
+    while !client_closed || !upstream_closed {
+        tokio::select! {
+            read = client_read.read(&mut client_buf), if !client_closed => {
+                let len = read?;
+                if len == 0 {
+                    client_closed = true;
+                    upstream_write.shutdown().await?;
+                } else {
+                    upstream_write.write_all(&client_buf[..len]).await?;
+                    metrics.add_received_bytes(len);
+                }
+            }
+            read = upstream_read.read(&mut upstream_buf), if !upstream_closed => {
+                let len = read?;
+                if len == 0 {
+                    upstream_closed = true;
+                    client_write.shutdown().await?;
+                } else {
+                    client_write.write_all(&upstream_buf[..len]).await?;
+                    metrics.add_sent_bytes(len);
+                }
+            }
+            _ = &mut idle => {
+                log::debug!("[SG.Stream] Tcp connection to {backend_addr} closed after idle timeout");
+                return Ok(());
+            }
+        }
+        if let Some(idle_timeout) = idle_timeout {
+            idle.as_mut().reset(Instant::now() + idle_timeout);
+        }
+    }
+    Ok(())
+}
+
+/// UDP session of a client, identified by the client address.
+struct SgUdpSession {
+    id: u64,
+    /// Datagrams of the client waiting to be sent to the backend.
+    sender: mpsc::Sender<Vec<u8>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for SgUdpSession {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+type SgUdpSessions = Arc<Mutex<HashMap<SocketAddr, SgUdpSession>>>;
+
+/// Receive datagrams of an `udp` listener until the gateway is shut down.
+///
+/// Each client address gets its own socket towards the chosen backend,
+/// the responses of the backend are sent back to the client from the listener socket.
+pub(crate) async fn serve_udp(gateway_name: Arc<String>, listener_conf: SgListener, socket: UdpSocket, mut shutdown_rx: Receiver<()>) -> Result<(), hyper::Error> {
+    let socket = Arc::new(socket);
+    let listener_conf = Arc::new(listener_conf);
+    let sessions: SgUdpSessions = Default::default();
+    let mut buf = vec![0; UDP_BUFFER_SIZE];
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => match received {
+                Ok((len, client_addr)) => process_udp(&gateway_name, &listener_conf, &socket, &sessions, client_addr, &buf[..len]),
+                Err(error) => log::debug!("[SG.Stream] Udp receive failed: {error}"),
+            },
+            _ = shutdown_rx.changed() => {
+                sessions.lock().unwrap_or_else(|e| e.into_inner()).clear();
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Hand the datagram over to the session of the client, the session is created in the background
+/// so that resolving and connecting to the backend does not hold up the datagrams of other clients.
+fn process_udp(gateway_name: &Arc<String>, listener_conf: &Arc<SgListener>, socket: &Arc<UdpSocket>, sessions: &SgUdpSessions, client_addr: SocketAddr, datagram: &[u8]) {
+    static SESSION_ID: AtomicU64 = AtomicU64::new(0);
+    let mut sessions_write = sessions.lock().unwrap_or_else(|e| e.into_inner());
+    // Sessions closed in the meantime are replaced
+    if sessions_write.get(&client_addr).is_some_and(|session| session.sender.is_closed()) {
+        sessions_write.remove(&client_addr);
+    }
+    let session = sessions_write.entry(client_addr).or_insert_with(|| {
+        let id = SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(UDP_QUEUE_SIZE);
+        let task = tokio::spawn(run_udp_session(
+            id,
+            gateway_name.clone(),
+            listener_conf.clone(),
+            socket.clone(),
+            sessions.clone(),
+            client_addr,
+            receiver,
+        ));
+        SgUdpSession { id, sender, task }
+    });
+    if session.sender.try_send(datagram.to_vec()).is_err() {
+        log::debug!("[SG.Stream] Udp datagram from {client_addr} dropped, the session is busy");
+    }
+}
+
+async fn run_udp_session(
+    id: u64,
+    gateway_name: Arc<String>,
+    listener_conf: Arc<SgListener>,
+    socket: Arc<UdpSocket>,
+    sessions: SgUdpSessions,
+    client_addr: SocketAddr,
+    receiver: mpsc::Receiver<Vec<u8>>,
+) {
+    if let Err(error) = relay_udp_session(&gateway_name, &listener_conf, &socket, client_addr, receiver).await {
+        log::warn!("[SG.Stream] Udp session of {client_addr} failed: {error}");
+    }
+    let mut sessions_write = sessions.lock().unwrap_or_else(|e| e.into_inner());
+    // The client may already have a new session
+    if sessions_write.get(&client_addr).is_some_and(|session| session.id == id) {
+        sessions_write.remove(&client_addr);
+    }
+}
+
+async fn relay_udp_session(gateway_name: &str, listener_conf: &SgListener, socket: &UdpSocket, client_addr: SocketAddr, mut receiver: mpsc::Receiver<Vec<u8>>) -> TardisResult<()> {
+    let (connection, backend, metrics) = connect(gateway_name, listener_conf, "udp").await?;
+    let backend_addr = backend.addr;
+    let idle_timeout = Duration::from_millis(connection.0.idle_timeout_ms.unwrap_or(DEFAULT_UDP_IDLE_TIMEOUT_MS));
+    let upstream = UdpSocket::bind(if backend_addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }).await?;
+    upstream.connect(backend_addr).await?;
+    let mut buf = vec![0; UDP_BUFFER_SIZE];
+    let idle = sleep(idle_timeout);
+    tokio::pin!(idle);
+    loop {
+        tokio::select! {
+            datagram = receiver.recv() => {
+                let Some(datagram) = datagram else {
+                    return Ok(());
+                };
+                upstream.send(&datagram).await?;
+                metrics.add_received_bytes(datagram.len());
+            }
+            received = upstream.recv(&mut buf) => {
+                let len = received.map_err(|error| TardisError::bad_gateway(&format!("[SG.Stream] Udp receive from {backend_addr} failed: {error}"), ""))?;
+                socket.send_to(&buf[..len], client_addr).await?;
+                metrics.add_sent_bytes(len);
+            }
+            _ = &mut idle => {
+                log::debug!("[SG.Stream] Udp session of {client_addr} closed after idle timeout");
+                return Ok(());
+            }
+        }
+        idle.as_mut().reset(Instant::now() + idle_timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::config::gateway_dto::SgListener;
+    use crate::instance::{SgStreamBackendInst, SgStreamRouteInst};
+
+    use super::{choose_backend, SgStreamConnection};
+
+    fn new_backend(port: u16, weight: u16) -> SgStreamBackendInst {
+        SgStreamBackendInst {
+            name_or_host: "127.0.0.1".to_string(),
+            namespace: None,
+            port,
+            weight,
+            proxy_protocol: None,
+        }
+    }
+
+    fn new_route(section_name: Option<&str>, port: Option<u16>, max_connections: Option<u64>) -> Arc<SgStreamRouteInst> {
+        Arc::new(SgStreamRouteInst {
+            name: "test".to_string(),
+            section_name: section_name.map(|section_name| section_name.to_string()),
+            port,
+            backends: vec![],
+            idle_timeout_ms: None,
+            max_connections,
+            connections: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_choose_backend() {
+        assert!(choose_backend(&[]).is_none());
+        let backends = [new_backend(1, 0), new_backend(2, 3)];
+        for _ in 0..100 {
+            assert_eq!(choose_backend(&backends).unwrap().port, 2);
+        }
+        let backends = [new_backend(1, 0), new_backend(2, 0)];
+        let ports = (0..100).map(|_| choose_backend(&backends).unwrap().port).collect::<Vec<_>>();
+        assert!(ports.contains(&1) && ports.contains(&2));
+    }
+
+    #[test]
+    fn test_route_attached() {
+        let listener = SgListener {
+            name: Some("db".to_string()),
+            port: 5432,
+            ..Default::default()
+        };
+        assert!(new_route(None, None, None).is_attached(&listener));
+        assert!(new_route(Some("db"), None, None).is_attached(&listener));
+        assert!(new_route(Some("db"), Some(5432), None).is_attached(&listener));
+        assert!(!new_route(Some("db"), Some(6379), None).is_attached(&listener));
+        assert!(!new_route(Some("cache"), None, None).is_attached(&listener));
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let route = new_route(None, None, Some(2));
+        let first = SgStreamConnection::acquire(route.clone()).unwrap();
+        let _second = SgStreamConnection::acquire(route.clone()).unwrap();
+        assert!(SgStreamConnection::acquire(route.clone()).is_none());
+        drop(first);
+        assert!(SgStreamConnection::acquire(route.clone()).is_some());
+    }
+}
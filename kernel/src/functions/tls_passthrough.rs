@@ -0,0 +1,249 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tardis::{
+    basic::{error::TardisError, result::TardisResult},
+    log,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::watch::Receiver,
+        time::{sleep, timeout},
+    },
+};
+
+use super::http_route;
+use super::proxy_protocol::{self, SgProxyProtocolInst};
+
+/// Maximum size of the TLS records buffered until the ClientHello is complete.
+const MAX_CLIENT_HELLO_SIZE: usize = 64 * 1024;
+const CLIENT_HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 0x01;
+const EXTENSION_SERVER_NAME: usize = 0x00;
+const SERVER_NAME_TYPE_HOST_NAME: usize = 0x00;
+
+/// Result of parsing the first bytes of a TLS session.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SgClientHello {
+    /// More bytes are required.
+    Incomplete,
+    /// Not a TLS ClientHello.
+    Invalid,
+    /// The ClientHello is complete, with the server name (SNI) requested by the client if any.
+    Complete(Option<String>),
+}
+
+/// Accept connections of a passthrough listener until the gateway is shut down.
+///
+/// The TLS session is not terminated, the backend is chosen by the server name of the ClientHello
+/// and the bytes are copied in both directions as they are.
+pub(crate) async fn serve(
+    gateway_name: Arc<String>,
+    listener: TcpListener,
+    proxy_protocol: Option<SgProxyProtocolInst>,
+    mut shutdown_rx: Receiver<()>,
+) -> Result<(), hyper::Error> {
+    let proxy_protocol = proxy_protocol.map(Arc::new);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, remote_addr)) => {
+                    let gateway_name = gateway_name.clone();
+                    let proxy_protocol = proxy_protocol.clone();
+                    tokio::spawn(async move {
+                        if let Err(error) = process(&gateway_name, stream, remote_addr, proxy_protocol.as_deref()).await {
+                            log::warn!("[SG.Passthrough] Connection from {remote_addr} failed: {error}");
+                        }
+                    });
+                }
+                Err(error) => {
+                    // e.g. too many open files, wait for connections to be closed
+                    log::warn!("[SG.Passthrough] Accept failed: {error}");
+                    sleep(Duration::from_millis(100)).await;
+                }
+            },
+            _ = shutdown_rx.changed() => return Ok(()),
+        }
+    }
+}
+
+async fn process(gateway_name: &str, mut stream: TcpStream, peer_addr: SocketAddr, proxy_protocol: Option<&SgProxyProtocolInst>) -> TardisResult<()> {
+    let local_addr = stream.local_addr()?;
+    let remote_addr = proxy_protocol::accept(proxy_protocol, &mut stream, peer_addr).await?;
+    let mut buf = Vec::with_capacity(4096);
+    let server_name = timeout(CLIENT_HELLO_TIMEOUT, async {
+        loop {
+            match parse_client_hello(&buf) {
+                SgClientHello::Complete(server_name) => return Ok(server_name),
+                SgClientHello::Invalid => return Err(TardisError::bad_request("[SG.Passthrough] Not a TLS ClientHello", "")),
+                SgClientHello::Incomplete if buf.len() >= MAX_CLIENT_HELLO_SIZE => return Err(TardisError::bad_request("[SG.Passthrough] TLS ClientHello is too large", "")),
+                SgClientHello::Incomplete => {}
+            }
+            if stream.read_buf(&mut buf).await? == 0 {
+                return Err(TardisError::bad_request("[SG.Passthrough] Connection closed before TLS ClientHello", ""));
+            }
+        }
+    })
+    .await
+    .map_err(|_| TardisError::timeout("[SG.Passthrough] Wait TLS ClientHello timeout", ""))??;
+
+    let Some((host, port, backend_proxy_protocol)) = http_route::choose_passthrough_backend(gateway_name, server_name.as_deref(), local_addr.port(), remote_addr).await? else {
+        return Err(TardisError::not_found(
+            &format!("[SG.Passthrough] No backend matched server name {}", server_name.as_deref().unwrap_or("<none>")),
+            "",
+        ));
+    };
+    log::trace!(
+        "[SG.Passthrough] Connection from {remote_addr} with server name {} to {host}:{port} @ {gateway_name}",
+        server_name.as_deref().unwrap_or("<none>")
+    );
+    let mut upstream = timeout(CONNECT_TIMEOUT, TcpStream::connect((host.as_str(), port)))
+        .await
+        .map_err(|_| TardisError::timeout(&format!("[SG.Passthrough] Connect to {host}:{port} timeout"), ""))?
+        .map_err(|error| TardisError::bad_gateway(&format!("[SG.Passthrough] Connect to {host}:{port} failed: {error}"), ""))?;
+    if let Some(version) = backend_proxy_protocol {
+        upstream.write_all(&proxy_protocol::encode(version, Some((remote_addr, local_addr)))).await?;
+    }
+    upstream.write_all(&buf).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+    Ok(())
+}
+
+/// Parse the server name (SNI) from the ClientHello at the beginning of `buf`,
+/// the handshake message may be fragmented into multiple TLS records.
+pub(crate) fn parse_client_hello(buf: &[u8]) -> SgClientHello {
+    if buf.first().is_some_and(|content_type| *content_type != CONTENT_TYPE_HANDSHAKE) {
+        return SgClientHello::Invalid;
+    }
+    let mut handshake = Vec::new();
+    let mut records = buf;
+    while records.len() >= 5 {
+        let len = u16::from_be_bytes([records[3], records[4]]) as usize;
+        if records[0] != CONTENT_TYPE_HANDSHAKE || records[1] != 3 || len == 0 {
+            return SgClientHello::Invalid;
+        }
+        let Some(fragment) = records.get(5..5 + len) else {
+            break;
+        };
+        handshake.extend_from_slice(fragment);
+        records = &records[5 + len..];
+        if handshake.len() >= 4 {
+            if handshake[0] != HANDSHAKE_TYPE_CLIENT_HELLO {
+                return SgClientHello::Invalid;
+            }
+            let hello_len = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]) as usize;
+            if let Some(hello) = handshake.get(4..4 + hello_len) {
+                return parse_server_name(hello).map_or(SgClientHello::Invalid, SgClientHello::Complete);
+            }
+        }
+    }
+    SgClientHello::Incomplete
+}
+
+fn parse_server_name(hello: &[u8]) -> Option<Option<String>> {
+    let mut hello = Reader(hello);
+    // Legacy version and random
+    hello.take(2 + 32)?;
+    let session_id_len = hello.u8()?;
+    hello.take(session_id_len)?;
+    let cipher_suites_len = hello.u16()?;
+    hello.take(cipher_suites_len)?;
+    let compression_methods_len = hello.u8()?;
+    hello.take(compression_methods_len)?;
+    if hello.0.is_empty() {
+        // No extensions
+        return Some(None);
+    }
+    let extensions_len = hello.u16()?;
+    let mut extensions = Reader(hello.take(extensions_len)?);
+    while !extensions.0.is_empty() {
+        let extension_type = extensions.u16()?;
+        let extension_len = extensions.u16()?;
+        let mut extension = Reader(extensions.take(extension_len)?);
+        if extension_type != EXTENSION_SERVER_NAME {
+            continue;
+        }
+        let names_len = extension.u16()?;
+        let mut names = Reader(extension.take(names_len)?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let name_len = names.u16()?;
+            let name = names.take(name_len)?;
+            if name_type == SERVER_NAME_TYPE_HOST_NAME {
+                return std::str::from_utf8(name).ok().map(|name| Some(name.to_ascii_lowercase()));
+            }
+        }
+    }
+    Some(None)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<usize> {
+        self.take(1).map(|bytes| bytes[0] as usize)
+    }
+
+    fn u16(&mut self) -> Option<usize> {
+        self.take(2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+
+    use super::{parse_client_hello, SgClientHello};
+
+    fn client_hello(server_name: &str) -> Vec<u8> {
+        let config = ClientConfig::builder().with_safe_defaults().with_root_certificates(RootCertStore::empty()).with_no_client_auth();
+        let mut conn = ClientConnection::new(Arc::new(config), server_name.try_into().unwrap()).unwrap();
+        let mut buf = Vec::new();
+        conn.write_tls(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_parse_client_hello() {
+        let hello = client_hello("SG.idealworld.group");
+        assert_eq!(parse_client_hello(&hello), SgClientHello::Complete(Some("sg.idealworld.group".to_string())));
+        for len in [0, 3, 5, 40, hello.len() - 1] {
+            assert_eq!(parse_client_hello(&hello[..len]), SgClientHello::Incomplete);
+        }
+
+        // IP addresses are not sent as server name
+        assert_eq!(parse_client_hello(&client_hello("127.0.0.1")), SgClientHello::Complete(None));
+
+        // Handshake fragmented into two records
+        let handshake = &hello[5..];
+        let mut fragmented = Vec::new();
+        for fragment in [&handshake[..10], &handshake[10..]] {
+            fragmented.extend_from_slice(&[0x16, 0x03, 0x01]);
+            fragmented.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+            fragmented.extend_from_slice(fragment);
+        }
+        assert_eq!(parse_client_hello(&fragmented[..20]), SgClientHello::Incomplete);
+        assert_eq!(parse_client_hello(&fragmented), SgClientHello::Complete(Some("sg.idealworld.group".to_string())));
+
+        assert_eq!(parse_client_hello(b"GET / HTTP/1.1\r\n"), SgClientHello::Invalid);
+        let mut corrupted = hello.clone();
+        corrupted[5] = 0x02;
+        assert_eq!(parse_client_hello(&corrupted), SgClientHello::Invalid);
+    }
+}
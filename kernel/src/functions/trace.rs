@@ -0,0 +1,592 @@
+//! Trace context propagation and span export.
+//!
+//! The tracer of a gateway is registered by the `tracing` filter, every request processed by the gateway then gets a [SgTrace]
+//! holding the request (server) span, to which route matching, filters and the upstream call add their spans.
+//! Finished traces are exported in batches over OTLP/HTTP with JSON encoding.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use http::{HeaderMap, HeaderName, HeaderValue, Method};
+use hyper::Body;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tardis::log;
+use tardis::rand::{thread_rng, Rng};
+use tardis::tokio::{self, sync::mpsc};
+
+use super::http_client;
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+pub const B3: &str = "b3";
+pub const X_B3_TRACE_ID: &str = "x-b3-traceid";
+pub const X_B3_SPAN_ID: &str = "x-b3-spanid";
+pub const X_B3_PARENT_SPAN_ID: &str = "x-b3-parentspanid";
+pub const X_B3_SAMPLED: &str = "x-b3-sampled";
+
+lazy_static! {
+    static ref TRACERS: RwLock<HashMap<String, Arc<SgTracer>>> = RwLock::new(HashMap::new());
+}
+
+/// Format of the trace context in the request headers.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SgTracePropagation {
+    /// [W3C Trace Context](https://www.w3.org/TR/trace-context/), `traceparent` and `tracestate` headers.
+    W3c,
+    /// [B3](https://github.com/openzipkin/b3-propagation) multiple headers, `X-B3-TraceId`, `X-B3-SpanId` etc.
+    B3,
+    /// [B3](https://github.com/openzipkin/b3-propagation) single header, `b3`.
+    B3Single,
+}
+
+/// Exporter of finished spans, OTLP over HTTP with JSON encoding.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgOtlpExporter {
+    /// Traces endpoint of the collector, e.g. `http://localhost:4318/v1/traces`.
+    pub endpoint: String,
+    /// Extra headers sent to the collector, e.g. authentication.
+    pub headers: HashMap<String, String>,
+    pub timeout_ms: u64,
+    /// Spans are sent when the batch is full or the flush interval elapses.
+    pub batch_size: usize,
+    pub flush_interval_ms: u64,
+    /// Spans are dropped when the queue is full, e.g. the collector is unreachable.
+    pub max_queue_size: usize,
+}
+
+impl Default for SgOtlpExporter {
+    fn default() -> Self {
+        SgOtlpExporter {
+            endpoint: "http://localhost:4318/v1/traces".to_string(),
+            headers: HashMap::new(),
+            timeout_ms: 5000,
+            batch_size: 512,
+            flush_interval_ms: 1000,
+            max_queue_size: 4096,
+        }
+    }
+}
+
+/// Tracer of a gateway.
+#[derive(Debug)]
+pub struct SgTracer {
+    propagation: Vec<SgTracePropagation>,
+    sample_ratio: f64,
+    exporter: Option<mpsc::Sender<SgSpan>>,
+}
+
+impl SgTracer {
+    /// Create a tracer, the exporter task is started if an exporter is configured and stops once the tracer is dropped.
+    pub fn new(service_name: String, propagation: Vec<SgTracePropagation>, sample_ratio: f64, exporter: Option<SgOtlpExporter>) -> Self {
+        let exporter = exporter.map(|config| {
+            let (tx, rx) = mpsc::channel(config.max_queue_size.max(1));
+            tokio::spawn(export(service_name, config, rx));
+            tx
+        });
+        SgTracer {
+            propagation,
+            sample_ratio,
+            exporter,
+        }
+    }
+
+    /// Start the trace of a request, continuing the trace context of the request headers if any.
+    pub fn start(self: &Arc<Self>, method: &Method, url: &str, headers: &HeaderMap<HeaderValue>, peer: &str) -> SgTrace {
+        let parent = self.propagation.iter().find_map(|propagation| extract(*propagation, headers));
+        let sample = || thread_rng().gen::<f64>() < self.sample_ratio;
+        let (trace_id, parent_span_id, sampled, trace_state) = match parent {
+            // Defer the decision to the gateway if the sampling state is absent
+            Some(parent) => (parent.trace_id, Some(parent.span_id), parent.sampled.unwrap_or_else(sample), parent.trace_state),
+            None => (thread_rng().gen::<u128>().max(1), None, sample(), None),
+        };
+        SgTrace {
+            inner: Arc::new(SgTraceInner {
+                tracer: self.clone(),
+                trace_id,
+                span_id: new_span_id(),
+                parent_span_id,
+                sampled,
+                trace_state,
+                start_time: SystemTime::now(),
+                attributes: vec![
+                    ("http.method".to_string(), method.to_string()),
+                    ("http.url".to_string(), url.to_string()),
+                    ("net.peer.ip".to_string(), peer.to_string()),
+                ],
+                spans: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+}
+
+/// Register the tracer of the gateway, replacing the previous one.
+pub fn register(gateway_name: &str, tracer: SgTracer) {
+    TRACERS.write().unwrap_or_else(|e| e.into_inner()).insert(gateway_name.to_string(), Arc::new(tracer));
+}
+
+pub fn unregister(gateway_name: &str) {
+    TRACERS.write().unwrap_or_else(|e| e.into_inner()).remove(gateway_name);
+}
+
+pub fn get(gateway_name: &str) -> Option<Arc<SgTracer>> {
+    TRACERS.read().unwrap_or_else(|e| e.into_inner()).get(gateway_name).cloned()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgSpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+#[derive(Debug, Clone)]
+pub struct SgSpan {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    name: String,
+    kind: SgSpanKind,
+    start_time: SystemTime,
+    end_time: SystemTime,
+    attributes: Vec<(String, String)>,
+    error: bool,
+}
+
+impl SgSpan {
+    pub fn span_id(&self) -> u64 {
+        self.span_id
+    }
+
+    pub fn set_attribute(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.attributes.push((key.into(), value.into()));
+    }
+
+    pub fn set_error(&mut self) {
+        self.error = true;
+    }
+}
+
+/// Trace of a request, cloning shares the same trace.
+#[derive(Debug, Clone)]
+pub struct SgTrace {
+    inner: Arc<SgTraceInner>,
+}
+
+#[derive(Debug)]
+struct SgTraceInner {
+    tracer: Arc<SgTracer>,
+    trace_id: u128,
+    /// Id of the request span
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    sampled: bool,
+    trace_state: Option<String>,
+    start_time: SystemTime,
+    attributes: Vec<(String, String)>,
+    spans: Mutex<Vec<SgSpan>>,
+}
+
+impl SgTrace {
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.inner.trace_id)
+    }
+
+    pub fn span_id(&self) -> String {
+        format!("{:016x}", self.inner.span_id)
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.inner.sampled
+    }
+
+    /// Start a child span of the request span.
+    pub fn start_span(&self, name: impl Into<String>, kind: SgSpanKind) -> SgSpan {
+        let now = SystemTime::now();
+        SgSpan {
+            trace_id: self.inner.trace_id,
+            span_id: new_span_id(),
+            parent_span_id: Some(self.inner.span_id),
+            name: name.into(),
+            kind,
+            start_time: now,
+            end_time: now,
+            attributes: Vec::new(),
+            error: false,
+        }
+    }
+
+    pub fn end_span(&self, mut span: SgSpan) {
+        if self.inner.sampled {
+            span.end_time = SystemTime::now();
+            self.inner.spans.lock().unwrap_or_else(|e| e.into_inner()).push(span);
+        }
+    }
+
+    /// Write the trace context into the headers of a request sent on behalf of `span`.
+    pub fn inject(&self, span: &SgSpan, headers: &mut HeaderMap<HeaderValue>) {
+        let trace_id = format!("{:032x}", self.inner.trace_id);
+        let span_id = format!("{:016x}", span.span_id);
+        let parent_span_id = span.parent_span_id.map(|id| format!("{id:016x}"));
+        let sampled = if self.inner.sampled { "1" } else { "0" };
+        let mut insert = |name: &'static str, value: String| {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        };
+        for propagation in &self.inner.tracer.propagation {
+            match propagation {
+                SgTracePropagation::W3c => {
+                    insert(TRACEPARENT, format!("00-{trace_id}-{span_id}-0{sampled}"));
+                    if let Some(trace_state) = &self.inner.trace_state {
+                        insert(TRACESTATE, trace_state.clone());
+                    }
+                }
+                SgTracePropagation::B3 => {
+                    insert(X_B3_TRACE_ID, trace_id.clone());
+                    insert(X_B3_SPAN_ID, span_id.clone());
+                    if let Some(parent_span_id) = &parent_span_id {
+                        insert(X_B3_PARENT_SPAN_ID, parent_span_id.clone());
+                    }
+                    insert(X_B3_SAMPLED, sampled.to_string());
+                }
+                SgTracePropagation::B3Single => match &parent_span_id {
+                    Some(parent_span_id) => insert(B3, format!("{trace_id}-{span_id}-{sampled}-{parent_span_id}")),
+                    None => insert(B3, format!("{trace_id}-{span_id}-{sampled}")),
+                },
+            }
+        }
+    }
+
+    /// Finish the request span and export the trace.
+    pub fn finish(self, status_code: u16) {
+        if !self.inner.sampled {
+            return;
+        }
+        let Some(exporter) = &self.inner.tracer.exporter else {
+            return;
+        };
+        let mut attributes = self.inner.attributes.clone();
+        attributes.push(("http.status_code".to_string(), status_code.to_string()));
+        let request_span = SgSpan {
+            trace_id: self.inner.trace_id,
+            span_id: self.inner.span_id,
+            parent_span_id: self.inner.parent_span_id,
+            name: "request".to_string(),
+            kind: SgSpanKind::Server,
+            start_time: self.inner.start_time,
+            end_time: SystemTime::now(),
+            attributes,
+            error: status_code >= 500,
+        };
+        let spans = std::mem::take(&mut *self.inner.spans.lock().unwrap_or_else(|e| e.into_inner()));
+        for span in std::iter::once(request_span).chain(spans) {
+            if exporter.try_send(span).is_err() {
+                log::debug!("[SG.Trace] Export queue is full, span dropped");
+            }
+        }
+    }
+}
+
+fn new_span_id() -> u64 {
+    thread_rng().gen::<u64>().max(1)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct SgTraceParent {
+    trace_id: u128,
+    span_id: u64,
+    /// `None` if the parent leaves the sampling decision to the gateway.
+    sampled: Option<bool>,
+    trace_state: Option<String>,
+}
+
+fn extract(propagation: SgTracePropagation, headers: &HeaderMap<HeaderValue>) -> Option<SgTraceParent> {
+    let get = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+    match propagation {
+        SgTracePropagation::W3c => {
+            let mut parts = get(TRACEPARENT)?.split('-');
+            let (version, trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+            if version.len() != 2 || version == "ff" || trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 || (version == "00" && parts.next().is_some()) {
+                return None;
+            }
+            Some(SgTraceParent {
+                trace_id: parse_id(trace_id)?,
+                span_id: parse_id(span_id)? as u64,
+                sampled: Some(u8::from_str_radix(flags, 16).ok()? & 1 == 1),
+                trace_state: get(TRACESTATE).filter(|trace_state| !trace_state.is_empty()).map(str::to_string),
+            })
+        }
+        SgTracePropagation::B3 => Some(SgTraceParent {
+            trace_id: parse_b3_trace_id(get(X_B3_TRACE_ID)?)?,
+            span_id: parse_b3_span_id(get(X_B3_SPAN_ID)?)?,
+            sampled: if get("x-b3-flags") == Some("1") {
+                Some(true)
+            } else {
+                get(X_B3_SAMPLED).map(|sampled| sampled == "1" || sampled.eq_ignore_ascii_case("true"))
+            },
+            trace_state: None,
+        }),
+        SgTracePropagation::B3Single => {
+            let mut parts = get(B3)?.split('-');
+            let (trace_id, span_id) = (parts.next()?, parts.next()?);
+            Some(SgTraceParent {
+                trace_id: parse_b3_trace_id(trace_id)?,
+                span_id: parse_b3_span_id(span_id)?,
+                sampled: parts.next().map(|sampled| sampled == "1" || sampled == "d"),
+                trace_state: None,
+            })
+        }
+    }
+}
+
+/// Parse a lower case hex id, an id of all zeros is invalid.
+fn parse_id(id: &str) -> Option<u128> {
+    if !id.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+        return None;
+    }
+    u128::from_str_radix(id, 16).ok().filter(|id| *id != 0)
+}
+
+fn parse_b3_trace_id(trace_id: &str) -> Option<u128> {
+    if trace_id.len() != 16 && trace_id.len() != 32 {
+        return None;
+    }
+    parse_id(trace_id)
+}
+
+fn parse_b3_span_id(span_id: &str) -> Option<u64> {
+    if span_id.len() != 16 {
+        return None;
+    }
+    parse_id(span_id).map(|id| id as u64)
+}
+
+async fn export(service_name: String, config: SgOtlpExporter, mut rx: mpsc::Receiver<SgSpan>) {
+    let mut batch = Vec::with_capacity(config.batch_size);
+    let mut interval = tokio::time::interval(Duration::from_millis(config.flush_interval_ms.max(1)));
+    loop {
+        tokio::select! {
+            span = rx.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() >= config.batch_size {
+                        flush(&service_name, &config, &mut batch).await;
+                    }
+                }
+                None => {
+                    flush(&service_name, &config, &mut batch).await;
+                    break;
+                }
+            },
+            _ = interval.tick() => flush(&service_name, &config, &mut batch).await,
+        }
+    }
+}
+
+async fn flush(service_name: &str, config: &SgOtlpExporter, batch: &mut Vec<SgSpan>) {
+    if batch.is_empty() {
+        return;
+    }
+    let body = to_otlp_json(service_name, batch).to_string();
+    batch.clear();
+    let mut headers = HeaderMap::new();
+    headers.insert(http::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    for (name, value) in &config.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            headers.insert(name, value);
+        }
+    }
+    match http_client::raw_request(None, Method::POST, &config.endpoint, Body::from(body), &headers, Some(config.timeout_ms)).await {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => log::warn!("[SG.Trace] Export spans to {} failed with status {}", config.endpoint, response.status()),
+        Err(error) => log::warn!("[SG.Trace] Export spans to {} error: {}", config.endpoint, error.message),
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+/// Encode spans as an OTLP `ExportTraceServiceRequest` in JSON.
+///
+/// Reference: [OTLP/HTTP JSON Protobuf Encoding](https://opentelemetry.io/docs/specs/otlp/#json-protobuf-encoding)
+fn to_otlp_json(service_name: &str, spans: &[SgSpan]) -> Value {
+    let string_value = |key: &str, value: &str| json!({"key": key, "value": {"stringValue": value}});
+    let spans = spans
+        .iter()
+        .map(|span| {
+            let mut value = json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "spanId": format!("{:016x}", span.span_id),
+                "name": span.name,
+                "kind": span.kind as u8,
+                "startTimeUnixNano": unix_nanos(span.start_time),
+                "endTimeUnixNano": unix_nanos(span.end_time),
+                "attributes": span.attributes.iter().map(|(key, value)| string_value(key, value)).collect::<Vec<_>>(),
+                // STATUS_CODE_ERROR = 2
+                "status": if span.error { json!({"code": 2}) } else { json!({}) },
+            });
+            if let Some(parent_span_id) = span.parent_span_id {
+                value["parentSpanId"] = Value::String(format!("{parent_span_id:016x}"));
+            }
+            value
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "resourceSpans": [{
+            "resource": {"attributes": [string_value("service.name", service_name)]},
+            "scopeSpans": [{
+                "scope": {"name": crate::constants::DOMAIN_CODE},
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use http::{HeaderMap, HeaderValue, Method};
+    use tardis::tokio;
+
+    use super::{extract, to_otlp_json, SgSpanKind, SgTraceParent, SgTracePropagation, SgTracer};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap<HeaderValue> {
+        pairs.iter().map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value))).collect()
+    }
+
+    #[test]
+    fn test_extract() {
+        let parent = extract(
+            SgTracePropagation::W3c,
+            &headers(&[
+                ("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+                ("tracestate", "congo=t61rcWkgMzE"),
+            ]),
+        );
+        assert_eq!(
+            parent,
+            Some(SgTraceParent {
+                trace_id: 0x0af7651916cd43dd8448eb211c80319c,
+                span_id: 0xb7ad6b7169203331,
+                sampled: Some(true),
+                trace_state: Some("congo=t61rcWkgMzE".to_string()),
+            })
+        );
+        assert!(!extract(
+            SgTracePropagation::W3c,
+            &headers(&[("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00")])
+        )
+        .unwrap()
+        .sampled
+        .unwrap());
+        // Invalid ids and versions
+        assert!(extract(
+            SgTracePropagation::W3c,
+            &headers(&[("traceparent", "00-00000000000000000000000000000000-b7ad6b7169203331-01")])
+        )
+        .is_none());
+        assert!(extract(
+            SgTracePropagation::W3c,
+            &headers(&[("traceparent", "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")])
+        )
+        .is_none());
+        assert!(extract(
+            SgTracePropagation::W3c,
+            &headers(&[("traceparent", "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01")])
+        )
+        .is_none());
+
+        let parent = extract(
+            SgTracePropagation::B3,
+            &headers(&[("x-b3-traceid", "463ac35c9f6413ad"), ("x-b3-spanid", "a2fb4a1d1a96d312"), ("x-b3-sampled", "0")]),
+        )
+        .unwrap();
+        assert_eq!(parent.trace_id, 0x463ac35c9f6413ad);
+        assert_eq!(parent.span_id, 0xa2fb4a1d1a96d312);
+        assert_eq!(parent.sampled, Some(false));
+        let parent = extract(
+            SgTracePropagation::B3,
+            &headers(&[("x-b3-traceid", "463ac35c9f6413ad"), ("x-b3-spanid", "a2fb4a1d1a96d312")]),
+        )
+        .unwrap();
+        assert_eq!(parent.sampled, None);
+
+        let parent = extract(
+            SgTracePropagation::B3Single,
+            &headers(&[("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1-05e3ac9a4f6e3b90")]),
+        )
+        .unwrap();
+        assert_eq!(parent.trace_id, 0x80f198ee56343ba864fe8b2a57d3eff7);
+        assert_eq!(parent.span_id, 0xe457b5a2e4d86bd1);
+        assert_eq!(parent.sampled, Some(true));
+        assert!(extract(SgTracePropagation::B3Single, &headers(&[("b3", "0")])).is_none());
+        let parent = extract(SgTracePropagation::B3Single, &headers(&[("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1")])).unwrap();
+        assert_eq!(parent.sampled, None);
+    }
+
+    #[tokio::test]
+    async fn test_trace() {
+        let tracer = Arc::new(SgTracer::new("sg".to_string(), vec![SgTracePropagation::W3c, SgTracePropagation::B3], 1.0, None));
+        let trace = tracer.start(
+            &Method::GET,
+            "http://sg.idealworld.group/iam",
+            &headers(&[("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")]),
+            "127.0.0.1",
+        );
+        assert_eq!(trace.trace_id(), "0af7651916cd43dd8448eb211c80319c");
+        assert_ne!(trace.span_id(), "b7ad6b7169203331");
+
+        let span = trace.start_span("upstream", SgSpanKind::Client);
+        let mut upstream_headers = HeaderMap::new();
+        trace.inject(&span, &mut upstream_headers);
+        assert_eq!(
+            upstream_headers.get("traceparent").unwrap(),
+            &format!("00-0af7651916cd43dd8448eb211c80319c-{:016x}-01", span.span_id())
+        );
+        assert_eq!(upstream_headers.get("x-b3-traceid").unwrap(), "0af7651916cd43dd8448eb211c80319c");
+        assert_eq!(upstream_headers.get("x-b3-parentspanid").unwrap(), &trace.span_id());
+        trace.end_span(span);
+        assert_eq!(trace.inner.spans.lock().unwrap().len(), 1);
+
+        // Without a parent, the sample ratio decides
+        let tracer = Arc::new(SgTracer::new("sg".to_string(), vec![SgTracePropagation::W3c], 0.0, None));
+        let trace = tracer.start(&Method::GET, "http://sg.idealworld.group/iam", &HeaderMap::new(), "127.0.0.1");
+        assert!(!trace.is_sampled());
+        // A B3 parent without the sampling state leaves the decision to the sample ratio
+        let tracer = Arc::new(SgTracer::new("sg".to_string(), vec![SgTracePropagation::B3], 0.0, None));
+        let trace = tracer.start(
+            &Method::GET,
+            "http://sg.idealworld.group/iam",
+            &headers(&[("x-b3-traceid", "463ac35c9f6413ad"), ("x-b3-spanid", "a2fb4a1d1a96d312")]),
+            "127.0.0.1",
+        );
+        assert_eq!(trace.trace_id(), "0000000000000000463ac35c9f6413ad");
+        assert!(!trace.is_sampled());
+        trace.end_span(trace.start_span("route", SgSpanKind::Internal));
+        assert!(trace.inner.spans.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_to_otlp_json() {
+        let tracer = Arc::new(SgTracer::new("sg".to_string(), vec![SgTracePropagation::W3c], 1.0, None));
+        let trace = tracer.start(&Method::GET, "http://sg.idealworld.group/iam", &HeaderMap::new(), "127.0.0.1");
+        let mut span = trace.start_span("upstream", SgSpanKind::Client);
+        span.set_attribute("http.status_code", "502");
+        span.set_error();
+        let json = to_otlp_json("sg", &[span.clone()]);
+        assert_eq!(json["resourceSpans"][0]["resource"]["attributes"][0]["value"]["stringValue"], "sg");
+        let exported = &json["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(exported["traceId"], trace.trace_id());
+        assert_eq!(exported["spanId"], format!("{:016x}", span.span_id()));
+        assert_eq!(exported["parentSpanId"], trace.span_id());
+        assert_eq!(exported["kind"], 3);
+        assert_eq!(exported["status"]["code"], 2);
+        assert_eq!(exported["attributes"][0]["key"], "http.status_code");
+    }
+}
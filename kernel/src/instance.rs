@@ -1,8 +1,12 @@
 use crate::{
     config::{
-        gateway_dto::{SgListener, SgProtocol},
-        http_route_dto::{SgHttpHeaderMatchType, SgHttpPathMatchType, SgHttpQueryMatchType},
+        gateway_dto::{SgListener, SgProtocol, SgProxyProtocolVersion},
+        http_route_dto::{
+            SgBackendHttpVersion, SgBackendTlsConfig, SgGrpcMethodMatchType, SgHealthPanicMode, SgHttpHeaderMatchType, SgHttpPathMatchType, SgHttpQueryMatchType,
+            SgOutlierDetection,
+        },
     },
+    functions::{access_log::SgAccessLogger, health_check::SgBackendHealth, load_balancer::SgLoadBalancer, outlier_detection::SgBackendOutlier, trace::SgTracer},
     plugins::filters::BoxSgPluginFilter,
 };
 
@@ -10,18 +14,30 @@ use http::Method;
 use hyper::{client::HttpConnector, Client};
 use hyper_rustls::HttpsConnector;
 
-use std::{fmt, vec::Vec};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    vec::Vec,
+};
 use tardis::regex::Regex;
 
 pub(crate) struct SgGatewayInst {
     pub filters: Vec<(String, BoxSgPluginFilter)>,
     pub routes: Vec<SgHttpRouteInst>,
     pub client: Client<HttpsConnector<HttpConnector>>,
+    pub ignore_tls_verification: bool,
+    pub metrics: bool,
+    pub tracer: Option<Arc<SgTracer>>,
+    pub access_log: Option<Arc<SgAccessLogger>>,
     pub listeners: Vec<SgListener>,
 }
 
 #[derive(Default)]
 pub struct SgHttpRouteInst {
+    pub name: String,
     pub hostnames: Option<Vec<String>>,
     pub filters: Vec<(String, BoxSgPluginFilter)>,
     pub rules: Option<Vec<SgHttpRouteRuleInst>>,
@@ -49,10 +65,14 @@ impl fmt::Display for SgHttpRouteInst {
 
 #[derive(Default)]
 pub struct SgHttpRouteRuleInst {
+    pub name: String,
     pub filters: Vec<(String, BoxSgPluginFilter)>,
     pub matches: Option<Vec<SgHttpRouteMatchInst>>,
     pub backends: Option<Vec<SgBackendInst>>,
     pub timeout_ms: Option<u64>,
+    pub load_balancer: SgLoadBalancer,
+    pub panic_mode: SgHealthPanicMode,
+    pub outlier_detection: Option<SgOutlierDetection>,
 }
 
 impl fmt::Display for SgHttpRouteRuleInst {
@@ -81,6 +101,7 @@ pub struct SgHttpRouteMatchInst {
     pub query: Option<Vec<SgHttpQueryMatchInst>>,
     // here method should be Method
     pub method: Option<Vec<Method>>,
+    pub grpc: Option<SgGrpcMethodMatchInst>,
 }
 
 impl fmt::Display for SgHttpRouteMatchInst {
@@ -101,7 +122,8 @@ impl fmt::Display for SgHttpRouteMatchInst {
         } else {
             "".to_string()
         };
-        write!(f, "{}", format!("{} {} {} {}", path, header, query, method).trim())
+        let grpc = if let Some(grpc) = &self.grpc { format!("grpc:{}", grpc) } else { "".to_string() };
+        write!(f, "{}", format!("{} {} {} {} {}", path, header, query, method, grpc).trim())
     }
 }
 #[derive(Default, Debug, Clone)]
@@ -155,6 +177,21 @@ impl fmt::Display for SgHttpQueryMatchInst {
     }
 }
 
+#[derive(Default, Debug, Clone)]
+pub struct SgGrpcMethodMatchInst {
+    pub kind: SgGrpcMethodMatchType,
+    pub service: Option<String>,
+    pub method: Option<String>,
+    pub service_regular: Option<Regex>,
+    pub method_regular: Option<Regex>,
+}
+
+impl fmt::Display for SgGrpcMethodMatchInst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} /{}/{}", self.kind, self.service.as_deref().unwrap_or("*"), self.method.as_deref().unwrap_or("*"))
+    }
+}
+
 #[derive(Default)]
 pub struct SgBackendInst {
     pub name_or_host: String,
@@ -162,8 +199,40 @@ pub struct SgBackendInst {
     pub port: u16,
     pub timeout_ms: Option<u64>,
     pub protocol: Option<SgProtocol>,
+    pub http_version: Option<SgBackendHttpVersion>,
+    pub tls: Option<Arc<SgBackendTlsConfig>>,
+    pub proxy_protocol: Option<SgProxyProtocolVersion>,
     pub weight: Option<u16>,
     pub filters: Vec<(String, BoxSgPluginFilter)>,
+    /// Number of requests currently being processed by this backend.
+    pub in_flight: Arc<AtomicU64>,
+    pub health: Arc<SgBackendHealth>,
+    pub outlier: SgBackendOutlier,
+}
+
+impl SgBackendInst {
+    /// Whether the backend can be chosen, i.e. it is healthy and not ejected by outlier detection.
+    pub fn is_available(&self) -> bool {
+        self.health.is_healthy() && !self.outlier.is_ejected()
+    }
+
+    pub fn get_in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Count the request as in-flight until the returned guard is dropped.
+    pub fn begin_request(&self) -> SgBackendRequestGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        SgBackendRequestGuard(self.in_flight.clone())
+    }
+}
+
+pub struct SgBackendRequestGuard(Arc<AtomicU64>);
+
+impl Drop for SgBackendRequestGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl fmt::Display for SgBackendInst {
@@ -179,3 +248,42 @@ impl fmt::Display for SgBackendInst {
         write!(f, "weight({}){timeout_ms}->{url}", self.weight.as_ref().unwrap_or(&0),)
     }
 }
+
+/// Instance of [SgStreamRoute](crate::config::stream_route_dto::SgStreamRoute).
+pub struct SgStreamRouteInst {
+    pub name: String,
+    pub section_name: Option<String>,
+    pub port: Option<u16>,
+    pub backends: Vec<SgStreamBackendInst>,
+    pub idle_timeout_ms: Option<u64>,
+    pub max_connections: Option<u64>,
+    /// Number of connections (or UDP sessions) being forwarded, kept when the routes of the gateway are re-initialized.
+    pub connections: Arc<AtomicU64>,
+}
+
+impl SgStreamRouteInst {
+    /// Whether the route is attached to the listener.
+    pub fn is_attached(&self, listener: &SgListener) -> bool {
+        self.section_name.iter().all(|section_name| listener.name.as_ref() == Some(section_name)) && self.port.iter().all(|port| listener.port == *port)
+    }
+}
+
+pub struct SgStreamBackendInst {
+    pub name_or_host: String,
+    pub namespace: Option<String>,
+    pub port: u16,
+    pub weight: u16,
+    pub proxy_protocol: Option<SgProxyProtocolVersion>,
+}
+
+impl SgStreamBackendInst {
+    pub fn host(&self) -> String {
+        format!("{}{}", self.name_or_host, self.namespace.as_ref().map(|n| format!(".{n}")).unwrap_or("".to_string()))
+    }
+}
+
+impl fmt::Display for SgStreamBackendInst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host(), self.port)
+    }
+}
@@ -20,8 +20,8 @@
 //! logs at most.
 
 #![warn(clippy::unwrap_used)]
-use config::{gateway_dto::SgGateway, http_route_dto::SgHttpRoute};
-use functions::{http_route, server};
+use config::{gateway_dto::SgGateway, http_route_dto::SgHttpRoute, stream_route_dto::SgStreamRoute};
+use functions::{http_route, server, stream_route};
 pub use http;
 pub use hyper;
 use plugins::filters::{self, SgPluginFilterDef};
@@ -52,13 +52,19 @@ pub async fn startup_simplify(conf_path: String, check_interval_sec: u64) -> Tar
 pub async fn startup(k8s_mode: bool, namespace_or_conf_uri: Option<String>, check_interval_sec: Option<u64>) -> TardisResult<()> {
     // Initialize configuration according to different modes
     let configs = config::init(k8s_mode, namespace_or_conf_uri, check_interval_sec).await?;
-    for (gateway, http_routes) in configs {
-        do_startup(gateway, http_routes).await?;
+    for (gateway, http_routes, stream_routes) in configs {
+        do_startup_with_stream_routes(gateway, http_routes, stream_routes).await?;
     }
     Ok(())
 }
 
+#[inline]
 pub async fn do_startup(gateway: SgGateway, http_routes: Vec<SgHttpRoute>) -> TardisResult<()> {
+    do_startup_with_stream_routes(gateway, http_routes, Vec::new()).await
+}
+
+/// Start the gateway, with routes of `tcp` and `udp` listeners.
+pub async fn do_startup_with_stream_routes(gateway: SgGateway, http_routes: Vec<SgHttpRoute>, stream_routes: Vec<SgStreamRoute>) -> TardisResult<()> {
     // Initialize service instances
     let server_insts = server::init(&gateway).await?;
     let gateway_name = &gateway.name.clone();
@@ -70,20 +76,44 @@ pub async fn do_startup(gateway: SgGateway, http_routes: Vec<SgHttpRoute>) -> Ta
             functions::cache_client::init(gateway_name, url).await?;
         }
     }
+    // Initialize metrics listener
+    if let Some(metrics) = &gateway.parameters.metrics {
+        functions::metrics::init(gateway_name, metrics).await?;
+    }
     // Initialize route instances
+    stream_route::init(&gateway, stream_routes).await?;
     http_route::init(gateway, http_routes).await?;
     // Start service instances
     server::startup(gateway_name, server_insts).await
 }
 
+/// Apply the changed configuration of a running gateway.
+///
+/// If only TLS certificates changed they are swapped in the running listeners without dropping connections,
+/// otherwise the gateway is restarted.
+pub async fn do_reload(gateway: SgGateway, http_routes: Vec<SgHttpRoute>, stream_routes: Vec<SgStreamRoute>) -> TardisResult<()> {
+    if server::reload_tls(&gateway).await? {
+        stream_route::init(&gateway, stream_routes).await?;
+        // Destroy the filters of the replaced routes before initializing the new ones
+        http_route::remove(&gateway.name).await?;
+        http_route::init(gateway, http_routes).await
+    } else {
+        shutdown(&gateway.name).await?;
+        do_startup_with_stream_routes(gateway, http_routes, stream_routes).await
+    }
+}
+
 pub async fn shutdown(gateway_name: &str) -> TardisResult<()> {
     // Remove route instances
     http_route::remove(gateway_name).await?;
+    stream_route::remove(gateway_name).await?;
     #[cfg(feature = "cache")]
     {
         // Remove cache instances
         functions::cache_client::remove(gateway_name).await?;
     }
+    // Remove metrics listener
+    functions::metrics::remove(gateway_name).await?;
     // Shutdown service instances
     server::shutdown(gateway_name).await
 }
@@ -1,15 +1,24 @@
 use http::{HeaderMap, HeaderName, HeaderValue, Method, Response, StatusCode, Uri, Version};
+use hyper::body::{Bytes, HttpBody, SizeHint};
 use hyper::Body;
 use serde::{Deserialize, Serialize};
+use std::any::Any;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 use tardis::basic::error::TardisError;
 use tardis::basic::result::TardisResult;
 
 use tardis::TardisFuns;
 
-use crate::config::gateway_dto::SgProtocol;
+use crate::config::gateway_dto::{SgProtocol, SgProxyProtocolVersion};
+use crate::config::http_route_dto::{SgBackendHttpVersion, SgBackendTlsConfig};
+use crate::functions::grpc;
+use crate::functions::trace::SgTrace;
 
 use crate::instance::{SgBackendInst, SgHttpRouteMatchInst, SgHttpRouteRuleInst};
 
@@ -43,6 +52,9 @@ pub struct AvailableBackendInst {
     pub port: u16,
     pub timeout_ms: Option<u64>,
     pub protocol: Option<SgProtocol>,
+    pub http_version: Option<SgBackendHttpVersion>,
+    pub tls: Option<Arc<SgBackendTlsConfig>>,
+    pub proxy_protocol: Option<SgProxyProtocolVersion>,
     pub weight: Option<u16>,
 }
 
@@ -54,6 +66,9 @@ impl AvailableBackendInst {
             port: value.port,
             timeout_ms: value.timeout_ms,
             protocol: value.protocol.clone(),
+            http_version: value.http_version.clone(),
+            tls: value.tls.clone(),
+            proxy_protocol: value.proxy_protocol,
             weight: value.weight,
         }
     }
@@ -456,6 +471,21 @@ pub struct SGRoleInfo {
     pub name: Option<String>,
 }
 
+/// Verified client certificate of a mutual TLS connection.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SgClientCertInfo {
+    /// Subject distinguished name, e.g. `O=idealworld, CN=client`.
+    pub subject: String,
+    /// Issuer distinguished name.
+    pub issuer: String,
+    /// Subject alternative names, DNS names, IP addresses, emails and URIs.
+    pub sans: Vec<String>,
+    /// Hex encoded serial number.
+    pub serial: String,
+    /// Lowercase hex encoded SHA-256 of the DER encoded certificate.
+    pub fingerprint_sha256: String,
+}
+
 /// The SgRoutePluginContext struct is used for communication within Spacegate.
 /// It serves as the core of the plugin system and represents a single request.
 /// When a request enters Spacegate, the information is encapsulated into a context,
@@ -484,8 +514,71 @@ pub struct SgRoutePluginContext {
 
     /// Describe user information
     ident_info: Option<SGIdentInfo>,
+    /// Client certificate, only present on mutual TLS connections.
+    client_cert: Option<SgClientCertInfo>,
     action: SgRouteFilterRequestAction,
     gateway_name: String,
+    /// Trace of the request, only present if tracing is enabled for the gateway.
+    trace: Option<SgTrace>,
+    /// Time taken by the backend to respond, only present once the request has been sent.
+    upstream_latency: Option<Duration>,
+    /// Values kept alive until the request completes, see [SgRoutePluginContext::add_guard].
+    guards: Vec<Arc<dyn Any + Send + Sync>>,
+}
+
+/// Response body keeping the guards of the request alive, they are dropped at the end of the stream or when the body is dropped,
+/// e.g. the client went away.
+#[derive(Default)]
+pub struct SgGuardedBody {
+    body: Body,
+    guards: Vec<Arc<dyn Any + Send + Sync>>,
+}
+
+impl SgGuardedBody {
+    fn new(body: Body, guards: Vec<Arc<dyn Any + Send + Sync>>) -> Self {
+        Self { body, guards }
+    }
+}
+
+impl From<Body> for SgGuardedBody {
+    fn from(body: Body) -> Self {
+        Self::new(body, Vec::new())
+    }
+}
+
+impl std::fmt::Debug for SgGuardedBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.body.fmt(f)
+    }
+}
+
+impl HttpBody for SgGuardedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = Pin::new(&mut self.body).poll_data(cx);
+        if matches!(data, Poll::Ready(None | Some(Err(_)))) {
+            self.guards.clear();
+        }
+        data
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = Pin::new(&mut self.body).poll_trailers(cx);
+        if trailers.is_ready() {
+            self.guards.clear();
+        }
+        trailers
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
 }
 
 #[allow(dead_code)]
@@ -501,6 +594,15 @@ impl SgRoutePluginContext {
         chose_route_rule: Option<ChosenHttpRouteRuleInst>,
         chose_backend: Option<AvailableBackendInst>,
     ) -> Self {
+        // The content type is chosen by the client, so it only makes a gRPC request where gRPC is expected,
+        // otherwise filters not accepting gRPC could be skipped by any client.
+        let grpc_expected = chose_route_rule.as_ref().and_then(|rule| rule.matched_match.as_ref()).is_some_and(|matched_match| matched_match.grpc.is_some())
+            || chose_backend.as_ref().is_some_and(|backend| matches!(backend.http_version, Some(SgBackendHttpVersion::H2c | SgBackendHttpVersion::Alpn)));
+        let request_kind = if grpc_expected && grpc::is_grpc_request(&headers) {
+            SgPluginFilterKind::Grpc
+        } else {
+            SgPluginFilterKind::Http
+        };
         Self {
             request_id: TardisFuns::field.nanoid(),
             request: SgCtxRequest::new(method, uri, version, headers, body, remote_addr),
@@ -510,8 +612,12 @@ impl SgRoutePluginContext {
             gateway_name,
             chosen_route_rule: chose_route_rule,
             chosen_backend: chose_backend,
-            request_kind: SgPluginFilterKind::Http,
+            request_kind,
             ident_info: None,
+            client_cert: None,
+            trace: None,
+            upstream_latency: None,
+            guards: Vec::new(),
         }
     }
 
@@ -535,6 +641,10 @@ impl SgRoutePluginContext {
             chosen_backend: None,
             request_kind: SgPluginFilterKind::Ws,
             ident_info: None,
+            client_cert: None,
+            trace: None,
+            upstream_latency: None,
+            guards: Vec::new(),
         }
     }
 
@@ -566,7 +676,9 @@ impl SgRoutePluginContext {
     }
 
     /// build response from Context
-    pub async fn build_response(&mut self) -> TardisResult<Response<Body>> {
+    ///
+    /// The guards of the context are kept alive until the response body is sent.
+    pub async fn build_response(&mut self) -> TardisResult<Response<SgGuardedBody>> {
         if let Some(err) = &self.response.resp_err {
             return Err(err.clone());
         }
@@ -577,10 +689,8 @@ impl SgRoutePluginContext {
                 v.to_str().map_err(|_| TardisError::bad_request(&format!("[SG.Route] header {k}'s value illegal: is not ascii"), ""))?.to_string(),
             );
         }
-        let resp = resp
-            .status(self.response.get_status_code())
-            .body(self.response.take_body())
-            .map_err(|error| TardisError::internal_error(&format!("[SG.Route] Build response error:{error}"), ""))?;
+        let body = SgGuardedBody::new(self.response.take_body(), std::mem::take(&mut self.guards));
+        let resp = resp.status(self.response.get_status_code()).body(body).map_err(|error| TardisError::internal_error(&format!("[SG.Route] Build response error:{error}"), ""))?;
         Ok(resp)
     }
 
@@ -655,12 +765,102 @@ impl SgRoutePluginContext {
         self.ident_info = Some(cert_info);
     }
 
+    pub fn get_client_cert(&self) -> Option<&SgClientCertInfo> {
+        self.client_cert.as_ref()
+    }
+
+    pub fn set_client_cert(&mut self, client_cert: SgClientCertInfo) {
+        self.client_cert = Some(client_cert);
+    }
+
+    pub fn get_trace(&self) -> Option<&SgTrace> {
+        self.trace.as_ref()
+    }
+
+    pub fn set_trace(&mut self, trace: SgTrace) {
+        self.trace = Some(trace);
+    }
+
     pub fn get_remote_addr(&self) -> SocketAddr {
         self.request.remote_addr
     }
 
+    pub fn get_upstream_latency(&self) -> Option<Duration> {
+        self.upstream_latency
+    }
+
+    pub fn set_upstream_latency(&mut self, upstream_latency: Duration) {
+        self.upstream_latency = Some(upstream_latency);
+    }
+
+    /// Keep `guard` alive until the request completes whether it succeeded or not,
+    /// i.e. until the context is dropped or, once the response is built, until the response body is sent.
+    pub fn add_guard(&mut self, guard: impl Any + Send + Sync) {
+        self.guards.push(Arc::new(guard));
+    }
+
     #[cfg(feature = "cache")]
     pub async fn cache(&self) -> TardisResult<std::sync::Arc<tardis::cache::cache_client::TardisCacheClient>> {
         crate::functions::cache_client::get(&self.gateway_name).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, Method, StatusCode, Uri, Version};
+    use hyper::body::HttpBody;
+    use hyper::Body;
+    use tardis::tokio;
+
+    use super::SgRoutePluginContext;
+    use crate::instance::SgBackendInst;
+
+    #[tokio::test]
+    async fn test_build_response_holds_guards() {
+        let backend = SgBackendInst::default();
+        let mut ctx = SgRoutePluginContext::new_http(
+            Method::GET,
+            Uri::from_static("http://sg.idealworld.group/iam"),
+            Version::HTTP_11,
+            HeaderMap::new(),
+            Body::empty(),
+            "127.0.0.1:8080".parse().unwrap(),
+            "".to_string(),
+            None,
+            None,
+        );
+        ctx.add_guard(backend.begin_request());
+        let (mut sender, body) = Body::channel();
+        let mut ctx = ctx.resp(StatusCode::OK, HeaderMap::new(), body);
+        let resp = ctx.build_response().await.unwrap();
+        drop(ctx);
+        // The request is in flight until the streamed body is sent
+        assert_eq!(backend.get_in_flight(), 1);
+        sender.send_data("iam".into()).await.unwrap();
+        let mut body = resp.into_body();
+        assert_eq!(body.data().await.unwrap().unwrap(), "iam");
+        assert_eq!(backend.get_in_flight(), 1);
+        drop(sender);
+        assert!(body.data().await.is_none());
+        assert_eq!(backend.get_in_flight(), 0);
+
+        // Dropped before the end of the stream, e.g. the client went away
+        let mut ctx = SgRoutePluginContext::new_http(
+            Method::GET,
+            Uri::from_static("http://sg.idealworld.group/iam"),
+            Version::HTTP_11,
+            HeaderMap::new(),
+            Body::empty(),
+            "127.0.0.1:8080".parse().unwrap(),
+            "".to_string(),
+            None,
+            None,
+        );
+        ctx.add_guard(backend.begin_request());
+        let (_sender, body) = Body::channel();
+        let resp = ctx.resp(StatusCode::OK, HeaderMap::new(), body).build_response().await.unwrap();
+        assert_eq!(backend.get_in_flight(), 1);
+        drop(resp);
+        assert_eq!(backend.get_in_flight(), 0);
+    }
+}
@@ -1,13 +1,21 @@
+pub mod authz;
+pub mod breaker;
 pub mod compression;
+pub mod concurrency;
+pub mod cors;
 pub mod header_modifier;
 mod inject;
+pub mod ip_restriction;
+pub mod jwt;
 #[cfg(feature = "cache")]
 mod limit;
+pub mod local_limit;
 pub mod maintenance;
 pub mod redirect;
 pub mod retry;
 pub mod rewrite;
 pub mod status;
+pub mod tracing;
 use async_trait::async_trait;
 
 use core::fmt;
@@ -68,10 +76,18 @@ fn init_filter_defs() {
     filters.insert(inject::CODE.to_string(), Box::new(inject::SgFilterInjectDef));
     #[cfg(feature = "cache")]
     filters.insert(limit::CODE.to_string(), Box::new(limit::SgFilterLimitDef));
+    filters.insert(local_limit::CODE.to_string(), Box::new(local_limit::SgFilterLocalLimitDef));
     filters.insert(compression::CODE.to_string(), Box::new(compression::SgFilterCompressionDef));
     filters.insert(status::CODE.to_string(), Box::new(status::SgFilterStatusDef));
     filters.insert(maintenance::CODE.to_string(), Box::new(maintenance::SgFilterMaintenanceDef));
     filters.insert(retry::CODE.to_string(), Box::new(retry::SgFilterRetryDef));
+    filters.insert(breaker::CODE.to_string(), Box::new(breaker::SgFilterBreakerDef));
+    filters.insert(tracing::CODE.to_string(), Box::new(tracing::SgFilterTracingDef));
+    filters.insert(jwt::CODE.to_string(), Box::new(jwt::SgFilterJwtDef));
+    filters.insert(authz::CODE.to_string(), Box::new(authz::SgFilterAuthzDef));
+    filters.insert(cors::CODE.to_string(), Box::new(cors::SgFilterCorsDef));
+    filters.insert(ip_restriction::CODE.to_string(), Box::new(ip_restriction::SgFilterIpRestrictionDef));
+    filters.insert(concurrency::CODE.to_string(), Box::new(concurrency::SgFilterConcurrencyDef));
     unsafe {
         FILTERS = Some(filters);
     }
@@ -335,18 +351,59 @@ impl SgPluginFilterInitDto {
 
 #[derive(Debug, Clone)]
 pub struct SgPluginFilterAccept {
+    /// Kinds of requests the filter is executed for, default is HTTP and gRPC.
     pub kind: Vec<SgPluginFilterKind>,
     /// Whether to accept the error response, default is false .
     ///
     /// if filter can accept the error response, it should return true
     pub accept_error_response: bool,
+    /// Whether the filter needs the whole response body, default is false.
+    ///
+    /// The response body is streamed to the client unless a filter needs it,
+    /// in which case it is buffered before the filter is called and the `Content-Length` is recalculated afterwards.
+    /// A filter that replaces the body with a stream of unknown length should remove the `Content-Length` header itself.
+    pub need_body: bool,
 }
 
 impl Default for SgPluginFilterAccept {
     fn default() -> Self {
         Self {
-            kind: vec![SgPluginFilterKind::Http],
+            kind: vec![SgPluginFilterKind::Http, SgPluginFilterKind::Grpc],
             accept_error_response: false,
+            need_body: false,
+        }
+    }
+}
+
+/// Fixtures shared by the tests of the filters.
+#[cfg(test)]
+pub(crate) mod test_fixtures {
+    use http::{HeaderMap, Method, Uri, Version};
+    use hyper::Body;
+
+    use super::{SgAttachedLevel, SgPluginFilterInitDto};
+    use crate::plugins::context::SgRoutePluginContext;
+
+    pub fn new_ctx(method: Method, uri: &'static str, headers: HeaderMap, remote_addr: &str, gateway_name: &str) -> SgRoutePluginContext {
+        SgRoutePluginContext::new_http(
+            method,
+            Uri::from_static(uri),
+            Version::HTTP_11,
+            headers,
+            Body::empty(),
+            remote_addr.parse().unwrap(),
+            gateway_name.to_string(),
+            None,
+            None,
+        )
+    }
+
+    pub fn init_dto(attached_level: SgAttachedLevel) -> SgPluginFilterInitDto {
+        SgPluginFilterInitDto {
+            gateway_name: "".to_string(),
+            gateway_parameters: Default::default(),
+            http_route_rules: vec![],
+            attached_level,
         }
     }
 }
@@ -361,9 +418,66 @@ mod tests {
             http_route_dto::SgHttpPathMatchType,
             plugin_filter_dto::{SgHttpPathModifier, SgHttpPathModifierType},
         },
-        instance::{SgHttpPathMatchInst, SgHttpRouteMatchInst},
-        plugins::filters::http_common_modify_path,
+        instance::{SgGrpcMethodMatchInst, SgHttpPathMatchInst, SgHttpRouteMatchInst, SgHttpRouteRuleInst},
+        plugins::{
+            context::{ChosenHttpRouteRuleInst, SgRoutePluginContext},
+            filters::{http_common_modify_path, SgPluginFilter, SgPluginFilterInitDto, SgPluginFilterKind},
+        },
     };
+    use async_trait::async_trait;
+    use http::{header::CONTENT_TYPE, HeaderMap, Method, Uri, Version};
+    use hyper::Body;
+
+    struct DefaultAcceptFilter;
+
+    #[async_trait]
+    impl SgPluginFilter for DefaultAcceptFilter {
+        async fn init(&mut self, _: &SgPluginFilterInitDto) -> TardisResult<()> {
+            Ok(())
+        }
+
+        async fn destroy(&self) -> TardisResult<()> {
+            Ok(())
+        }
+
+        async fn req_filter(&self, _: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+            Ok((true, ctx))
+        }
+
+        async fn resp_filter(&self, _: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+            Ok((true, ctx))
+        }
+    }
+
+    #[test]
+    fn test_grpc_request_kind() {
+        let new_ctx = |matched_match: Option<&SgHttpRouteMatchInst>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, "application/grpc".parse().unwrap());
+            SgRoutePluginContext::new_http(
+                Method::POST,
+                Uri::from_static("http://sg.idealworld.group/helloworld.Greeter/SayHello"),
+                Version::HTTP_2,
+                headers,
+                Body::empty(),
+                "127.0.0.1:8080".parse().unwrap(),
+                String::new(),
+                Some(ChosenHttpRouteRuleInst::cloned_from(&SgHttpRouteRuleInst::default(), matched_match)),
+                None,
+            )
+        };
+        // The content type alone does not make a gRPC request, so filters can not be skipped by setting it
+        let ctx = new_ctx(None);
+        assert_eq!(ctx.get_request_kind(), &SgPluginFilterKind::Http);
+        assert!(DefaultAcceptFilter.before_resp_filter_check(&ctx));
+
+        let ctx = new_ctx(Some(&SgHttpRouteMatchInst {
+            grpc: Some(SgGrpcMethodMatchInst::default()),
+            ..Default::default()
+        }));
+        assert_eq!(ctx.get_request_kind(), &SgPluginFilterKind::Grpc);
+        assert!(DefaultAcceptFilter.before_resp_filter_check(&ctx));
+    }
 
     #[test]
     fn test_http_common_modify_path() -> TardisResult<()> {
@@ -0,0 +1,259 @@
+use async_trait::async_trait;
+use http::Method;
+use serde::{Deserialize, Serialize};
+use tardis::basic::{error::TardisError, result::TardisResult};
+use tardis::regex::Regex;
+
+use crate::config::http_route_dto::{SgHttpPathMatch, SgHttpPathMatchType};
+use crate::def_filter;
+use crate::plugins::context::SGIdentInfo;
+
+use super::{SgPluginFilter, SgPluginFilterInitDto, SgRoutePluginContext};
+
+def_filter!("authz", SgFilterAuthzDef, SgFilterAuthz);
+
+/// Role based authorization of the identity set by an authentication filter, e.g. [jwt](super::jwt).
+///
+/// Rules are evaluated in order and the first rule matching the request decides,
+/// requests matching no rule are handled by `default_action`.
+/// Requests without identity are rejected with `401`, identities not meeting the requirements of the rule with `403`.
+///
+/// Filters are executed from the backend level up to the gateway level,
+/// so the authentication filter must be attached to the same or a more specific level and before this filter.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgFilterAuthz {
+    pub rules: Vec<SgAuthzRule>,
+    pub default_action: SgAuthzAction,
+    #[serde(skip)]
+    path_regulars: Vec<Vec<Option<Regex>>>,
+}
+
+impl Default for SgFilterAuthz {
+    fn default() -> Self {
+        Self {
+            rules: vec![],
+            default_action: SgAuthzAction::Deny,
+            path_regulars: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SgAuthzAction {
+    Allow,
+    Deny,
+}
+
+/// The request matches the rule if it matches all conditions set, unset conditions match any request.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SgAuthzRule {
+    /// Hostnames, `*.` matches any subdomain, e.g. `*.idealworld.group`.
+    pub hosts: Option<Vec<String>>,
+    pub methods: Option<Vec<String>>,
+    pub paths: Option<Vec<SgHttpPathMatch>>,
+    /// Requests matching the rule are allowed without identity.
+    pub allow_anonymous: bool,
+    /// The identity must have at least one of these roles.
+    pub any_roles: Option<Vec<String>>,
+    /// The identity must have all of these roles.
+    pub all_roles: Option<Vec<String>>,
+    /// The id of the identity must be one of these.
+    pub ids: Option<Vec<String>>,
+}
+
+impl SgAuthzRule {
+    fn is_match(&self, path_regulars: &[Option<Regex>], host: &str, method: &Method, path: &str) -> bool {
+        let host_matched = self.hosts.iter().all(|hosts| {
+            hosts.iter().any(|expected| match expected.strip_prefix('*') {
+                Some(suffix) => host.len() > suffix.len() && host[host.len() - suffix.len()..].eq_ignore_ascii_case(suffix),
+                None => expected.eq_ignore_ascii_case(host),
+            })
+        });
+        let method_matched = self.methods.iter().all(|methods| methods.iter().any(|expected| expected.eq_ignore_ascii_case(method.as_str())));
+        let path_matched = self.paths.iter().all(|paths| {
+            paths.iter().zip(path_regulars).any(|(expected, regular)| match expected.kind {
+                SgHttpPathMatchType::Exact => path == expected.value,
+                SgHttpPathMatchType::Prefix => path.starts_with(&expected.value),
+                SgHttpPathMatchType::Regular => regular.as_ref().is_some_and(|regular| regular.is_match(path)),
+            })
+        });
+        host_matched && method_matched && path_matched
+    }
+
+    fn is_allowed(&self, ident: &SGIdentInfo) -> bool {
+        let has_role = |expected: &String| ident.roles.iter().any(|role| &role.id == expected);
+        self.any_roles.iter().all(|roles| roles.iter().any(has_role))
+            && self.all_roles.iter().all(|roles| roles.iter().all(has_role))
+            && self.ids.iter().all(|ids| ids.contains(&ident.id))
+    }
+}
+
+#[async_trait]
+impl SgPluginFilter for SgFilterAuthz {
+    fn accept(&self) -> super::SgPluginFilterAccept {
+        super::SgPluginFilterAccept {
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc, super::SgPluginFilterKind::Ws],
+            ..Default::default()
+        }
+    }
+
+    async fn init(&mut self, _: &SgPluginFilterInitDto) -> TardisResult<()> {
+        self.path_regulars = self
+            .rules
+            .iter()
+            .map(|rule| {
+                rule.paths
+                    .iter()
+                    .flatten()
+                    .map(|path| {
+                        if path.kind != SgHttpPathMatchType::Regular {
+                            return Ok(None);
+                        }
+                        Regex::new(&path.value)
+                            .map(Some)
+                            .map_err(|error| TardisError::format_error(&format!("[SG.Filter.Authz] Path regular {} format error: {error}", path.value), ""))
+                    })
+                    .collect()
+            })
+            .collect::<TardisResult<_>>()?;
+        Ok(())
+    }
+
+    async fn destroy(&self) -> TardisResult<()> {
+        Ok(())
+    }
+
+    async fn req_filter(&self, _: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        let uri = ctx.request.get_uri();
+        let host = uri.host().unwrap_or_default();
+        let method = ctx.request.get_method();
+        let Some((rule, _)) = self.rules.iter().zip(&self.path_regulars).find(|(rule, path_regulars)| rule.is_match(path_regulars, host, method, uri.path())) else {
+            return match self.default_action {
+                SgAuthzAction::Allow => Ok((true, ctx)),
+                SgAuthzAction::Deny => Err(TardisError::forbidden("[SG.Filter.Authz] No rule matched", "")),
+            };
+        };
+        if rule.allow_anonymous {
+            return Ok((true, ctx));
+        }
+        let Some(ident) = ctx.get_cert_info() else {
+            return Err(TardisError::unauthorized("[SG.Filter.Authz] Identity is required", ""));
+        };
+        if !rule.is_allowed(ident) {
+            return Err(TardisError::forbidden(&format!("[SG.Filter.Authz] {} is not allowed", ident.id), ""));
+        }
+        Ok((true, ctx))
+    }
+
+    async fn resp_filter(&self, _: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        Ok((true, ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, Method};
+    use tardis::tokio;
+
+    use super::*;
+    use crate::plugins::context::SGRoleInfo;
+    use crate::plugins::filters::{test_fixtures, SgAttachedLevel};
+
+    fn new_ctx(method: Method, uri: &'static str, ident: Option<(&str, Vec<&str>)>) -> SgRoutePluginContext {
+        let mut ctx = test_fixtures::new_ctx(method, uri, HeaderMap::new(), "127.0.0.1:8080", "");
+        if let Some((id, roles)) = ident {
+            ctx.set_cert_info(SGIdentInfo {
+                id: id.to_string(),
+                name: None,
+                roles: roles.into_iter().map(|role| SGRoleInfo { id: role.to_string(), name: None }).collect(),
+            });
+        }
+        ctx
+    }
+
+    async fn check(authz: &SgFilterAuthz, ctx: SgRoutePluginContext) -> Result<(), String> {
+        authz.req_filter("", ctx).await.map(|_| ()).map_err(|error| error.code)
+    }
+
+    #[tokio::test]
+    async fn test_authz() {
+        let mut authz = SgFilterAuthz {
+            rules: vec![
+                SgAuthzRule {
+                    paths: Some(vec![SgHttpPathMatch {
+                        kind: SgHttpPathMatchType::Prefix,
+                        value: "/public".to_string(),
+                    }]),
+                    allow_anonymous: true,
+                    ..Default::default()
+                },
+                SgAuthzRule {
+                    hosts: Some(vec!["*.idealworld.group".to_string()]),
+                    methods: Some(vec!["delete".to_string()]),
+                    paths: Some(vec![SgHttpPathMatch {
+                        kind: SgHttpPathMatchType::Regular,
+                        value: "^/iam/[0-9]+$".to_string(),
+                    }]),
+                    all_roles: Some(vec!["admin".to_string(), "iam".to_string()]),
+                    ..Default::default()
+                },
+                SgAuthzRule {
+                    paths: Some(vec![SgHttpPathMatch {
+                        kind: SgHttpPathMatchType::Prefix,
+                        value: "/iam".to_string(),
+                    }]),
+                    any_roles: Some(vec!["admin".to_string(), "user".to_string()]),
+                    ..Default::default()
+                },
+                SgAuthzRule {
+                    paths: Some(vec![SgHttpPathMatch {
+                        kind: SgHttpPathMatchType::Exact,
+                        value: "/audit".to_string(),
+                    }]),
+                    ids: Some(vec!["u001".to_string()]),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        authz.init(&test_fixtures::init_dto(SgAttachedLevel::HttpRoute)).await.unwrap();
+
+        assert!(check(&authz, new_ctx(Method::GET, "http://sg.idealworld.group/public/a", None)).await.is_ok());
+
+        assert_eq!(check(&authz, new_ctx(Method::GET, "http://sg.idealworld.group/iam/1", None)).await, Err("401".to_string()));
+        assert!(check(&authz, new_ctx(Method::GET, "http://sg.idealworld.group/iam/1", Some(("u002", vec!["user"])))).await.is_ok());
+        assert_eq!(
+            check(&authz, new_ctx(Method::GET, "http://sg.idealworld.group/iam/1", Some(("u002", vec!["guest"])))).await,
+            Err("403".to_string())
+        );
+
+        assert_eq!(
+            check(&authz, new_ctx(Method::DELETE, "http://sg.idealworld.group/iam/1", Some(("u002", vec!["admin"])))).await,
+            Err("403".to_string())
+        );
+        assert!(check(&authz, new_ctx(Method::DELETE, "http://sg.idealworld.group/iam/1", Some(("u002", vec!["admin", "iam"])))).await.is_ok());
+        // Not matching the host of the delete rule
+        assert!(check(&authz, new_ctx(Method::DELETE, "http://idealworld.group/iam/1", Some(("u002", vec!["admin"])))).await.is_ok());
+
+        assert!(check(&authz, new_ctx(Method::GET, "http://sg.idealworld.group/audit", Some(("u001", vec![])))).await.is_ok());
+        assert_eq!(
+            check(&authz, new_ctx(Method::GET, "http://sg.idealworld.group/audit", Some(("u002", vec!["admin"])))).await,
+            Err("403".to_string())
+        );
+
+        // Default action
+        assert_eq!(
+            check(&authz, new_ctx(Method::GET, "http://sg.idealworld.group/other", Some(("u001", vec!["admin"])))).await,
+            Err("403".to_string())
+        );
+        authz.default_action = SgAuthzAction::Allow;
+        assert!(check(&authz, new_ctx(Method::GET, "http://sg.idealworld.group/other", None)).await.is_ok());
+
+        // Invalid configuration
+        authz.rules[1].paths.as_mut().unwrap()[0].value = "(".to_string();
+        assert!(authz.init(&test_fixtures::init_dto(SgAttachedLevel::HttpRoute)).await.is_err());
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use http::{HeaderName, HeaderValue, StatusCode};
+#[cfg(feature = "cache")]
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tardis::basic::{error::TardisError, result::TardisResult};
+use tardis::chrono::Utc;
+use tardis::log;
+use tardis::tokio::sync::Mutex;
+#[cfg(feature = "cache")]
+use tardis::{cache::Script, TardisFuns};
+
+use crate::def_filter;
+use crate::plugins::context::SgRouteFilterRequestAction;
+
+use super::{SgPluginFilter, SgPluginFilterInitDto, SgRoutePluginContext};
+
+def_filter!("breaker", SgFilterBreakerDef, SgFilterBreaker);
+
+#[cfg(feature = "cache")]
+lazy_static! {
+    /// Compare and set script of a backend state, so that concurrent updates of gateway replicas are not lost
+    ///
+    /// # Arguments
+    ///
+    /// * KEYS[1]  breaker state key
+    /// * ARGV[1]  backend key
+    /// * ARGV[2]  expected state, empty if absent
+    /// * ARGV[3]  new state
+    /// * ARGV[4]  expiration of the states, milliseconds
+    ///
+    /// # Return
+    ///
+    /// * 1 updated, 0 the state was changed by others
+    static ref COMPARE_AND_SET_SCRIPT: Script = Script::new(
+        r"
+    if (redis.call('hget', KEYS[1], ARGV[1]) or '') ~= ARGV[2] then
+        return 0;
+    end
+    redis.call('hset', KEYS[1], ARGV[1], ARGV[3]);
+    redis.call('pexpire', KEYS[1], ARGV[4]);
+    return 1;
+    ",
+    );
+}
+
+/// Circuit breaker, the state is maintained separately for each backend.
+///
+/// - `Closed`: requests pass through, failures are counted.
+/// - `Open`: requests are answered with the [fallback](SgBreakerFallback) response without reaching the backend.
+/// - `HalfOpen`: after `open_ms`, a limited number of probe requests are let through,
+///   the breaker closes if they all succeed and opens again on any failure.
+///
+/// With the `cache` feature and a gateway redis configured, the state is shared between the gateway replicas running the same
+/// filter (give the filter a `name`, unnamed filters get a random id), it expires once the backends are neither requested nor
+/// probed for `window_ms` + `open_ms`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgFilterBreaker {
+    /// Open the breaker after this many consecutive failures, `0` disables this check.
+    pub consecutive_failures: u32,
+    /// Open the breaker when the ratio of failures in the window reaches this value (0.0 ~ 1.0), `0` disables this check.
+    pub failure_ratio: f64,
+    /// The minimum number of requests in a window before `failure_ratio` is evaluated.
+    pub min_requests: u32,
+    /// milliseconds
+    pub window_ms: u64,
+    /// How long the breaker stays open before letting probe requests through, milliseconds.
+    pub open_ms: u64,
+    /// Number of probe requests allowed in the half-open state.
+    pub half_open_requests: u32,
+    /// Response status codes treated as failures, default is all 5xx.
+    pub failure_status_codes: Option<Vec<u16>>,
+    pub fallback: SgBreakerFallback,
+    #[cfg(feature = "cache")]
+    pub cache_key: String,
+    #[serde(skip)]
+    states: Arc<Mutex<HashMap<String, BreakerState>>>,
+}
+
+impl Default for SgFilterBreaker {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            failure_ratio: 0.0,
+            min_requests: 20,
+            window_ms: 10000,
+            open_ms: 30000,
+            half_open_requests: 1,
+            failure_status_codes: None,
+            fallback: SgBreakerFallback::default(),
+            #[cfg(feature = "cache")]
+            cache_key: "sg:plugin:filter:breaker".to_string(),
+            states: Default::default(),
+        }
+    }
+}
+
+/// The response returned while the breaker is open.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgBreakerFallback {
+    pub status_code: u16,
+    pub headers: Option<HashMap<String, String>>,
+    pub body: Option<String>,
+}
+
+impl Default for SgBreakerFallback {
+    fn default() -> Self {
+        Self {
+            status_code: StatusCode::SERVICE_UNAVAILABLE.as_u16(),
+            headers: None,
+            body: None,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BreakerStatus {
+    #[default]
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct BreakerState {
+    status: BreakerStatus,
+    /// Timestamp of the last status change, milliseconds
+    changed_at_ms: u64,
+    consecutive_failures: u32,
+    window_start_ms: u64,
+    window_requests: u32,
+    window_failures: u32,
+    half_open_requests: u32,
+    half_open_successes: u32,
+}
+
+impl BreakerState {
+    fn change_to(&mut self, status: BreakerStatus, now: u64) {
+        self.status = status;
+        self.changed_at_ms = now;
+        self.consecutive_failures = 0;
+        self.window_start_ms = now;
+        self.window_requests = 0;
+        self.window_failures = 0;
+        self.half_open_requests = 0;
+        self.half_open_successes = 0;
+    }
+}
+
+impl SgFilterBreaker {
+    /// Whether the request is allowed to reach the backend.
+    fn try_pass(&self, state: &mut BreakerState, now: u64) -> bool {
+        match state.status {
+            BreakerStatus::Closed => true,
+            BreakerStatus::Open => {
+                if now >= state.changed_at_ms + self.open_ms {
+                    state.change_to(BreakerStatus::HalfOpen, now);
+                    state.half_open_requests = 1;
+                    true
+                } else {
+                    false
+                }
+            }
+            BreakerStatus::HalfOpen => {
+                // Probes that never report back would otherwise keep the breaker half-open forever
+                if now >= state.changed_at_ms + self.open_ms {
+                    state.change_to(BreakerStatus::HalfOpen, now);
+                }
+                if state.half_open_requests < self.half_open_requests.max(1) {
+                    state.half_open_requests += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    fn record(&self, state: &mut BreakerState, now: u64, success: bool) {
+        match state.status {
+            BreakerStatus::Closed => {
+                if now >= state.window_start_ms + self.window_ms {
+                    state.window_start_ms = now;
+                    state.window_requests = 0;
+                    state.window_failures = 0;
+                }
+                state.window_requests += 1;
+                if success {
+                    state.consecutive_failures = 0;
+                } else {
+                    state.consecutive_failures += 1;
+                    state.window_failures += 1;
+                }
+                let consecutive_tripped = self.consecutive_failures > 0 && state.consecutive_failures >= self.consecutive_failures;
+                let ratio_tripped = self.failure_ratio > 0.0
+                    && state.window_requests >= self.min_requests.max(1)
+                    && state.window_failures as f64 / state.window_requests as f64 >= self.failure_ratio;
+                if consecutive_tripped || ratio_tripped {
+                    state.change_to(BreakerStatus::Open, now);
+                }
+            }
+            BreakerStatus::HalfOpen => {
+                if success {
+                    state.half_open_successes += 1;
+                    if state.half_open_successes >= self.half_open_requests.max(1) {
+                        state.change_to(BreakerStatus::Closed, now);
+                    }
+                } else {
+                    state.change_to(BreakerStatus::Open, now);
+                }
+            }
+            // Late responses of requests issued before the breaker opened
+            BreakerStatus::Open => {}
+        }
+    }
+
+    fn is_failure(&self, ctx: &SgRoutePluginContext) -> bool {
+        if ctx.is_resp_error() {
+            return true;
+        }
+        let status_code = ctx.response.get_status_code();
+        if let Some(failure_status_codes) = &self.failure_status_codes {
+            failure_status_codes.contains(&status_code.as_u16())
+        } else {
+            status_code.is_server_error()
+        }
+    }
+
+    async fn update_state<T>(&self, _id: &str, _ctx: &SgRoutePluginContext, backend_key: &str, f: impl Fn(&mut BreakerState) -> T) -> TardisResult<T> {
+        #[cfg(feature = "cache")]
+        {
+            if let Ok(cache) = _ctx.cache().await {
+                // Filters with different thresholds must not share a state
+                let cache_key = format!("{}:{}:{_id}", self.cache_key, _ctx.get_gateway_name());
+                // The update is retried from the latest state if another replica changed it in between
+                loop {
+                    let old_value = cache.hget(&cache_key, backend_key).await?;
+                    let mut state = match &old_value {
+                        Some(state) => TardisFuns::json.str_to_obj::<BreakerState>(state)?,
+                        None => BreakerState::default(),
+                    };
+                    let old_state = state.clone();
+                    let result = f(&mut state);
+                    if state == old_state {
+                        return Ok(result);
+                    }
+                    let updated: bool = COMPARE_AND_SET_SCRIPT
+                        .key(&cache_key)
+                        .arg(backend_key)
+                        .arg(old_value.unwrap_or_default())
+                        .arg(TardisFuns::json.obj_to_string(&state)?)
+                        .arg((self.window_ms + self.open_ms).max(1))
+                        .invoke_async(&mut cache.cmd().await?)
+                        .await
+                        .map_err(|e| TardisError::internal_error(&format!("[SG.Filter.Breaker] redis error : {e}"), ""))?;
+                    if updated {
+                        return Ok(result);
+                    }
+                }
+            }
+        }
+        let mut states = self.states.lock().await;
+        Ok(f(states.entry(backend_key.to_string()).or_default()))
+    }
+
+    fn fallback(&self, mut ctx: SgRoutePluginContext) -> TardisResult<SgRoutePluginContext> {
+        ctx.set_action(SgRouteFilterRequestAction::Response);
+        ctx.response.set_status_code(
+            StatusCode::from_u16(self.fallback.status_code)
+                .map_err(|e| TardisError::format_error(&format!("[SG.Filter.Breaker] invalid fallback status code {}: {e}", self.fallback.status_code), ""))?,
+        );
+        if let Some(headers) = &self.fallback.headers {
+            for (k, v) in headers {
+                ctx.response.set_header_str(k, v)?;
+            }
+        }
+        if let Some(body) = &self.fallback.body {
+            ctx.response.set_body(body.clone());
+        }
+        Ok(ctx)
+    }
+}
+
+#[async_trait]
+impl SgPluginFilter for SgFilterBreaker {
+    fn accept(&self) -> super::SgPluginFilterAccept {
+        super::SgPluginFilterAccept {
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc],
+            accept_error_response: true,
+            ..Default::default()
+        }
+    }
+
+    async fn init(&mut self, _: &SgPluginFilterInitDto) -> TardisResult<()> {
+        if let Some(headers) = &self.fallback.headers {
+            for (k, v) in headers {
+                HeaderName::try_from(k).map_err(|e| TardisError::format_error(&format!("[SG.Filter.Breaker] invalid fallback header {k}: {e}"), ""))?;
+                HeaderValue::try_from(v).map_err(|e| TardisError::format_error(&format!("[SG.Filter.Breaker] invalid fallback header value {v}: {e}"), ""))?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn destroy(&self) -> TardisResult<()> {
+        Ok(())
+    }
+
+    async fn req_filter(&self, id: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        if let Some(backend) = ctx.get_chose_backend() {
+            let backend_key = backend.get_base_url();
+            let now = Utc::now().timestamp_millis() as u64;
+            if !self.update_state(id, &ctx, &backend_key, |state| self.try_pass(state, now)).await? {
+                log::debug!("[SG.Filter.Breaker] breaker of backend {backend_key} is open, return fallback response");
+                return Ok((false, self.fallback(ctx)?));
+            }
+        }
+        Ok((true, ctx))
+    }
+
+    async fn resp_filter(&self, id: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        if let Some(backend) = ctx.get_chose_backend() {
+            let backend_key = backend.get_base_url();
+            let success = !self.is_failure(&ctx);
+            let now = Utc::now().timestamp_millis() as u64;
+            let status = self
+                .update_state(id, &ctx, &backend_key, |state| {
+                    self.record(state, now, success);
+                    state.status.clone()
+                })
+                .await?;
+            if status == BreakerStatus::Open {
+                log::trace!("[SG.Filter.Breaker] breaker of backend {backend_key} is open");
+            }
+        }
+        Ok((true, ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, Method, StatusCode, Uri, Version};
+    use hyper::Body;
+    use tardis::basic::error::TardisError;
+    use tardis::tokio;
+
+    use super::*;
+    use crate::plugins::context::AvailableBackendInst;
+
+    fn new_ctx() -> SgRoutePluginContext {
+        SgRoutePluginContext::new_http(
+            Method::GET,
+            Uri::from_static("http://sg.idealworld.group/iam/ct/001?name=sg"),
+            Version::HTTP_11,
+            HeaderMap::new(),
+            Body::empty(),
+            "127.0.0.1:8080".parse().unwrap(),
+            "".to_string(),
+            None,
+            Some(AvailableBackendInst {
+                name_or_host: "iam".to_string(),
+                port: 8080,
+                ..Default::default()
+            }),
+        )
+    }
+
+    #[test]
+    fn test_breaker_state() {
+        let breaker = SgFilterBreaker {
+            consecutive_failures: 0,
+            failure_ratio: 0.5,
+            min_requests: 4,
+            window_ms: 1000,
+            open_ms: 100,
+            half_open_requests: 2,
+            ..Default::default()
+        };
+        let mut state = BreakerState::default();
+        breaker.record(&mut state, 0, false);
+        breaker.record(&mut state, 1, false);
+        breaker.record(&mut state, 2, true);
+        assert_eq!(state.status, BreakerStatus::Closed);
+        breaker.record(&mut state, 3, true);
+        assert_eq!(state.status, BreakerStatus::Open);
+        assert!(!breaker.try_pass(&mut state, 50));
+
+        // half open, only two probes are allowed
+        assert!(breaker.try_pass(&mut state, 103));
+        assert_eq!(state.status, BreakerStatus::HalfOpen);
+        assert!(breaker.try_pass(&mut state, 104));
+        assert!(!breaker.try_pass(&mut state, 105));
+        breaker.record(&mut state, 106, true);
+        assert_eq!(state.status, BreakerStatus::HalfOpen);
+        breaker.record(&mut state, 107, true);
+        assert_eq!(state.status, BreakerStatus::Closed);
+
+        // a failed probe opens the breaker again
+        breaker.record(&mut state, 1200, false);
+        breaker.record(&mut state, 1201, false);
+        breaker.record(&mut state, 1202, false);
+        breaker.record(&mut state, 1203, false);
+        assert!(breaker.try_pass(&mut state, 1303));
+        breaker.record(&mut state, 1304, false);
+        assert_eq!(state.status, BreakerStatus::Open);
+
+        // the window is reset, failures of the previous window are not counted
+        let mut state = BreakerState::default();
+        breaker.record(&mut state, 0, false);
+        breaker.record(&mut state, 1, false);
+        breaker.record(&mut state, 1001, true);
+        breaker.record(&mut state, 1002, true);
+        breaker.record(&mut state, 1003, true);
+        breaker.record(&mut state, 1004, false);
+        assert_eq!(state.status, BreakerStatus::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_breaker_filter() {
+        let breaker = SgFilterBreaker {
+            consecutive_failures: 2,
+            open_ms: 200,
+            fallback: SgBreakerFallback {
+                status_code: 503,
+                headers: Some(HashMap::from([("x-sg-breaker".to_string(), "open".to_string())])),
+                body: Some("service unavailable".to_string()),
+            },
+            ..Default::default()
+        };
+
+        let (is_continue, ctx) = breaker.req_filter("", new_ctx()).await.unwrap();
+        assert!(is_continue);
+        let (_, _) = breaker.resp_filter("", ctx.resp_from_error(TardisError::bad_gateway("mock error", ""))).await.unwrap();
+        let (_, ctx) = breaker.req_filter("", new_ctx()).await.unwrap();
+        let (_, _) = breaker.resp_filter("", ctx.resp(StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), Body::empty())).await.unwrap();
+
+        let (is_continue, mut ctx) = breaker.req_filter("", new_ctx()).await.unwrap();
+        assert!(!is_continue);
+        assert_eq!(ctx.get_action(), &SgRouteFilterRequestAction::Response);
+        assert!(ctx.get_chose_backend().is_none());
+        assert_eq!(ctx.response.get_status_code(), &StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ctx.response.get_headers().get("x-sg-breaker").unwrap(), "open");
+        assert_eq!(ctx.response.dump_body().await.unwrap(), "service unavailable");
+
+        tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        let (is_continue, ctx) = breaker.req_filter("", new_ctx()).await.unwrap();
+        assert!(is_continue);
+        let (_, _) = breaker.resp_filter("", ctx.resp(StatusCode::OK, HeaderMap::new(), Body::empty())).await.unwrap();
+        let (is_continue, _) = breaker.req_filter("", new_ctx()).await.unwrap();
+        assert!(is_continue);
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tardis::basic::{error::TardisError, result::TardisResult};
+use tardis::log;
+use tardis::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tardis::tokio::time::timeout;
+
+use crate::def_filter;
+use crate::plugins::context::SgRouteFilterRequestAction;
+
+use super::{SgPluginFilter, SgPluginFilterInitDto, SgRoutePluginContext};
+
+def_filter!("concurrency", SgFilterConcurrencyDef, SgFilterConcurrency);
+
+/// Limit the number of in-flight requests.
+///
+/// Requests beyond the limit wait in a FIFO queue for up to `queue_timeout_ms`,
+/// they are answered with `503` when the queue is full or the timeout is reached.
+/// A request is in flight until its response body has been sent to the client or the client has gone away.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgFilterConcurrency {
+    /// Maximum number of in-flight requests, the initial and upper limit in adaptive mode.
+    pub max_concurrency: usize,
+    /// Maximum number of waiting requests, `0` rejects requests as soon as the limit is reached.
+    pub queue_size: usize,
+    pub queue_timeout_ms: u64,
+    /// Count requests per backend instead of counting all requests passing through the filter.
+    pub per_backend: bool,
+    /// Tune the limit from the backend latency.
+    pub adaptive: Option<SgConcurrencyAdaptive>,
+    #[serde(skip)]
+    limiters: Arc<Mutex<HashMap<String, Arc<ConcurrencyLimiter>>>>,
+}
+
+impl Default for SgFilterConcurrency {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 100,
+            queue_size: 100,
+            queue_timeout_ms: 1000,
+            per_backend: false,
+            adaptive: None,
+            limiters: Default::default(),
+        }
+    }
+}
+
+/// Additive increase, multiplicative decrease of the limit.
+///
+/// The limit is decreased by `backoff_ratio` when the backend fails or responds slower than `latency_threshold_ms`,
+/// otherwise it is increased by one while at least half of it is used.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgConcurrencyAdaptive {
+    pub min_concurrency: usize,
+    pub latency_threshold_ms: u64,
+    pub backoff_ratio: f64,
+}
+
+impl Default for SgConcurrencyAdaptive {
+    fn default() -> Self {
+        Self {
+            min_concurrency: 1,
+            latency_threshold_ms: 1000,
+            backoff_ratio: 0.9,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ConcurrencyLimiter {
+    semaphore: Arc<Semaphore>,
+    queued: AtomicUsize,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    limit: usize,
+    /// Permits in flight or available, exceeds `limit` after a decrease until enough requests complete.
+    permits: usize,
+}
+
+impl ConcurrencyLimiter {
+    fn new(limit: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            queued: AtomicUsize::new(0),
+            state: Mutex::new(LimiterState { limit, permits: limit }),
+        }
+    }
+
+    fn set_limit(&self, state: &mut LimiterState, limit: usize) {
+        state.limit = limit;
+        if limit > state.permits {
+            self.semaphore.add_permits(limit - state.permits);
+            state.permits = limit;
+        } else {
+            state.permits -= self.semaphore.forget_permits(state.permits - limit);
+        }
+    }
+
+    fn release(&self, permit: OwnedSemaphorePermit) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.permits > state.limit {
+            permit.forget();
+            state.permits -= 1;
+        }
+    }
+
+    fn adapt(&self, adaptive: &SgConcurrencyAdaptive, max_concurrency: usize, dropped: bool) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let limit = if dropped {
+            ((state.limit as f64 * adaptive.backoff_ratio) as usize).max(adaptive.min_concurrency)
+        } else if (state.permits - self.semaphore.available_permits()) * 2 >= state.limit {
+            (state.limit + 1).min(max_concurrency)
+        } else {
+            return;
+        };
+        if limit != state.limit {
+            log::trace!("[SG.Filter.Concurrency] Limit changed from {} to {limit}", state.limit);
+            self.set_limit(&mut state, limit);
+        }
+    }
+}
+
+/// Held by the request context, the slot is released when the request completes.
+struct ConcurrencyPermit {
+    limiter: Arc<ConcurrencyLimiter>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            self.limiter.release(permit);
+        }
+    }
+}
+
+/// Leaves the queue even if the waiting request is cancelled.
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl SgFilterConcurrency {
+    fn key(&self, ctx: &SgRoutePluginContext) -> String {
+        if self.per_backend {
+            ctx.get_chose_backend().map(|backend| format!("{}:{}", backend.name_or_host, backend.port)).unwrap_or_default()
+        } else {
+            String::new()
+        }
+    }
+
+    fn limiter(&self, key: String) -> Arc<ConcurrencyLimiter> {
+        let mut limiters = self.limiters.lock().unwrap_or_else(|e| e.into_inner());
+        limiters.entry(key).or_insert_with(|| Arc::new(ConcurrencyLimiter::new(self.max_concurrency))).clone()
+    }
+
+    async fn acquire(&self, limiter: Arc<ConcurrencyLimiter>) -> Option<ConcurrencyPermit> {
+        // Available permits are only left when nobody is waiting, so this does not jump the queue
+        if let Ok(permit) = limiter.semaphore.clone().try_acquire_owned() {
+            return Some(ConcurrencyPermit { limiter, permit: Some(permit) });
+        }
+        if limiter.queued.fetch_add(1, Ordering::SeqCst) >= self.queue_size {
+            limiter.queued.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        let permit = {
+            let _queued = QueuedGuard(&limiter.queued);
+            timeout(Duration::from_millis(self.queue_timeout_ms), limiter.semaphore.clone().acquire_owned()).await
+        };
+        match permit {
+            Ok(Ok(permit)) => Some(ConcurrencyPermit { limiter, permit: Some(permit) }),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl SgPluginFilter for SgFilterConcurrency {
+    fn accept(&self) -> super::SgPluginFilterAccept {
+        super::SgPluginFilterAccept {
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc],
+            // Failures of the backend decrease the limit in adaptive mode
+            accept_error_response: true,
+            ..Default::default()
+        }
+    }
+
+    async fn init(&mut self, _: &SgPluginFilterInitDto) -> TardisResult<()> {
+        if self.max_concurrency == 0 {
+            return Err(TardisError::format_error("[SG.Filter.Concurrency] max_concurrency must be greater than 0", ""));
+        }
+        if let Some(adaptive) = &self.adaptive {
+            if adaptive.min_concurrency == 0 || adaptive.min_concurrency > self.max_concurrency {
+                return Err(TardisError::format_error(
+                    "[SG.Filter.Concurrency] min_concurrency must be between 1 and max_concurrency",
+                    "",
+                ));
+            }
+            if !(adaptive.backoff_ratio > 0.0 && adaptive.backoff_ratio < 1.0) {
+                return Err(TardisError::format_error("[SG.Filter.Concurrency] backoff_ratio must be between 0 and 1", ""));
+            }
+        }
+        Ok(())
+    }
+
+    async fn destroy(&self) -> TardisResult<()> {
+        Ok(())
+    }
+
+    async fn req_filter(&self, _: &str, mut ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        let key = self.key(&ctx);
+        let Some(permit) = self.acquire(self.limiter(key.clone())).await else {
+            log::debug!(
+                "[SG.Filter.Concurrency] Request {} is rejected, the queue of [{key}] is full or timed out",
+                ctx.get_request_id()
+            );
+            ctx.set_action(SgRouteFilterRequestAction::Response);
+            ctx.response.set_status_code(StatusCode::SERVICE_UNAVAILABLE);
+            ctx.response.set_body("Service Unavailable");
+            return Ok((false, ctx));
+        };
+        ctx.add_guard(permit);
+        Ok((true, ctx))
+    }
+
+    async fn resp_filter(&self, _: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        let Some(adaptive) = &self.adaptive else {
+            return Ok((true, ctx));
+        };
+        // Only requests sent to the backend are taken into account
+        if ctx.is_resp_error() || ctx.get_upstream_latency().is_some() {
+            let dropped = ctx.is_resp_error() || ctx.get_upstream_latency().is_some_and(|latency| latency > Duration::from_millis(adaptive.latency_threshold_ms));
+            self.limiter(self.key(&ctx)).adapt(adaptive, self.max_concurrency, dropped);
+        }
+        Ok((true, ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, Method};
+    use hyper::body::HttpBody;
+    use hyper::Body;
+    use serde_json::json;
+    use tardis::tokio;
+
+    use super::*;
+    use crate::plugins::filters::{test_fixtures, SgAttachedLevel};
+
+    fn new_ctx() -> SgRoutePluginContext {
+        test_fixtures::new_ctx(Method::GET, "http://sg.idealworld.group/iam/ct/001", HeaderMap::new(), "127.0.0.1:8080", "")
+    }
+
+    async fn new_filter(spec: serde_json::Value) -> TardisResult<SgFilterConcurrency> {
+        let mut filter = tardis::TardisFuns::json.json_to_obj::<SgFilterConcurrency>(spec)?;
+        filter.init(&test_fixtures::init_dto(SgAttachedLevel::Rule)).await?;
+        Ok(filter)
+    }
+
+    #[tokio::test]
+    async fn test_concurrency() {
+        assert!(new_filter(json!({ "max_concurrency": 0 })).await.is_err());
+        assert!(new_filter(json!({ "adaptive": { "backoff_ratio": 1.5 } })).await.is_err());
+
+        let filter = Arc::new(new_filter(json!({ "max_concurrency": 1, "queue_size": 1, "queue_timeout_ms": 200 })).await.unwrap());
+        let (is_continue, in_flight) = filter.req_filter("", new_ctx()).await.unwrap();
+        assert!(is_continue);
+        let queued = tokio::spawn({
+            let filter = filter.clone();
+            async move { filter.req_filter("", new_ctx()).await.unwrap() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // The queue is full
+        let (is_continue, ctx) = filter.req_filter("", new_ctx()).await.unwrap();
+        assert!(!is_continue);
+        assert_eq!(ctx.get_action(), &SgRouteFilterRequestAction::Response);
+        assert_eq!(ctx.response.get_status_code(), &StatusCode::SERVICE_UNAVAILABLE);
+        // The slot is released with the context of the completed request
+        drop(in_flight);
+        let (is_continue, in_flight) = queued.await.unwrap();
+        assert!(is_continue);
+        // The queue timeout is reached
+        let (is_continue, _) = filter.req_filter("", new_ctx()).await.unwrap();
+        assert!(!is_continue);
+        drop(in_flight);
+        assert!(filter.req_filter("", new_ctx()).await.unwrap().0);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_response() {
+        let filter = new_filter(json!({ "max_concurrency": 1, "queue_size": 0 })).await.unwrap();
+        let (_, ctx) = filter.req_filter("", new_ctx()).await.unwrap();
+        let (mut sender, body) = Body::channel();
+        let (_, mut ctx) = filter.resp_filter("", ctx.resp(StatusCode::OK, HeaderMap::new(), body)).await.unwrap();
+        let mut body = ctx.build_response().await.unwrap().into_body();
+        drop(ctx);
+        // The permit is held while the body is pending
+        sender.send_data("iam".into()).await.unwrap();
+        assert_eq!(body.data().await.unwrap().unwrap(), "iam");
+        assert!(!filter.req_filter("", new_ctx()).await.unwrap().0);
+        drop(sender);
+        assert!(body.data().await.is_none());
+        assert!(filter.req_filter("", new_ctx()).await.unwrap().0);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive() {
+        let filter = new_filter(json!({
+            "max_concurrency": 10,
+            "queue_size": 0,
+            "adaptive": { "min_concurrency": 2, "latency_threshold_ms": 100, "backoff_ratio": 0.5 }
+        }))
+        .await
+        .unwrap();
+        let respond = |latency: u64, ctx: SgRoutePluginContext| {
+            let mut ctx = ctx.resp(StatusCode::OK, HeaderMap::new(), Body::empty());
+            ctx.set_upstream_latency(Duration::from_millis(latency));
+            filter.resp_filter("", ctx)
+        };
+        let limiter = filter.limiter(String::new());
+
+        // Slow responses halve the limit
+        let (_, ctx) = filter.req_filter("", new_ctx()).await.unwrap();
+        respond(200, ctx).await.unwrap();
+        assert_eq!(limiter.state.lock().unwrap().limit, 5);
+        let (_, ctx) = filter.req_filter("", new_ctx()).await.unwrap();
+        respond(200, ctx).await.unwrap();
+        let (_, ctx) = filter.req_filter("", new_ctx()).await.unwrap();
+        respond(200, ctx).await.unwrap();
+        assert_eq!(limiter.state.lock().unwrap().limit, 2);
+
+        // In-flight requests keep their slots after a decrease
+        let mut in_flight = vec![];
+        for _ in 0..2 {
+            let (is_continue, ctx) = filter.req_filter("", new_ctx()).await.unwrap();
+            assert!(is_continue);
+            in_flight.push(ctx);
+        }
+        assert!(!filter.req_filter("", new_ctx()).await.unwrap().0);
+
+        // Fast responses increase the limit while it is used
+        let (_, ctx) = respond(10, in_flight.pop().unwrap()).await.unwrap();
+        assert_eq!(limiter.state.lock().unwrap().limit, 3);
+        drop(ctx);
+        for _ in 0..2 {
+            let (is_continue, ctx) = filter.req_filter("", new_ctx()).await.unwrap();
+            assert!(is_continue);
+            in_flight.push(ctx);
+        }
+        assert!(!filter.req_filter("", new_ctx()).await.unwrap().0);
+
+        // Failures decrease the limit
+        let ctx = in_flight.pop().unwrap().resp_from_error(TardisError::internal_error("", ""));
+        filter.resp_filter("", ctx).await.unwrap();
+        assert_eq!(limiter.state.lock().unwrap().limit, 2);
+        assert!(!filter.req_filter("", new_ctx()).await.unwrap().0);
+        in_flight.clear();
+        assert_eq!(limiter.semaphore.available_permits(), 2);
+    }
+}
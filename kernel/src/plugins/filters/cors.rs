@@ -0,0 +1,313 @@
+use async_trait::async_trait;
+use http::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use http::{HeaderValue, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use tardis::basic::error::TardisError;
+use tardis::basic::result::TardisResult;
+use tardis::log;
+use tardis::regex::Regex;
+
+use crate::def_filter;
+use crate::plugins::context::SgRouteFilterRequestAction;
+
+use super::{SgPluginFilter, SgPluginFilterInitDto, SgRoutePluginContext};
+
+def_filter!("cors", SgFilterCorsDef, SgFilterCors);
+
+/// Cross-Origin Resource Sharing, see [CORS protocol](https://fetch.spec.whatwg.org/#http-cors-protocol).
+///
+/// Preflight requests (`OPTIONS` with `Origin` and `Access-Control-Request-Method`) are answered by the filter without being forwarded,
+/// preflight requests of disallowed origins, methods or headers are answered with `403`.
+/// Responses to actual requests from allowed origins are decorated, the others are left unchanged so that the browser blocks them.
+///
+/// Preflight requests carry no credentials, so the filter must be executed before filters rejecting anonymous requests, e.g. [jwt](super::jwt).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgFilterCors {
+    /// Allowed origins, compared case-insensitively.
+    ///
+    /// `*` allows any origin, a `*` inside an origin matches any subdomain, e.g. `https://*.idealworld.group`.
+    pub allow_origins: Vec<String>,
+    /// Regular expressions of allowed origins, e.g. `^https://.+\.idealworld\.group(:\d+)?$`.
+    pub allow_origin_regulars: Vec<String>,
+    /// Allowed methods, `*` allows any method. Default is `GET`, `HEAD` and `POST`.
+    pub allow_methods: Vec<String>,
+    /// Allowed request headers, `*` allows any header.
+    pub allow_headers: Vec<String>,
+    /// Response headers readable by the browser besides the safelisted ones.
+    pub expose_headers: Vec<String>,
+    /// Whether credentials (cookies, authorization headers and client certificates) are allowed,
+    /// the origin is echoed instead of `*` then.
+    pub allow_credentials: bool,
+    /// How long the result of a preflight request may be cached by the browser, in seconds.
+    pub max_age_secs: Option<u64>,
+    #[serde(skip)]
+    origin_regulars: Vec<Regex>,
+}
+
+impl Default for SgFilterCors {
+    fn default() -> Self {
+        Self {
+            allow_origins: vec![],
+            allow_origin_regulars: vec![],
+            allow_methods: vec![Method::GET.to_string(), Method::HEAD.to_string(), Method::POST.to_string()],
+            allow_headers: vec![],
+            expose_headers: vec![],
+            allow_credentials: false,
+            max_age_secs: None,
+            origin_regulars: vec![],
+        }
+    }
+}
+
+impl SgFilterCors {
+    fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.allow_origins.iter().any(|allowed| match allowed.split_once('*') {
+            Some(("", "")) => true,
+            Some((prefix, suffix)) => {
+                origin.len() > prefix.len() + suffix.len()
+                    && origin[..prefix.len()].eq_ignore_ascii_case(prefix)
+                    && origin[origin.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+            }
+            None => allowed.eq_ignore_ascii_case(origin),
+        }) || self.origin_regulars.iter().any(|regular| regular.is_match(origin))
+    }
+
+    fn is_method_allowed(&self, method: &str) -> bool {
+        self.allow_methods.iter().any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(method))
+    }
+
+    /// `headers` is the comma separated value of `Access-Control-Request-Headers`.
+    fn is_headers_allowed(&self, headers: &str) -> bool {
+        headers
+            .split(',')
+            .map(str::trim)
+            .filter(|header| !header.is_empty())
+            .all(|header| self.allow_headers.iter().any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(header)))
+    }
+
+    /// Value of `Access-Control-Allow-Origin`, `*` is not allowed with credentials.
+    fn allow_origin<'a>(&self, origin: &'a str) -> &'a str {
+        if !self.allow_credentials && self.allow_origins.iter().any(|allowed| allowed == "*") {
+            "*"
+        } else {
+            origin
+        }
+    }
+}
+
+fn is_preflight(ctx: &SgRoutePluginContext) -> bool {
+    ctx.request.get_method() == Method::OPTIONS && ctx.request.get_headers().contains_key(ORIGIN) && ctx.request.get_headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+#[async_trait]
+impl SgPluginFilter for SgFilterCors {
+    fn accept(&self) -> super::SgPluginFilterAccept {
+        super::SgPluginFilterAccept {
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc],
+            ..Default::default()
+        }
+    }
+
+    async fn init(&mut self, _: &SgPluginFilterInitDto) -> TardisResult<()> {
+        self.origin_regulars = self
+            .allow_origin_regulars
+            .iter()
+            .map(|regular| Regex::new(regular).map_err(|error| TardisError::format_error(&format!("[SG.Filter.Cors] Origin regular {regular} format error: {error}"), "")))
+            .collect::<TardisResult<Vec<_>>>()?;
+        Ok(())
+    }
+
+    async fn destroy(&self) -> TardisResult<()> {
+        Ok(())
+    }
+
+    async fn req_filter(&self, _: &str, mut ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        if !is_preflight(&ctx) {
+            return Ok((true, ctx));
+        }
+        let headers = ctx.request.get_headers();
+        let origin = headers.get(ORIGIN).and_then(|origin| origin.to_str().ok()).unwrap_or_default().to_string();
+        let request_method = headers.get(ACCESS_CONTROL_REQUEST_METHOD).and_then(|method| method.to_str().ok()).unwrap_or_default().to_string();
+        let request_headers = headers.get(ACCESS_CONTROL_REQUEST_HEADERS).and_then(|headers| headers.to_str().ok()).unwrap_or_default().to_string();
+        ctx.set_action(SgRouteFilterRequestAction::Response);
+        // The answer depends on these request headers
+        ctx.response.get_headers_mut().append(VARY, HeaderValue::from_static("Origin, Access-Control-Request-Method, Access-Control-Request-Headers"));
+        if !self.is_origin_allowed(&origin) || !self.is_method_allowed(&request_method) || !self.is_headers_allowed(&request_headers) {
+            log::debug!("[SG.Filter.Cors] Preflight request from origin {origin} with method {request_method} and headers [{request_headers}] is rejected");
+            ctx.response.set_status_code(StatusCode::FORBIDDEN);
+            return Ok((false, ctx));
+        }
+        ctx.response.set_status_code(StatusCode::NO_CONTENT);
+        ctx.response.set_header(ACCESS_CONTROL_ALLOW_ORIGIN, self.allow_origin(&origin))?;
+        if self.allow_credentials {
+            ctx.response.set_header(ACCESS_CONTROL_ALLOW_CREDENTIALS, "true")?;
+        }
+        // Wildcards are echoed, as `*` is not honoured with credentials
+        if self.allow_methods.iter().any(|allowed| allowed == "*") {
+            ctx.response.set_header(ACCESS_CONTROL_ALLOW_METHODS, &request_method)?;
+        } else {
+            ctx.response.set_header(ACCESS_CONTROL_ALLOW_METHODS, &self.allow_methods.join(", "))?;
+        }
+        if !request_headers.is_empty() {
+            if self.allow_headers.iter().any(|allowed| allowed == "*") {
+                ctx.response.set_header(ACCESS_CONTROL_ALLOW_HEADERS, &request_headers)?;
+            } else {
+                ctx.response.set_header(ACCESS_CONTROL_ALLOW_HEADERS, &self.allow_headers.join(", "))?;
+            }
+        }
+        if let Some(max_age_secs) = self.max_age_secs {
+            ctx.response.set_header(ACCESS_CONTROL_MAX_AGE, &max_age_secs.to_string())?;
+        }
+        Ok((false, ctx))
+    }
+
+    async fn resp_filter(&self, _: &str, mut ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        // Preflight requests are answered in the request filter
+        if is_preflight(&ctx) && ctx.get_action() == &SgRouteFilterRequestAction::Response {
+            return Ok((true, ctx));
+        }
+        let Some(origin) = ctx.request.get_headers().get(ORIGIN).and_then(|origin| origin.to_str().ok()).map(|origin| origin.to_string()) else {
+            return Ok((true, ctx));
+        };
+        if !self.is_origin_allowed(&origin) {
+            return Ok((true, ctx));
+        }
+        let allow_origin = self.allow_origin(&origin);
+        ctx.response.set_header(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)?;
+        if allow_origin != "*" {
+            ctx.response.get_headers_mut().append(VARY, HeaderValue::from_static("Origin"));
+        }
+        if self.allow_credentials {
+            ctx.response.set_header(ACCESS_CONTROL_ALLOW_CREDENTIALS, "true")?;
+        }
+        if !self.expose_headers.is_empty() {
+            ctx.response.set_header(ACCESS_CONTROL_EXPOSE_HEADERS, &self.expose_headers.join(", "))?;
+        }
+        Ok((true, ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, Method, StatusCode};
+    use serde_json::json;
+    use tardis::tokio;
+
+    use super::*;
+    use crate::plugins::filters::{test_fixtures, SgAttachedLevel};
+
+    fn new_ctx(method: Method, headers: &[(&'static str, &str)]) -> SgRoutePluginContext {
+        let mut header_map = HeaderMap::new();
+        for (key, value) in headers {
+            header_map.insert(*key, value.parse().unwrap());
+        }
+        test_fixtures::new_ctx(method, "http://sg.idealworld.group/iam/ct", header_map, "127.0.0.1:8080", "")
+    }
+
+    #[tokio::test]
+    async fn test_cors() {
+        let mut filter = tardis::TardisFuns::json
+            .json_to_obj::<SgFilterCors>(json!({
+                "allow_origins": ["https://sg.idealworld.group", "https://*.idealworld.group"],
+                "allow_origin_regulars": ["^http://localhost(:\\d+)?$"],
+                "allow_methods": ["GET", "POST", "PUT"],
+                "allow_headers": ["Content-Type", "Authorization"],
+                "expose_headers": ["X-Request-Id"],
+                "allow_credentials": true,
+                "max_age_secs": 600
+            }))
+            .unwrap();
+        filter.init(&test_fixtures::init_dto(SgAttachedLevel::Rule)).await.unwrap();
+
+        // Preflight requests are answered directly
+        let ctx = new_ctx(
+            Method::OPTIONS,
+            &[
+                ("Origin", "https://app.idealworld.group"),
+                ("Access-Control-Request-Method", "PUT"),
+                ("Access-Control-Request-Headers", "content-type, authorization"),
+            ],
+        );
+        let (is_continue, ctx) = filter.req_filter("", ctx).await.unwrap();
+        assert!(!is_continue);
+        assert_eq!(ctx.get_action(), &SgRouteFilterRequestAction::Response);
+        assert_eq!(ctx.response.get_status_code(), &StatusCode::NO_CONTENT);
+        let headers = ctx.response.get_headers();
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://app.idealworld.group");
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_METHODS).unwrap(), "GET, POST, PUT");
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap(), "Content-Type, Authorization");
+        assert_eq!(headers.get(ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+        let (_, ctx) = filter.resp_filter("", ctx).await.unwrap();
+        assert_eq!(ctx.response.get_headers().get_all(VARY).iter().count(), 1);
+
+        // Disallowed origins, methods and headers
+        for headers in [
+            [
+                ("Origin", "https://idealworld.group"),
+                ("Access-Control-Request-Method", "GET"),
+                ("Access-Control-Request-Headers", ""),
+            ],
+            [
+                ("Origin", "http://localhost:8080"),
+                ("Access-Control-Request-Method", "DELETE"),
+                ("Access-Control-Request-Headers", ""),
+            ],
+            [
+                ("Origin", "http://localhost"),
+                ("Access-Control-Request-Method", "GET"),
+                ("Access-Control-Request-Headers", "X-Custom"),
+            ],
+        ] {
+            let (is_continue, ctx) = filter.req_filter("", new_ctx(Method::OPTIONS, &headers)).await.unwrap();
+            assert!(!is_continue);
+            assert_eq!(ctx.response.get_status_code(), &StatusCode::FORBIDDEN);
+            assert!(ctx.response.get_headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        }
+
+        // Actual requests are forwarded and their responses decorated
+        let ctx = new_ctx(Method::POST, &[("Origin", "http://localhost:8080")]);
+        let (is_continue, ctx) = filter.req_filter("", ctx).await.unwrap();
+        assert!(is_continue);
+        assert_eq!(ctx.get_action(), &SgRouteFilterRequestAction::None);
+        let (_, ctx) = filter.resp_filter("", ctx).await.unwrap();
+        let headers = ctx.response.get_headers();
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "http://localhost:8080");
+        assert_eq!(headers.get(ACCESS_CONTROL_EXPOSE_HEADERS).unwrap(), "X-Request-Id");
+        assert_eq!(headers.get(VARY).unwrap(), "Origin");
+
+        let (_, ctx) = filter.resp_filter("", new_ctx(Method::GET, &[("Origin", "https://evil.com")])).await.unwrap();
+        assert!(ctx.response.get_headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        let (_, ctx) = filter.resp_filter("", new_ctx(Method::GET, &[])).await.unwrap();
+        assert!(ctx.response.get_headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        // Any origin
+        let mut filter = tardis::TardisFuns::json.json_to_obj::<SgFilterCors>(json!({ "allow_origins": ["*"], "allow_methods": ["*"], "allow_headers": ["*"] })).unwrap();
+        filter.init(&test_fixtures::init_dto(SgAttachedLevel::Rule)).await.unwrap();
+        let ctx = new_ctx(
+            Method::OPTIONS,
+            &[
+                ("Origin", "https://evil.com"),
+                ("Access-Control-Request-Method", "PATCH"),
+                ("Access-Control-Request-Headers", "x-custom"),
+            ],
+        );
+        let (_, ctx) = filter.req_filter("", ctx).await.unwrap();
+        let headers = ctx.response.get_headers();
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_METHODS).unwrap(), "PATCH");
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_HEADERS).unwrap(), "x-custom");
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+        let (_, ctx) = filter.resp_filter("", new_ctx(Method::GET, &[("Origin", "https://evil.com")])).await.unwrap();
+        assert_eq!(ctx.response.get_headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+        assert!(ctx.response.get_headers().get(VARY).is_none());
+
+        // Invalid origin regulars
+        let mut filter = tardis::TardisFuns::json.json_to_obj::<SgFilterCors>(json!({ "allow_origin_regulars": ["("] })).unwrap();
+        assert!(filter.init(&test_fixtures::init_dto(SgAttachedLevel::Rule)).await.is_err());
+    }
+}
@@ -27,7 +27,7 @@ pub enum SgFilterHeaderModifierKind {
 impl SgPluginFilter for SgFilterHeaderModifier {
     fn accept(&self) -> super::SgPluginFilterAccept {
         super::SgPluginFilterAccept {
-            kind: vec![super::SgPluginFilterKind::Http],
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc],
             ..Default::default()
         }
     }
@@ -0,0 +1,297 @@
+use std::net::{IpAddr, SocketAddr};
+
+use async_trait::async_trait;
+use http::{HeaderName, StatusCode};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use tardis::basic::{error::TardisError, result::TardisResult};
+use tardis::log;
+
+use crate::def_filter;
+use crate::plugins::context::SgRouteFilterRequestAction;
+
+use super::{SgPluginFilter, SgPluginFilterInitDto, SgRoutePluginContext};
+
+def_filter!("ip_restriction", SgFilterIpRestrictionDef, SgFilterIpRestriction);
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Allow or deny requests by the client ip.
+///
+/// Requests from the `deny` list are rejected, then requests outside the `allow` list are rejected if the list is not empty.
+/// Requests whose client ip cannot be parsed are rejected as well.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgFilterIpRestriction {
+    /// Allowed ips or CIDR ranges, e.g. `10.0.0.0/8`, `172.30.30.30`, `fd00::/8`.
+    pub allow: Vec<String>,
+    /// Denied ips or CIDR ranges, take precedence over `allow`.
+    pub deny: Vec<String>,
+    /// Number of trusted proxies in front of the gateway.
+    ///
+    /// `0` uses the socket address, otherwise the client ip is taken from `X-Forwarded-For`,
+    /// skipping the addresses appended by the trusted proxies and the gateway, the socket peer counting as the nearest proxy.
+    pub trusted_proxy_hops: usize,
+    /// Status code of rejected requests.
+    pub status_code: u16,
+    /// Body of rejected requests.
+    pub body: String,
+    #[serde(skip)]
+    allow_trie: IpPrefixTrie,
+    #[serde(skip)]
+    deny_trie: IpPrefixTrie,
+}
+
+impl Default for SgFilterIpRestriction {
+    fn default() -> Self {
+        Self {
+            allow: vec![],
+            deny: vec![],
+            trusted_proxy_hops: 0,
+            status_code: StatusCode::FORBIDDEN.as_u16(),
+            body: "Forbidden".to_string(),
+            allow_trie: IpPrefixTrie::default(),
+            deny_trie: IpPrefixTrie::default(),
+        }
+    }
+}
+
+impl SgFilterIpRestriction {
+    fn client_ip(&self, ctx: &SgRoutePluginContext) -> Option<IpAddr> {
+        let remote_ip = ctx.request.get_remote_addr().ip();
+        if self.trusted_proxy_hops == 0 {
+            return Some(remote_ip);
+        }
+        let forwarded = ctx
+            .request
+            .get_headers()
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+            .collect::<Vec<_>>();
+        if forwarded.is_empty() {
+            return Some(remote_ip);
+        }
+        // The last address is the socket peer appended by the gateway, with fewer addresses than trusted proxies the farthest one is used
+        let ip = forwarded[forwarded.len().saturating_sub(1 + self.trusted_proxy_hops)];
+        ip.parse::<IpAddr>().or_else(|_| ip.parse::<SocketAddr>().map(|addr| addr.ip())).ok()
+    }
+
+    pub fn check_ip(&self, ip: &IpAddr) -> bool {
+        !self.deny_trie.contains(ip) && (self.allow.is_empty() || self.allow_trie.contains(ip))
+    }
+}
+
+fn parse_ip_nets(ips: &[String]) -> TardisResult<Vec<IpNet>> {
+    ips.iter()
+        .map(|ip| {
+            ip.parse::<IpNet>()
+                .or(ip.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|error| TardisError::format_error(&format!("[SG.Filter.IpRestriction] Ip {ip} parsing error: {error}"), ""))
+        })
+        .collect()
+}
+
+#[async_trait]
+impl SgPluginFilter for SgFilterIpRestriction {
+    fn accept(&self) -> super::SgPluginFilterAccept {
+        super::SgPluginFilterAccept {
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc, super::SgPluginFilterKind::Ws],
+            ..Default::default()
+        }
+    }
+
+    async fn init(&mut self, _: &SgPluginFilterInitDto) -> TardisResult<()> {
+        StatusCode::from_u16(self.status_code)
+            .map_err(|error| TardisError::format_error(&format!("[SG.Filter.IpRestriction] Status code {} parsing error: {error}", self.status_code), ""))?;
+        self.allow_trie = IpPrefixTrie::from_nets(&parse_ip_nets(&self.allow)?);
+        self.deny_trie = IpPrefixTrie::from_nets(&parse_ip_nets(&self.deny)?);
+        Ok(())
+    }
+
+    async fn destroy(&self) -> TardisResult<()> {
+        Ok(())
+    }
+
+    async fn req_filter(&self, _: &str, mut ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        let client_ip = self.client_ip(&ctx);
+        if client_ip.is_some_and(|ip| self.check_ip(&ip)) {
+            return Ok((true, ctx));
+        }
+        log::debug!("[SG.Filter.IpRestriction] Request from {client_ip:?} is rejected");
+        ctx.set_action(SgRouteFilterRequestAction::Response);
+        ctx.response.set_status_code(StatusCode::from_u16(self.status_code).unwrap_or(StatusCode::FORBIDDEN));
+        ctx.response.set_body(self.body.clone());
+        Ok((false, ctx))
+    }
+
+    async fn resp_filter(&self, _: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        Ok((true, ctx))
+    }
+}
+
+/// Binary trie of network prefixes, a lookup takes at most 32 (IPv4) or 128 (IPv6) steps whatever the number of prefixes.
+#[derive(Debug, Clone, Default)]
+struct IpPrefixTrie {
+    v4: PrefixTrie,
+    v6: PrefixTrie,
+}
+
+impl IpPrefixTrie {
+    fn from_nets(nets: &[IpNet]) -> Self {
+        let mut trie = Self::default();
+        for net in nets {
+            match net.trunc() {
+                IpNet::V4(net) => trie.v4.insert((u32::from(net.addr()) as u128) << 96, net.prefix_len()),
+                IpNet::V6(net) => trie.v6.insert(u128::from(net.addr()), net.prefix_len()),
+            }
+        }
+        trie
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => self.v4.contains((u32::from(*ip) as u128) << 96),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => self.v4.contains((u32::from(ip) as u128) << 96),
+                None => self.v6.contains(u128::from(*ip)),
+            },
+        }
+    }
+}
+
+/// Prefixes are stored left-aligned in `u128`s.
+#[derive(Debug, Clone, Default)]
+struct PrefixTrie {
+    nodes: Vec<PrefixTrieNode>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PrefixTrieNode {
+    children: [Option<usize>; 2],
+    /// A prefix ends at this node, so every address below it matches.
+    terminal: bool,
+}
+
+impl PrefixTrie {
+    fn insert(&mut self, bits: u128, prefix_len: u8) {
+        if self.nodes.is_empty() {
+            self.nodes.push(PrefixTrieNode::default());
+        }
+        let mut node = 0;
+        for i in 0..prefix_len {
+            if self.nodes[node].terminal {
+                // Already covered by a shorter prefix
+                return;
+            }
+            let bit = (bits >> (127 - i) & 1) as usize;
+            node = match self.nodes[node].children[bit] {
+                Some(child) => child,
+                None => {
+                    self.nodes.push(PrefixTrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children[bit] = Some(child);
+                    child
+                }
+            };
+        }
+        self.nodes[node].terminal = true;
+    }
+
+    fn contains(&self, bits: u128) -> bool {
+        let Some(mut node) = self.nodes.first() else {
+            return false;
+        };
+        for i in 0..128 {
+            if node.terminal {
+                return true;
+            }
+            match node.children[(bits >> (127 - i) & 1) as usize] {
+                Some(child) => node = &self.nodes[child],
+                None => return false,
+            }
+        }
+        node.terminal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Method, Request, StatusCode};
+    use hyper::Body;
+    use serde_json::json;
+    use tardis::tokio;
+
+    use super::*;
+    use crate::functions::http_route::process_request_headers;
+    use crate::plugins::filters::{test_fixtures, SgAttachedLevel, SgPluginFilterDef};
+
+    /// `X-Forwarded-For` is processed by the gateway as it is before the filters run.
+    fn new_ctx(remote_addr: &str, forwarded_for: &[&str]) -> SgRoutePluginContext {
+        let mut request = Request::new(Body::empty());
+        for value in forwarded_for {
+            request.headers_mut().append(X_FORWARDED_FOR, value.parse().unwrap());
+        }
+        process_request_headers(&mut request, remote_addr.parse().unwrap()).unwrap();
+        test_fixtures::new_ctx(Method::GET, "http://sg.idealworld.group", request.into_parts().0.headers, remote_addr, "")
+    }
+
+    async fn new_filter(spec: serde_json::Value) -> TardisResult<Box<dyn SgPluginFilter>> {
+        let mut filter = SgFilterIpRestrictionDef {}.inst(spec)?;
+        filter.init(&test_fixtures::init_dto(SgAttachedLevel::Gateway)).await?;
+        Ok(filter)
+    }
+
+    async fn is_allowed(filter: &dyn SgPluginFilter, ctx: SgRoutePluginContext) -> bool {
+        filter.req_filter("", ctx).await.unwrap().0
+    }
+
+    #[test]
+    fn test_prefix_trie() {
+        let nets = ["10.0.0.0/8", "10.1.0.0/16", "192.168.1.7", "172.16.0.1/12", "fd00::/8", "::1"]
+            .iter()
+            .map(|net| net.parse().or(net.parse::<IpAddr>().map(IpNet::from)).unwrap())
+            .collect::<Vec<_>>();
+        let trie = IpPrefixTrie::from_nets(&nets);
+        for ip in ["10.0.0.1", "10.255.255.255", "192.168.1.7", "172.31.0.1", "::ffff:10.0.0.1", "fd12::1", "::1"] {
+            assert!(trie.contains(&ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["11.0.0.1", "192.168.1.8", "172.32.0.1", "fe80::1", "::2"] {
+            assert!(!trie.contains(&ip.parse().unwrap()), "{ip}");
+        }
+        assert!(IpPrefixTrie::from_nets(&["0.0.0.0/0".parse().unwrap()]).contains(&"1.2.3.4".parse().unwrap()));
+        assert!(!IpPrefixTrie::default().contains(&"1.2.3.4".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_ip_restriction() {
+        assert!(new_filter(json!({ "allow": ["10.0.0.0/33"] })).await.is_err());
+        assert!(new_filter(json!({ "status_code": 1000 })).await.is_err());
+
+        let filter = new_filter(json!({ "allow": ["10.0.0.0/8", "192.168.1.0/24"], "deny": ["10.1.0.0/16"], "status_code": 401, "body": "Denied" })).await.unwrap();
+        assert!(is_allowed(filter.as_ref(), new_ctx("10.0.0.1:10000", &[])).await);
+        assert!(is_allowed(filter.as_ref(), new_ctx("192.168.1.1:10000", &[])).await);
+        assert!(!is_allowed(filter.as_ref(), new_ctx("10.1.0.1:10000", &[])).await);
+        // The socket address is used without trusted proxies
+        let (is_continue, mut ctx) = filter.req_filter("", new_ctx("172.30.30.30:10000", &["10.0.0.1"])).await.unwrap();
+        assert!(!is_continue);
+        assert_eq!(ctx.get_action(), &SgRouteFilterRequestAction::Response);
+        assert_eq!(ctx.response.get_status_code(), &StatusCode::UNAUTHORIZED);
+        assert_eq!(String::from_utf8(ctx.response.dump_body().await.unwrap().to_vec()).unwrap(), "Denied");
+
+        let filter = new_filter(json!({ "deny": ["192.168.1.0/24"], "trusted_proxy_hops": 2 })).await.unwrap();
+        assert!(is_allowed(filter.as_ref(), new_ctx("10.0.0.1:10000", &[])).await);
+        assert!(!is_allowed(filter.as_ref(), new_ctx("192.168.1.1:10000", &[])).await);
+        // The socket peer 10.0.0.1 and the trusted proxy 10.0.0.2 in front of it are skipped
+        assert!(!is_allowed(filter.as_ref(), new_ctx("10.0.0.1:10000", &["192.168.1.1, 10.0.0.2"])).await);
+        assert!(is_allowed(filter.as_ref(), new_ctx("10.0.0.1:10000", &["192.168.1.1, 1.1.1.1, 10.0.0.2"])).await);
+        assert!(!is_allowed(filter.as_ref(), new_ctx("10.0.0.1:10000", &["1.1.1.1, 192.168.1.1:5678", "10.0.0.2"])).await);
+        assert!(is_allowed(filter.as_ref(), new_ctx("10.0.0.1:10000", &["192.168.1.1, 1.1.1.1", "10.0.0.2"])).await);
+        // Fewer addresses than trusted proxies
+        assert!(!is_allowed(filter.as_ref(), new_ctx("10.0.0.1:10000", &["192.168.1.1"])).await);
+        assert!(!is_allowed(filter.as_ref(), new_ctx("10.0.0.1:10000", &["unknown, 10.0.0.2"])).await);
+    }
+}
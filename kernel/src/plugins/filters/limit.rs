@@ -1,22 +1,98 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use tardis::{
     basic::{error::TardisError, result::TardisResult},
     cache::Script,
+    log, TardisFuns,
 };
 
+use super::local_limit::{record_rate_limit, reject_too_many_requests, set_rate_limit_headers, SgLimitKey, SgRateLimitStatus};
 use super::{SgPluginFilter, SgPluginFilterInitDto, SgRoutePluginContext};
 use crate::def_filter;
 use lazy_static::lazy_static;
 
 def_filter!("limit", SgFilterLimitDef, SgFilterLimit);
 
+/// Distributed rate limiting, counters are shared by the gateway instances through the cache.
+///
+/// Rejected requests are answered with `429` and `Retry-After`,
+/// the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers are added to the responses.
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct SgFilterLimit {
+    /// Shorthand of a quota counting all requests, allowing `max_request_number` requests per `time_window_ms`.
     pub max_request_number: Option<u64>,
     pub time_window_ms: Option<u64>,
+    /// Quotas checked together, a request exceeding any of them is rejected and counted by none.
+    #[serde(default)]
+    pub quotas: Vec<SgLimitQuota>,
+    #[serde(skip)]
+    all_quotas: Vec<SgLimitQuota>,
+}
+
+/// Allows `rate` requests per `period_ms` for each combination of descriptor values,
+/// with the [generic cell rate algorithm](https://en.wikipedia.org/wiki/Generic_cell_rate_algorithm).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgLimitQuota {
+    /// Combined into the counter key, e.g. client ip and path, all requests are counted together if empty.
+    pub descriptors: Vec<SgLimitKey>,
+    pub rate: u64,
+    pub period_ms: u64,
+    /// Maximum number of requests at once, default is `rate`.
+    pub burst: Option<u64>,
+}
+
+impl Default for SgLimitQuota {
+    fn default() -> Self {
+        Self {
+            descriptors: vec![],
+            rate: 100,
+            period_ms: 1000,
+            burst: None,
+        }
+    }
+}
+
+impl SgLimitQuota {
+    /// Emission interval in microseconds, so that rates above one request per millisecond are kept.
+    fn interval_us(&self) -> u64 {
+        (self.period_ms.saturating_mul(1000) / self.rate).max(1)
+    }
+
+    fn capacity_us(&self) -> u64 {
+        self.interval_us().saturating_mul(self.burst.unwrap_or(self.rate))
+    }
+
+    /// Status from the delay of the next request returned by [SCRIPT].
+    fn status(&self, wait_us: u64, counted: bool) -> SgRateLimitStatus {
+        let (interval, capacity) = (self.interval_us(), self.capacity_us());
+        let limit = self.burst.unwrap_or(self.rate);
+        let tat = wait_us.saturating_sub(interval);
+        if wait_us > capacity {
+            SgRateLimitStatus {
+                limit,
+                remaining: 0,
+                reset: Duration::from_micros(tat),
+                retry_after: Some(Duration::from_micros(wait_us - capacity)),
+            }
+        } else if counted {
+            SgRateLimitStatus {
+                limit,
+                remaining: (capacity - wait_us) / interval,
+                reset: Duration::from_micros(wait_us),
+                retry_after: None,
+            }
+        } else {
+            SgRateLimitStatus {
+                limit,
+                remaining: (capacity - tat) / interval,
+                reset: Duration::from_micros(tat),
+                retry_after: None,
+            }
+        }
+    }
 }
 
 const CONF_LIMIT_KEY: &str = "sg:plugin:filter:limit:";
@@ -26,57 +102,58 @@ lazy_static! {
     ///
     /// # Arguments
     ///
-    /// * KEYS[1]  counter key
-    /// * KEYS[2]  last counter reset timestamp key
-    /// * ARGV[1]  maximum number of request
-    /// * ARGV[2]  time window
-    /// * ARGV[3]  current timestamp
+    /// * KEYS[i]        theoretical arrival time key of the i-th quota
+    /// * ARGV[1]        current timestamp in microseconds
+    /// * ARGV[i*2]      emission interval of the i-th quota in microseconds
+    /// * ARGV[i*2+1]    capacity (interval * burst) of the i-th quota in microseconds
     ///
     /// # Return
     ///
-    /// * 1   passed
-    /// * 0   limited
+    /// * [1]    1 passed, 0 limited
+    /// * [i+1]  delay of the next request of the i-th quota, including the current one
     ///
     /// # Kernel logic
     ///
     /// ```lua
-    /// -- Use `counter` to accumulate 1 for each request
-    /// local current_count = tonumber(redis.call('incr', KEYS[1]));
-    /// if current_count == 1 then
-    ///     -- The current request is the first request, record the current timestamp
-    ///     redis.call('set', KEYS[2], ARGV[3]);
+    /// local now = tonumber(ARGV[1]);
+    /// local result = {1};
+    /// for i, key in ipairs(KEYS) do
+    ///     -- The theoretical arrival time is the current time for new or expired keys
+    ///     local tat = math.max(tonumber(redis.call('get', key) or now), now);
+    ///     local wait = tat + tonumber(ARGV[i * 2]) - now;
+    ///     result[i + 1] = wait;
+    ///     -- The request would be too early for this quota, so it is limited
+    ///     if wait > tonumber(ARGV[i * 2 + 1]) then
+    ///         result[1] = 0;
+    ///     end
     /// end
-    /// -- When the `counter` value reaches the maximum number of requests
-    /// if current_count > tonumber(ARGV[1]) then
-    ///     local last_refresh_time = tonumber(redis.call('get', KEYS[2]));
-    ///     if last_refresh_time + tonumber(ARGV[2]) > tonumber(ARGV[3]) then
-    ///          -- Last reset time + time window > current time,
-    ///          -- indicating that the request has reached the upper limit within this time period,
-    ///          -- so the request is limited
-    ///         return 0;
+    /// if result[1] == 1 then
+    ///     -- Count the request in every quota, the keys expire once the quotas are fully restored,
+    ///     -- the timestamp is formatted as an integer as it is too long for the default number format
+    ///     for i, key in ipairs(KEYS) do
+    ///         redis.call('set', key, string.format('%d', now + result[i + 1]), 'PX', math.ceil(result[i + 1] / 1000));
     ///     end
-    ///     -- Otherwise reset the counter and timestamp,
-    ///     -- and allow the request
-    ///     redis.call('set', KEYS[1], '1')
-    ///     redis.call('set', KEYS[2], ARGV[3]);
     /// end
-    /// return 1;
+    /// return result;
     /// ```
     static ref SCRIPT: Script = Script::new(
         r"
-    local current_count = tonumber(redis.call('incr', KEYS[1]));
-    if current_count == 1 then
-        redis.call('set', KEYS[2], ARGV[3]);
+    local now = tonumber(ARGV[1]);
+    local result = {1};
+    for i, key in ipairs(KEYS) do
+        local tat = math.max(tonumber(redis.call('get', key) or now), now);
+        local wait = tat + tonumber(ARGV[i * 2]) - now;
+        result[i + 1] = wait;
+        if wait > tonumber(ARGV[i * 2 + 1]) then
+            result[1] = 0;
+        end
     end
-    if current_count > tonumber(ARGV[1]) then
-        local last_refresh_time = tonumber(redis.call('get', KEYS[2]));
-        if last_refresh_time + tonumber(ARGV[2]) > tonumber(ARGV[3]) then
-            return 0;
+    if result[1] == 1 then
+        for i, key in ipairs(KEYS) do
+            redis.call('set', key, string.format('%d', now + result[i + 1]), 'PX', math.ceil(result[i + 1] / 1000));
         end
-        redis.call('set', KEYS[1], '1')
-        redis.call('set', KEYS[2], ARGV[3]);
     end
-    return 1;
+    return result;
     ",
     );
 }
@@ -85,11 +162,24 @@ lazy_static! {
 impl SgPluginFilter for SgFilterLimit {
     fn accept(&self) -> super::SgPluginFilterAccept {
         super::SgPluginFilterAccept {
-            kind: vec![super::SgPluginFilterKind::Http],
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc],
             ..Default::default()
         }
     }
     async fn init(&mut self, _: &SgPluginFilterInitDto) -> TardisResult<()> {
+        let mut all_quotas = self.quotas.clone();
+        if let Some(max_request_number) = self.max_request_number {
+            all_quotas.push(SgLimitQuota {
+                descriptors: vec![],
+                rate: max_request_number,
+                period_ms: self.time_window_ms.unwrap_or(1000),
+                burst: None,
+            });
+        }
+        if all_quotas.iter().any(|quota| quota.rate == 0 || quota.period_ms == 0 || quota.burst == Some(0)) {
+            return Err(TardisError::format_error("[SG.Filter.Limit] rate, period_ms and burst must be greater than 0", ""));
+        }
+        self.all_quotas = all_quotas;
         Ok(())
     }
 
@@ -97,35 +187,41 @@ impl SgPluginFilter for SgFilterLimit {
         Ok(())
     }
 
-    async fn req_filter(&self, id: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
-        if let Some(max_request_number) = &self.max_request_number {
-            let result: &bool = &SCRIPT
-                // counter key
-                .key(format!("{CONF_LIMIT_KEY}{id}"))
-                // last counter reset timestamp key
-                .key(format!("{CONF_LIMIT_KEY}{id}_ts"))
-                // maximum number of request
-                .arg(max_request_number)
-                // time window
-                .arg(self.time_window_ms.unwrap_or(1000))
-                // current timestamp
-                .arg(
-                    SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .map_err(|e| TardisError::internal_error(&format!("[SG.Filter.Limit] invalid timestamp: {e}"), ""))?
-                        .as_millis() as u64,
-                )
-                .invoke_async(&mut ctx.cache().await?.cmd().await?)
-                .await
-                .map_err(|e| TardisError::internal_error(&format!("[SG.Filter.Limit] redis error : {e}"), ""))?;
-            if !result {
-                return Err(TardisError::forbidden("[SG.Filter.Limit] too many requests", ""));
-            }
+    async fn req_filter(&self, id: &str, mut ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        if self.all_quotas.is_empty() {
+            return Ok((true, ctx));
+        }
+        let mut invocation = SCRIPT.prepare_invoke();
+        // current timestamp
+        invocation.arg(
+            SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_err(|e| TardisError::internal_error(&format!("[SG.Filter.Limit] invalid timestamp: {e}"), ""))?.as_micros()
+                as u64,
+        );
+        for (index, quota) in self.all_quotas.iter().enumerate() {
+            let values = quota.descriptors.iter().map(|descriptor| descriptor.value(&ctx)).collect::<Vec<_>>();
+            // theoretical arrival time key, the values are encoded so that different combinations never collide
+            invocation.key(format!("{CONF_LIMIT_KEY}{id}:{index}:{}", TardisFuns::json.obj_to_string(&values)?));
+            invocation.arg(quota.interval_us()).arg(quota.capacity_us());
+        }
+        let result: Vec<u64> =
+            invocation.invoke_async(&mut ctx.cache().await?.cmd().await?).await.map_err(|e| TardisError::internal_error(&format!("[SG.Filter.Limit] redis error : {e}"), ""))?;
+        let passed = result.first() == Some(&1);
+        let mut retry_after = Duration::ZERO;
+        for (quota, wait_us) in self.all_quotas.iter().zip(result.into_iter().skip(1)) {
+            let status = quota.status(wait_us, passed);
+            record_rate_limit(&mut ctx, &status);
+            retry_after = retry_after.max(status.retry_after.unwrap_or_default());
+        }
+        if !passed {
+            log::debug!("[SG.Filter.Limit] Request {} is limited, retry after {retry_after:?}", ctx.get_request_id());
+            reject_too_many_requests(&mut ctx, retry_after)?;
+            return Ok((false, ctx));
         }
         Ok((true, ctx))
     }
 
-    async fn resp_filter(&self, _: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+    async fn resp_filter(&self, _: &str, mut ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        set_rate_limit_headers(&mut ctx)?;
         Ok((true, ctx))
     }
 }
@@ -134,17 +230,51 @@ impl SgPluginFilter for SgFilterLimit {
 mod tests {
     use std::time::Duration;
 
-    use crate::functions::cache_client;
+    use crate::{
+        functions::cache_client,
+        plugins::filters::{test_fixtures, SgAttachedLevel},
+    };
 
     use super::*;
-    use http::{HeaderMap, Method, Uri, Version};
+    use http::{header::RETRY_AFTER, HeaderMap, Method, StatusCode};
     use hyper::Body;
+    use serde_json::json;
     use tardis::{
         test::test_container::TardisTestContainer,
         testcontainers,
         tokio::{self, time::sleep},
     };
 
+    #[test]
+    fn test_quota_status() {
+        let quota = SgLimitQuota {
+            rate: 10,
+            period_ms: 1000,
+            burst: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(quota.status(100_000, true).remaining, 2);
+        assert_eq!(quota.status(300_000, true).remaining, 0);
+        assert_eq!(quota.status(300_000, true).retry_after, None);
+        // Not counted as another quota is exceeded
+        assert_eq!(quota.status(300_000, false).remaining, 1);
+        let status = quota.status(400_000, false);
+        assert_eq!(status.remaining, 0);
+        assert_eq!(status.reset, Duration::from_millis(300));
+        assert_eq!(status.retry_after, Some(Duration::from_millis(100)));
+
+        // More requests than milliseconds in the period
+        let quota = SgLimitQuota {
+            rate: 5000,
+            period_ms: 1000,
+            ..Default::default()
+        };
+        assert_eq!(quota.interval_us(), 200);
+        assert_eq!(quota.capacity_us(), 1_000_000);
+        assert_eq!(quota.status(200, true).remaining, 4999);
+        assert_eq!(quota.status(1_000_200, false).retry_after, Some(Duration::from_micros(200)));
+    }
+
     #[tokio::test]
     async fn test_limit_filter() {
         let docker = testcontainers::clients::Cli::default();
@@ -153,49 +283,78 @@ mod tests {
         let url = format!("redis://127.0.0.1:{port}/0",);
         cache_client::init("test_gate", &url).await.unwrap();
 
-        let filter = SgFilterLimit {
+        let init_dto = test_fixtures::init_dto(SgAttachedLevel::Rule);
+        let mut filter = SgFilterLimit {
             max_request_number: Some(4),
             ..Default::default()
         };
+        filter.init(&init_dto).await.unwrap();
 
-        fn new_ctx() -> SgRoutePluginContext {
-            SgRoutePluginContext::new_http(
-                Method::GET,
-                Uri::from_static("http://sg.idealworld.group/iam/ct/001?name=sg"),
-                Version::HTTP_11,
-                HeaderMap::new(),
-                Body::empty(),
-                "127.0.0.1:8080".parse().unwrap(),
-                "test_gate".to_string(),
-                None,
-                None,
-            )
+        fn new_ctx(remote_addr: &str, path: &'static str) -> SgRoutePluginContext {
+            test_fixtures::new_ctx(Method::GET, path, HeaderMap::new(), remote_addr, "test_gate")
         }
+        async fn is_passed(filter: &SgFilterLimit, id: &str, remote_addr: &str, path: &'static str) -> bool {
+            filter.req_filter(id, new_ctx(remote_addr, path)).await.unwrap().0
+        }
+        const PATH: &str = "http://sg.idealworld.group/iam/ct/001?name=sg";
 
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_err());
-        assert!(filter.req_filter("limit_002", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_err());
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(!is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_002", "127.0.0.1:8080", PATH).await);
+        assert!(!is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
 
         sleep(Duration::from_millis(1100)).await;
 
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_err());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_err());
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(!is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(!is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+
+        sleep(Duration::from_millis(1100)).await;
+
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(!is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+        assert!(!is_passed(&filter, "limit_001", "127.0.0.1:8080", PATH).await);
+
+        // Per client ip and path, 2 requests per second and 3 requests per minute
+        let mut filter = tardis::TardisFuns::json
+            .json_to_obj::<SgFilterLimit>(json!({
+                "quotas": [
+                    { "descriptors": [{ "kind": "client_ip" }, { "kind": "path" }], "rate": 2, "period_ms": 1000 },
+                    { "descriptors": [{ "kind": "client_ip" }, { "kind": "path" }], "rate": 3, "period_ms": 60000 }
+                ]
+            }))
+            .unwrap();
+        filter.init(&init_dto).await.unwrap();
+        const OTHER_PATH: &str = "http://sg.idealworld.group/iam/ct/002";
+
+        assert!(is_passed(&filter, "limit_003", "10.0.0.1:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_003", "10.0.0.1:8081", PATH).await);
+        let (is_continue, ctx) = filter.req_filter("limit_003", new_ctx("10.0.0.1:8080", PATH)).await.unwrap();
+        assert!(!is_continue);
+        assert_eq!(ctx.response.get_status_code(), &StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(ctx.response.get_headers().get(RETRY_AFTER).unwrap(), "1");
+        assert!(is_passed(&filter, "limit_003", "10.0.0.2:8080", PATH).await);
+        assert!(is_passed(&filter, "limit_003", "10.0.0.1:8080", OTHER_PATH).await);
 
         sleep(Duration::from_millis(1100)).await;
 
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_ok());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_err());
-        assert!(filter.req_filter("limit_001", new_ctx()).await.is_err());
+        // The rejected request was not counted by the per minute quota
+        let (is_continue, ctx) = filter.req_filter("limit_003", new_ctx("10.0.0.1:8080", PATH)).await.unwrap();
+        assert!(is_continue);
+        let ctx = ctx.resp(StatusCode::OK, HeaderMap::new(), Body::empty());
+        let (_, ctx) = filter.resp_filter("limit_003", ctx).await.unwrap();
+        assert_eq!(ctx.response.get_headers().get("RateLimit-Remaining").unwrap(), "0");
+        let (is_continue, ctx) = filter.req_filter("limit_003", new_ctx("10.0.0.1:8080", PATH)).await.unwrap();
+        assert!(!is_continue);
+        assert!(ctx.response.get_headers().get(RETRY_AFTER).unwrap().to_str().unwrap().parse::<u64>().unwrap() > 1);
     }
 }
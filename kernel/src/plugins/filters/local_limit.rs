@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use http::header::RETRY_AFTER;
+use http::{HeaderName, StatusCode};
+use serde::{Deserialize, Serialize};
+use tardis::basic::{error::TardisError, result::TardisResult};
+use tardis::log;
+
+use crate::def_filter;
+use crate::plugins::context::SgRouteFilterRequestAction;
+
+use super::{SgPluginFilter, SgPluginFilterInitDto, SgRoutePluginContext};
+
+def_filter!("local_limit", SgFilterLocalLimitDef, SgFilterLocalLimit);
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+const EXT_RATELIMIT_LIMIT: &str = "ratelimit_limit";
+const EXT_RATELIMIT_REMAINING: &str = "ratelimit_remaining";
+const EXT_RATELIMIT_RESET: &str = "ratelimit_reset";
+
+/// Expired entries are purged at most once per interval.
+const PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What requests are counted together.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SgLimitKey {
+    /// The client ip, taken from the PROXY protocol header when the listener accepts one.
+    ClientIp,
+    /// The value of a request header, requests without the header are counted together.
+    Header { name: String },
+    /// All requests passing through the filter, i.e. the gateway, route or rule it is attached to.
+    #[default]
+    Route,
+    /// The request path.
+    Path,
+    /// [SGIdentInfo](crate::plugins::context::SGIdentInfo) id set by the authentication filters, anonymous requests are counted together.
+    Identity,
+}
+
+impl SgLimitKey {
+    pub(crate) fn value(&self, ctx: &SgRoutePluginContext) -> String {
+        match self {
+            SgLimitKey::ClientIp => ctx.request.get_remote_addr().ip().to_string(),
+            SgLimitKey::Header { name } => ctx.request.get_headers().get(name).and_then(|value| value.to_str().ok()).unwrap_or_default().to_string(),
+            SgLimitKey::Route => String::new(),
+            SgLimitKey::Path => ctx.request.get_uri().path().to_string(),
+            SgLimitKey::Identity => ctx.get_cert_info().map(|ident| ident.id.clone()).unwrap_or_default(),
+        }
+    }
+}
+
+/// In-process rate limiting with the [generic cell rate algorithm](https://en.wikipedia.org/wiki/Generic_cell_rate_algorithm),
+/// counters are kept per gateway instance so no cache is required.
+///
+/// `rate` requests are allowed per `period_ms`, evenly spaced, and up to `burst` requests at once.
+/// Rejected requests are answered with `429` and `Retry-After`,
+/// the `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers are added to the responses.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgFilterLocalLimit {
+    pub rate: u64,
+    pub period_ms: u64,
+    /// Default is `rate`.
+    pub burst: Option<u64>,
+    pub key: SgLimitKey,
+    #[serde(skip)]
+    state: Arc<Mutex<SgLocalLimitState>>,
+}
+
+impl Default for SgFilterLocalLimit {
+    fn default() -> Self {
+        Self {
+            rate: 100,
+            period_ms: 1000,
+            burst: None,
+            key: SgLimitKey::default(),
+            state: Arc::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SgLocalLimitState {
+    started: Instant,
+    last_purge: Instant,
+    /// Theoretical arrival time of the next request of each key, in nanoseconds since `started`.
+    tats: HashMap<String, u64>,
+}
+
+impl Default for SgLocalLimitState {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last_purge: now,
+            tats: HashMap::new(),
+        }
+    }
+}
+
+/// Outcome of a rate limit check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SgRateLimitStatus {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Time until the quota is fully restored.
+    pub reset: Duration,
+    /// Time until the request would be allowed, `None` if it is allowed.
+    pub retry_after: Option<Duration>,
+}
+
+impl SgFilterLocalLimit {
+    fn burst(&self) -> u64 {
+        self.burst.unwrap_or(self.rate)
+    }
+
+    fn check(&self, key: String, now: Instant) -> SgRateLimitStatus {
+        let interval = (self.period_ms as u128 * 1_000_000 / self.rate as u128).max(1) as u64;
+        let capacity = interval.saturating_mul(self.burst());
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now_nanos = now.saturating_duration_since(state.started).as_nanos() as u64;
+        if now.saturating_duration_since(state.last_purge) >= PURGE_INTERVAL {
+            state.tats.retain(|_, tat| *tat > now_nanos);
+            state.last_purge = now;
+        }
+        let tat = state.tats.get(&key).copied().unwrap_or(now_nanos).max(now_nanos);
+        let wait = tat + interval - now_nanos;
+        if wait > capacity {
+            return SgRateLimitStatus {
+                limit: self.burst(),
+                remaining: 0,
+                reset: Duration::from_nanos(tat - now_nanos),
+                retry_after: Some(Duration::from_nanos(wait - capacity)),
+            };
+        }
+        state.tats.insert(key, tat + interval);
+        SgRateLimitStatus {
+            limit: self.burst(),
+            remaining: (capacity - wait) / interval,
+            reset: Duration::from_nanos(wait),
+            retry_after: None,
+        }
+    }
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Record the status for the response headers, the most restrictive one is kept when several limits apply.
+pub(crate) fn record_rate_limit(ctx: &mut SgRoutePluginContext, status: &SgRateLimitStatus) {
+    if ctx.get_ext(EXT_RATELIMIT_REMAINING).and_then(|remaining| remaining.parse::<u64>().ok()).is_some_and(|remaining| remaining < status.remaining) {
+        return;
+    }
+    ctx.set_ext(EXT_RATELIMIT_LIMIT, status.limit.to_string());
+    ctx.set_ext(EXT_RATELIMIT_REMAINING, status.remaining.to_string());
+    ctx.set_ext(EXT_RATELIMIT_RESET, ceil_secs(status.reset).to_string());
+}
+
+/// Set the `RateLimit-*` headers recorded by [record_rate_limit].
+pub(crate) fn set_rate_limit_headers(ctx: &mut SgRoutePluginContext) -> TardisResult<()> {
+    for (ext, header) in [
+        (EXT_RATELIMIT_LIMIT, RATELIMIT_LIMIT),
+        (EXT_RATELIMIT_REMAINING, RATELIMIT_REMAINING),
+        (EXT_RATELIMIT_RESET, RATELIMIT_RESET),
+    ] {
+        if let Some(value) = ctx.get_ext(ext).map(|value| value.to_string()) {
+            ctx.response.set_header(header, &value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Answer the request with `429` and `Retry-After`.
+pub(crate) fn reject_too_many_requests(ctx: &mut SgRoutePluginContext, retry_after: Duration) -> TardisResult<()> {
+    ctx.set_action(SgRouteFilterRequestAction::Response);
+    ctx.response.set_status_code(StatusCode::TOO_MANY_REQUESTS);
+    ctx.response.set_header(RETRY_AFTER, &ceil_secs(retry_after).to_string())?;
+    set_rate_limit_headers(ctx)?;
+    ctx.response.set_body("Too Many Requests");
+    Ok(())
+}
+
+#[async_trait]
+impl SgPluginFilter for SgFilterLocalLimit {
+    fn accept(&self) -> super::SgPluginFilterAccept {
+        super::SgPluginFilterAccept {
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc],
+            ..Default::default()
+        }
+    }
+
+    async fn init(&mut self, _: &SgPluginFilterInitDto) -> TardisResult<()> {
+        if self.rate == 0 || self.period_ms == 0 || self.burst() == 0 {
+            return Err(TardisError::format_error("[SG.Filter.LocalLimit] rate, period_ms and burst must be greater than 0", ""));
+        }
+        Ok(())
+    }
+
+    async fn destroy(&self) -> TardisResult<()> {
+        Ok(())
+    }
+
+    async fn req_filter(&self, _: &str, mut ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        let key = self.key.value(&ctx);
+        let status = self.check(key, Instant::now());
+        record_rate_limit(&mut ctx, &status);
+        if let Some(retry_after) = status.retry_after {
+            log::debug!("[SG.Filter.LocalLimit] Request {} is limited, retry after {retry_after:?}", ctx.get_request_id());
+            reject_too_many_requests(&mut ctx, retry_after)?;
+            return Ok((false, ctx));
+        }
+        Ok((true, ctx))
+    }
+
+    async fn resp_filter(&self, _: &str, mut ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        set_rate_limit_headers(&mut ctx)?;
+        Ok((true, ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, Method};
+    use hyper::Body;
+    use serde_json::json;
+    use tardis::tokio;
+
+    use super::*;
+    use crate::plugins::context::SGIdentInfo;
+    use crate::plugins::filters::{test_fixtures, SgAttachedLevel, SgPluginFilterDef};
+
+    fn new_ctx(remote_addr: &str) -> SgRoutePluginContext {
+        test_fixtures::new_ctx(Method::GET, "http://sg.idealworld.group/iam/ct/001", HeaderMap::new(), remote_addr, "")
+    }
+
+    #[test]
+    fn test_gcra() {
+        let filter = SgFilterLocalLimit {
+            rate: 10,
+            period_ms: 1000,
+            burst: Some(3),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        for remaining in [2, 1, 0] {
+            let status = filter.check("a".to_string(), now);
+            assert_eq!(status.remaining, remaining);
+            assert_eq!(status.retry_after, None);
+        }
+        let status = filter.check("a".to_string(), now);
+        assert_eq!(status.remaining, 0);
+        assert_eq!(status.retry_after, Some(Duration::from_millis(100)));
+        assert_eq!(status.reset, Duration::from_millis(300));
+        // Other keys have their own quota
+        assert_eq!(filter.check("b".to_string(), now).retry_after, None);
+        // One request is restored per interval
+        let later = now + Duration::from_millis(100);
+        assert_eq!(filter.check("a".to_string(), later).retry_after, None);
+        assert!(filter.check("a".to_string(), later).retry_after.is_some());
+        // Expired keys are purged
+        let status = filter.check("a".to_string(), now + Duration::from_secs(2));
+        assert_eq!(status.remaining, 2);
+        assert_eq!(filter.state.lock().unwrap().tats.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_local_limit_filter() {
+        assert!(SgFilterLocalLimitDef.inst(json!({ "rate": 0 })).unwrap().init(&test_fixtures::init_dto(SgAttachedLevel::Rule)).await.is_err());
+
+        let mut filter = SgFilterLocalLimitDef.inst(json!({ "rate": 2, "period_ms": 60000, "key": { "kind": "client_ip" } })).unwrap();
+        filter.init(&test_fixtures::init_dto(SgAttachedLevel::Rule)).await.unwrap();
+        let (is_continue, ctx) = filter.req_filter("", new_ctx("10.0.0.1:10000")).await.unwrap();
+        assert!(is_continue);
+        let ctx = ctx.resp(StatusCode::OK, HeaderMap::new(), Body::empty());
+        let (_, ctx) = filter.resp_filter("", ctx).await.unwrap();
+        assert_eq!(ctx.response.get_headers().get(RATELIMIT_LIMIT).unwrap(), "2");
+        assert_eq!(ctx.response.get_headers().get(RATELIMIT_REMAINING).unwrap(), "1");
+        assert_eq!(ctx.response.get_headers().get(RATELIMIT_RESET).unwrap(), "30");
+        assert!(filter.req_filter("", new_ctx("10.0.0.1:10001")).await.unwrap().0);
+        let (is_continue, ctx) = filter.req_filter("", new_ctx("10.0.0.1:10002")).await.unwrap();
+        assert!(!is_continue);
+        assert_eq!(ctx.get_action(), &SgRouteFilterRequestAction::Response);
+        assert_eq!(ctx.response.get_status_code(), &StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(ctx.response.get_headers().get(RETRY_AFTER).unwrap(), "30");
+        assert_eq!(ctx.response.get_headers().get(RATELIMIT_REMAINING).unwrap(), "0");
+        assert!(filter.req_filter("", new_ctx("10.0.0.2:10000")).await.unwrap().0);
+
+        let mut filter = SgFilterLocalLimitDef.inst(json!({ "rate": 1, "period_ms": 100, "key": { "kind": "identity" } })).unwrap();
+        filter.init(&test_fixtures::init_dto(SgAttachedLevel::Rule)).await.unwrap();
+        let new_ident_ctx = |id: &str| {
+            let mut ctx = new_ctx("10.0.0.1:10000");
+            ctx.set_cert_info(SGIdentInfo {
+                id: id.to_string(),
+                name: None,
+                roles: vec![],
+            });
+            ctx
+        };
+        assert!(filter.req_filter("", new_ident_ctx("u1")).await.unwrap().0);
+        assert!(!filter.req_filter("", new_ident_ctx("u1")).await.unwrap().0);
+        assert!(filter.req_filter("", new_ident_ctx("u2")).await.unwrap().0);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(filter.req_filter("", new_ident_ctx("u1")).await.unwrap().0);
+    }
+}
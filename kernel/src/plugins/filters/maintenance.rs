@@ -75,7 +75,7 @@ impl Default for SgFilterMaintenance {
 impl SgPluginFilter for SgFilterMaintenance {
     fn accept(&self) -> super::SgPluginFilterAccept {
         super::SgPluginFilterAccept {
-            kind: vec![super::SgPluginFilterKind::Http],
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc],
             ..Default::default()
         }
     }
@@ -80,8 +80,9 @@ impl Default for SgFilterStatus {
 impl SgPluginFilter for SgFilterStatus {
     fn accept(&self) -> super::SgPluginFilterAccept {
         super::SgPluginFilterAccept {
-            kind: vec![super::SgPluginFilterKind::Http],
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc],
             accept_error_response: true,
+            ..Default::default()
         }
     }
 
@@ -317,8 +318,12 @@ mod tests {
             port: 80,
             timeout_ms: None,
             protocol: Some(crate::config::gateway_dto::SgProtocol::Http),
+            http_version: None,
             weight: None,
             filters: None,
+            health_check: None,
+            tls: None,
+            proxy_protocol: None,
         };
         let docker = testcontainers::clients::Cli::default();
         let _x = docker_init(&docker).await.unwrap();
@@ -332,10 +337,14 @@ mod tests {
                 gateway_name: gateway_name.clone(),
                 gateway_parameters: SgParameters::default(),
                 http_route_rules: vec![SgHttpRouteRule {
+                    name: None,
                     matches: None,
                     filters: None,
                     backends: Some(vec![mock_backend_ref.clone()]),
                     timeout_ms: None,
+                    lb_policy: None,
+                    panic_mode: None,
+                    outlier_detection: None,
                 }],
                 attached_level: crate::plugins::filters::SgAttachedLevel::Gateway,
             })
@@ -347,8 +356,10 @@ mod tests {
             port: mock_backend_ref.port,
             timeout_ms: mock_backend_ref.timeout_ms,
             protocol: mock_backend_ref.protocol,
+            http_version: mock_backend_ref.http_version,
             weight: mock_backend_ref.weight,
             filters: vec![],
+            ..Default::default()
         };
         let mut ctx = SgRoutePluginContext::new_http(
             Method::POST,
@@ -0,0 +1,84 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tardis::basic::result::TardisResult;
+use tardis::log;
+
+use crate::def_filter;
+use crate::functions::trace::{self, SgOtlpExporter, SgTracePropagation, SgTracer};
+
+use super::{SgAttachedLevel, SgPluginFilter, SgPluginFilterInitDto, SgRoutePluginContext};
+
+def_filter!("tracing", SgFilterTracingDef, SgFilterTracing);
+
+/// Distributed tracing of the gateway.
+///
+/// The trace context is extracted from the request headers with the configured `propagation` formats (the first one found wins)
+/// and injected into the upstream request with all of them.
+/// Each request records a server span, with child spans for route matching, every filter and the upstream call.
+///
+/// This filter can only be attached to the gateway.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SgFilterTracing {
+    /// `service.name` of the exported spans.
+    pub service_name: String,
+    pub propagation: Vec<SgTracePropagation>,
+    /// Ratio of sampled traces (0.0 ~ 1.0) when the request does not carry a trace context,
+    /// otherwise the sampling decision of the caller is followed.
+    pub sample_ratio: f64,
+    /// Spans are not exported if this is not set, the trace context is propagated nevertheless.
+    pub exporter: Option<SgOtlpExporter>,
+    #[serde(skip)]
+    gateway_name: Option<String>,
+}
+
+impl Default for SgFilterTracing {
+    fn default() -> Self {
+        Self {
+            service_name: "spacegate".to_string(),
+            propagation: vec![SgTracePropagation::W3c],
+            sample_ratio: 1.0,
+            exporter: None,
+            gateway_name: None,
+        }
+    }
+}
+
+#[async_trait]
+impl SgPluginFilter for SgFilterTracing {
+    fn accept(&self) -> super::SgPluginFilterAccept {
+        super::SgPluginFilterAccept {
+            kind: vec![super::SgPluginFilterKind::Http, super::SgPluginFilterKind::Grpc],
+            accept_error_response: true,
+            ..Default::default()
+        }
+    }
+
+    async fn init(&mut self, init_dto: &SgPluginFilterInitDto) -> TardisResult<()> {
+        if !init_dto.attached_level.eq(&SgAttachedLevel::Gateway) {
+            log::error!("[SG.Filter.Tracing] init filter is only can attached to gateway");
+            return Ok(());
+        }
+        trace::register(
+            &init_dto.gateway_name,
+            SgTracer::new(self.service_name.clone(), self.propagation.clone(), self.sample_ratio, self.exporter.clone()),
+        );
+        self.gateway_name = Some(init_dto.gateway_name.clone());
+        Ok(())
+    }
+
+    async fn destroy(&self) -> TardisResult<()> {
+        if let Some(gateway_name) = &self.gateway_name {
+            trace::unregister(gateway_name);
+        }
+        Ok(())
+    }
+
+    async fn req_filter(&self, _: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        Ok((true, ctx))
+    }
+
+    async fn resp_filter(&self, _: &str, ctx: SgRoutePluginContext) -> TardisResult<(bool, SgRoutePluginContext)> {
+        Ok((true, ctx))
+    }
+}
@@ -35,6 +35,7 @@ async fn test_compression() -> TardisResult<()> {
                         mode: SgTlsMode::Terminate,
                         key: TLS_KEY.to_string(),
                         cert: TLS_CERT.to_string(),
+                        ..Default::default()
                     }),
                     ..Default::default()
                 },
@@ -0,0 +1,119 @@
+use std::{env, time::Duration, vec};
+
+use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, Method, Request, Response};
+use hyper::{body::HttpBody, server::conn::Http, service::service_fn, Body, Client};
+use spacegate_kernel::config::{
+    gateway_dto::{SgGateway, SgListener},
+    http_route_dto::{SgBackendRef, SgGrpcMethodMatch, SgGrpcMethodMatchType, SgHttpPathMatch, SgHttpPathMatchType, SgHttpRoute, SgHttpRouteMatch, SgHttpRouteRule},
+};
+use tardis::{
+    basic::result::TardisResult,
+    tokio::{self, net::TcpListener, time::sleep},
+};
+
+async fn start_grpc_backend(port: u16) -> TardisResult<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(Http::new().http2_only(true).serve_connection(
+                stream,
+                service_fn(|req: Request<Body>| async move {
+                    let (mut sender, body) = Body::channel();
+                    let path = req.uri().path().to_string();
+                    tokio::spawn(async move {
+                        sender.send_data(path.into()).await.unwrap();
+                        let mut trailers = HeaderMap::new();
+                        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+                        sender.send_trailers(trailers).await.unwrap();
+                    });
+                    let mut resp = Response::new(body);
+                    resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+                    Ok::<_, hyper::Error>(resp)
+                }),
+            ));
+        }
+    });
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_grpc() -> TardisResult<()> {
+    env::set_var("RUST_LOG", "info,spacegate_kernel=trace");
+    tracing_subscriber::fmt::init();
+    start_grpc_backend(8893).await?;
+    spacegate_kernel::do_startup(
+        SgGateway {
+            name: "test_gw".to_string(),
+            listeners: vec![SgListener { port: 8892, ..Default::default() }],
+            ..Default::default()
+        },
+        vec![
+            SgHttpRoute {
+                gateway_name: "test_gw".to_string(),
+                rules: Some(vec![SgHttpRouteRule {
+                    matches: Some(vec![SgHttpRouteMatch {
+                        grpc: Some(SgGrpcMethodMatch {
+                            kind: SgGrpcMethodMatchType::Exact,
+                            service: Some("helloworld.Greeter".to_string()),
+                            method: None,
+                        }),
+                        ..Default::default()
+                    }]),
+                    backends: Some(vec![SgBackendRef {
+                        name_or_host: "127.0.0.1".to_string(),
+                        port: 8893,
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+            SgHttpRoute {
+                gateway_name: "test_gw".to_string(),
+                rules: Some(vec![SgHttpRouteRule {
+                    matches: Some(vec![SgHttpRouteMatch {
+                        path: Some(SgHttpPathMatch {
+                            kind: SgHttpPathMatchType::Prefix,
+                            value: "/iam".to_string(),
+                        }),
+                        ..Default::default()
+                    }]),
+                    backends: Some(vec![SgBackendRef {
+                        name_or_host: "127.0.0.1".to_string(),
+                        port: 8894,
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+        ],
+    )
+    .await?;
+    sleep(Duration::from_millis(500)).await;
+
+    let client = Client::builder().http2_only(true).build_http::<Body>();
+    let request =
+        |path: &str| Request::builder().method(Method::POST).uri(format!("http://127.0.0.1:8892{path}")).header(CONTENT_TYPE, "application/grpc").body(Body::empty()).unwrap();
+
+    // Body and trailers of the backend are passed through
+    let mut resp = client.request(request("/helloworld.Greeter/SayHello")).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = hyper::body::to_bytes(resp.body_mut()).await.unwrap();
+    assert_eq!(body, "/helloworld.Greeter/SayHello");
+    let trailers = resp.body_mut().trailers().await.unwrap().unwrap();
+    assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+
+    // Unmatched services are answered with a gRPC status instead of a HTTP error
+    let resp = client.request(request("/helloworld.Other/SayHello")).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), "application/grpc");
+    assert_eq!(resp.headers().get("grpc-status").unwrap(), "12");
+
+    // Errors of plain HTTP routes are not converted whatever the content type
+    let resp = client.request(request("/iam/ct")).await.unwrap();
+    assert_ne!(resp.status().as_u16(), 200);
+    assert!(resp.headers().get("grpc-status").is_none());
+    Ok(())
+}
@@ -111,6 +111,7 @@ async fn test_https() -> TardisResult<()> {
                         mode: SgTlsMode::Terminate,
                         key: TLS_RSA_KEY.to_string(),
                         cert: TLS_CERT.to_string(),
+                        ..Default::default()
                     }),
                     ..Default::default()
                 },
@@ -121,6 +122,7 @@ async fn test_https() -> TardisResult<()> {
                         mode: SgTlsMode::Terminate,
                         key: TLS_PKCS8_KEY.to_string(),
                         cert: TLS_EC_CERT.to_string(),
+                        ..Default::default()
                     }),
                     ..Default::default()
                 },
@@ -131,6 +133,7 @@ async fn test_https() -> TardisResult<()> {
                         mode: SgTlsMode::Terminate,
                         key: TLS_EC_KEY.to_string(),
                         cert: TLS_EC_CERT.to_string(),
+                        ..Default::default()
                     }),
                     ..Default::default()
                 },
@@ -0,0 +1,202 @@
+use std::{env, time::Duration, vec};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use serde_json::json;
+use spacegate_kernel::config::{
+    gateway_dto::{SgGateway, SgListener, SgProtocol, SgProxyProtocolConfig, SgProxyProtocolVersion},
+    http_route_dto::{SgBackendRef, SgHttpRoute, SgHttpRouteRule},
+    plugin_filter_dto::SgRouteFilter,
+    stream_route_dto::{SgStreamBackendRef, SgStreamRoute},
+};
+use tardis::{
+    basic::result::TardisResult,
+    tokio::{
+        self,
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+        time::{sleep, timeout},
+    },
+};
+
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+fn v1_header(source: &str, port: u16) -> Vec<u8> {
+    format!("PROXY TCP4 {source} 127.0.0.1 {port} 8919\r\n").into_bytes()
+}
+
+fn v2_header(source: [u8; 4], port: u16) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x21, 0x11, 0x00, 0x0c]);
+    header.extend_from_slice(&source);
+    header.extend_from_slice(&[127, 0, 0, 1]);
+    header.extend_from_slice(&port.to_be_bytes());
+    header.extend_from_slice(&8919u16.to_be_bytes());
+    header
+}
+
+/// Send a raw HTTP/1.1 request after the `header`, returns the whole response.
+async fn request(port: u16, header: &[u8], host: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    stream.write_all(header).await.unwrap();
+    stream.write_all(format!("GET / HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n").as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    let _ = timeout(Duration::from_secs(3), stream.read_to_end(&mut response)).await.unwrap();
+    String::from_utf8_lossy(&response).to_string()
+}
+
+#[tokio::test]
+async fn test_proxy_protocol() -> TardisResult<()> {
+    env::set_var("RUST_LOG", "info,spacegate_kernel=trace");
+    tracing_subscriber::fmt::init();
+    tokio::spawn(async move {
+        let make_svc = make_service_fn(|_| async { Ok::<_, hyper::Error>(service_fn(|_| async { Ok::<_, hyper::Error>(Response::new(Body::from("backend"))) })) });
+        Server::bind(&"127.0.0.1:8920".parse().unwrap()).serve(make_svc).await.unwrap();
+    });
+    // Backend expecting PROXY protocol v1 headers before the HTTP requests
+    let (http_header_tx, mut http_header_rx) = mpsc::unbounded_channel();
+    let http_backend = TcpListener::bind("127.0.0.1:8921").await?;
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = http_backend.accept().await {
+            let http_header_tx = http_header_tx.clone();
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                http_header_tx.send(line.clone()).unwrap();
+                while line != "\r\n" {
+                    line.clear();
+                    stream.read_line(&mut line).await.unwrap();
+                }
+                stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok").await.unwrap();
+            });
+        }
+    });
+    // Backend expecting PROXY protocol v2 headers, then echoing the bytes
+    let (tcp_header_tx, mut tcp_header_rx) = mpsc::unbounded_channel();
+    let tcp_backend = TcpListener::bind("127.0.0.1:8924").await?;
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = tcp_backend.accept().await {
+            let tcp_header_tx = tcp_header_tx.clone();
+            tokio::spawn(async move {
+                let mut header = vec![0; 28];
+                stream.read_exact(&mut header).await.unwrap();
+                tcp_header_tx.send(header).unwrap();
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    spacegate_kernel::do_startup_with_stream_routes(
+        SgGateway {
+            name: "test_gw".to_string(),
+            listeners: vec![
+                SgListener {
+                    port: 8919,
+                    proxy_protocol: Some(SgProxyProtocolConfig {
+                        trusted_sources: vec!["127.0.0.0/8".to_string()],
+                    }),
+                    ..Default::default()
+                },
+                SgListener {
+                    port: 8922,
+                    proxy_protocol: Some(SgProxyProtocolConfig {
+                        trusted_sources: vec!["10.0.0.0/8".to_string()],
+                    }),
+                    ..Default::default()
+                },
+                SgListener {
+                    name: Some("tcp".to_string()),
+                    port: 8923,
+                    protocol: SgProtocol::Tcp,
+                    proxy_protocol: Some(SgProxyProtocolConfig {
+                        trusted_sources: vec!["127.0.0.1".to_string()],
+                    }),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        },
+        vec![
+            SgHttpRoute {
+                gateway_name: "test_gw".to_string(),
+                hostnames: Some(vec!["maintenance.local".to_string()]),
+                filters: Some(vec![SgRouteFilter {
+                    code: "maintenance".to_string(),
+                    spec: json!({
+                        "exclude_ip_range": ["192.168.0.1"]
+                    }),
+                    ..Default::default()
+                }]),
+                rules: Some(vec![SgHttpRouteRule {
+                    backends: Some(vec![SgBackendRef {
+                        name_or_host: "127.0.0.1".to_string(),
+                        port: 8920,
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+            SgHttpRoute {
+                gateway_name: "test_gw".to_string(),
+                hostnames: Some(vec!["proxy.local".to_string()]),
+                rules: Some(vec![SgHttpRouteRule {
+                    backends: Some(vec![SgBackendRef {
+                        name_or_host: "127.0.0.1".to_string(),
+                        port: 8921,
+                        proxy_protocol: Some(SgProxyProtocolVersion::V1),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+        ],
+        vec![SgStreamRoute {
+            name: "tcp_route".to_string(),
+            gateway_name: "test_gw".to_string(),
+            section_name: Some("tcp".to_string()),
+            backends: vec![SgStreamBackendRef {
+                name_or_host: "127.0.0.1".to_string(),
+                port: 8924,
+                proxy_protocol: Some(SgProxyProtocolVersion::V2),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }],
+    )
+    .await?;
+    sleep(Duration::from_millis(500)).await;
+
+    // The client address from the header is used by the filters
+    assert!(request(8919, &v1_header("192.168.0.1", 56324), "maintenance.local").await.ends_with("backend"));
+    assert!(request(8919, &v2_header([192, 168, 0, 1], 56324), "maintenance.local").await.ends_with("backend"));
+    assert!(request(8919, &v1_header("192.168.0.2", 56324), "maintenance.local").await.contains("<h1>"));
+    assert!(request(8919, &v2_header([192, 168, 0, 2], 56324), "maintenance.local").await.contains("<h1>"));
+    // Trusted peers must send the header
+    assert_eq!(request(8919, b"", "maintenance.local").await, "");
+    // Untrusted peers are handled as direct connections
+    assert!(request(8922, b"", "maintenance.local").await.contains("<h1>"));
+
+    // The client address is sent to the backend
+    assert!(request(8919, &v1_header("192.168.0.1", 56324), "proxy.local").await.ends_with("ok"));
+    assert_eq!(http_header_rx.recv().await.unwrap(), "PROXY TCP4 192.168.0.1 127.0.0.1 56324 8919\r\n");
+
+    let mut stream = TcpStream::connect("127.0.0.1:8923").await?;
+    stream.write_all(&v1_header("192.168.0.3", 56325)).await?;
+    stream.write_all(b"hello").await?;
+    let mut buf = vec![0; 5];
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(buf, b"hello");
+    let header = tcp_header_rx.recv().await.unwrap();
+    assert_eq!(&header[..12], V2_SIGNATURE);
+    assert_eq!(&header[12..16], &[0x21, 0x11, 0x00, 0x0c]);
+    assert_eq!(&header[16..20], &[192, 168, 0, 3]);
+    assert_eq!(&header[24..26], &56325u16.to_be_bytes());
+    assert_eq!(&header[26..28], &8923u16.to_be_bytes());
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::{env, time::Duration, vec};
+
+use spacegate_kernel::config::{
+    gateway_dto::{SgGateway, SgListener, SgProtocol},
+    stream_route_dto::{SgStreamBackendRef, SgStreamRoute},
+};
+use tardis::{
+    basic::result::TardisResult,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream, UdpSocket},
+        time::{sleep, timeout},
+    },
+};
+
+fn new_route(name: &str, section_name: &str, port: u16) -> SgStreamRoute {
+    SgStreamRoute {
+        name: name.to_string(),
+        gateway_name: "test_gw".to_string(),
+        section_name: Some(section_name.to_string()),
+        backends: vec![SgStreamBackendRef {
+            name_or_host: "127.0.0.1".to_string(),
+            port,
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+async fn echo(stream: &mut TcpStream, msg: &[u8]) -> Vec<u8> {
+    stream.write_all(msg).await.unwrap();
+    let mut buf = vec![0; msg.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    buf
+}
+
+#[tokio::test]
+async fn test_stream() -> TardisResult<()> {
+    env::set_var("RUST_LOG", "info,spacegate_kernel=trace");
+    tracing_subscriber::fmt::init();
+    let tcp_backend = TcpListener::bind("127.0.0.1:8914").await?;
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = tcp_backend.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    let udp_backend = UdpSocket::bind("127.0.0.1:8915").await?;
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        while let Ok((len, addr)) = udp_backend.recv_from(&mut buf).await {
+            let _ = udp_backend.send_to(&buf[..len], addr).await;
+        }
+    });
+    spacegate_kernel::do_startup_with_stream_routes(
+        SgGateway {
+            name: "test_gw".to_string(),
+            listeners: vec![
+                SgListener {
+                    name: Some("tcp".to_string()),
+                    port: 8912,
+                    protocol: SgProtocol::Tcp,
+                    ..Default::default()
+                },
+                SgListener {
+                    name: Some("udp".to_string()),
+                    port: 8913,
+                    protocol: SgProtocol::Udp,
+                    ..Default::default()
+                },
+                SgListener {
+                    name: Some("limited".to_string()),
+                    port: 8916,
+                    protocol: SgProtocol::Tcp,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        },
+        vec![],
+        vec![
+            new_route("tcp_route", "tcp", 8914),
+            new_route("udp_route", "udp", 8915),
+            SgStreamRoute {
+                idle_timeout_ms: Some(300),
+                max_connections: Some(1),
+                ..new_route("limited_route", "limited", 8914)
+            },
+        ],
+    )
+    .await?;
+    sleep(Duration::from_millis(500)).await;
+
+    // TCP
+    let mut stream = TcpStream::connect("127.0.0.1:8912").await?;
+    assert_eq!(echo(&mut stream, b"hello").await, b"hello");
+    assert_eq!(echo(&mut stream, b"spacegate").await, b"spacegate");
+    // Half-close is forwarded to the backend, which then closes the connection
+    stream.shutdown().await?;
+    let mut buf = Vec::new();
+    assert_eq!(timeout(Duration::from_secs(1), stream.read_to_end(&mut buf)).await.unwrap()?, 0);
+
+    // UDP
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    socket.connect("127.0.0.1:8913").await?;
+    for msg in [&b"hello"[..], &b"spacegate"[..]] {
+        socket.send(msg).await?;
+        let mut buf = [0; 1024];
+        let len = timeout(Duration::from_secs(1), socket.recv(&mut buf)).await.unwrap()?;
+        assert_eq!(&buf[..len], msg);
+    }
+
+    // Connections over the limit are closed immediately
+    let mut stream = TcpStream::connect("127.0.0.1:8916").await?;
+    assert_eq!(echo(&mut stream, b"hello").await, b"hello");
+    let mut rejected = TcpStream::connect("127.0.0.1:8916").await?;
+    let mut buf = Vec::new();
+    assert_eq!(timeout(Duration::from_secs(1), rejected.read_to_end(&mut buf)).await.unwrap().unwrap_or(0), 0);
+
+    // Idle connections are closed, releasing the slot
+    sleep(Duration::from_millis(600)).await;
+    assert_eq!(timeout(Duration::from_secs(1), stream.read_to_end(&mut buf)).await.unwrap().unwrap_or(0), 0);
+    let mut stream = TcpStream::connect("127.0.0.1:8916").await?;
+    assert_eq!(echo(&mut stream, b"hello").await, b"hello");
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+    vec,
+};
+
+use http::{Request, Response};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Server,
+};
+use serde_json::{json, Value};
+use spacegate_kernel::config::{
+    gateway_dto::{SgGateway, SgListener},
+    http_route_dto::{SgBackendRef, SgHttpRoute, SgHttpRouteRule},
+    plugin_filter_dto::SgRouteFilter,
+};
+use tardis::{
+    basic::result::TardisResult,
+    tokio::{self, time::sleep},
+};
+
+type Captured = Arc<Mutex<Vec<String>>>;
+
+/// Start a server capturing the given header (or the body if `None`) of every request.
+fn start_capture_server(port: u16, header: Option<&'static str>) -> Captured {
+    let captured: Captured = Default::default();
+    let make_svc = {
+        let captured = captured.clone();
+        make_service_fn(move |_| {
+            let captured = captured.clone();
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                    let captured = captured.clone();
+                    async move {
+                        let value = match header {
+                            Some(header) => req.headers().get(header).map(|value| value.to_str().unwrap().to_string()).unwrap_or_default(),
+                            None => String::from_utf8(hyper::body::to_bytes(req.into_body()).await.unwrap().to_vec()).unwrap(),
+                        };
+                        captured.lock().unwrap().push(value);
+                        Ok::<_, hyper::Error>(Response::new(Body::from("ok")))
+                    }
+                }))
+            }
+        })
+    };
+    tokio::spawn(Server::bind(&([127, 0, 0, 1], port).into()).serve(make_svc));
+    captured
+}
+
+#[tokio::test]
+async fn test_tracing() -> TardisResult<()> {
+    env::set_var("RUST_LOG", "info,spacegate_kernel=trace");
+    tracing_subscriber::fmt::init();
+    let upstream = start_capture_server(8896, Some("traceparent"));
+    let collector = start_capture_server(8897, None);
+    spacegate_kernel::do_startup(
+        SgGateway {
+            name: "test_gw".to_string(),
+            listeners: vec![SgListener { port: 8895, ..Default::default() }],
+            filters: Some(vec![SgRouteFilter {
+                code: "tracing".to_string(),
+                spec: json!({
+                    "service_name": "test_gateway",
+                    "exporter": {
+                        "endpoint": "http://127.0.0.1:8897/v1/traces",
+                        "flush_interval_ms": 100
+                    }
+                }),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        vec![SgHttpRoute {
+            gateway_name: "test_gw".to_string(),
+            rules: Some(vec![SgHttpRouteRule {
+                backends: Some(vec![SgBackendRef {
+                    name_or_host: "127.0.0.1".to_string(),
+                    port: 8896,
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }],
+    )
+    .await?;
+    sleep(Duration::from_millis(500)).await;
+
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let client = reqwest::Client::new();
+    let resp = client.get("http://127.0.0.1:8895/hi").header("traceparent", format!("00-{trace_id}-00f067aa0ba902b7-01")).send().await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+
+    // The trace of the caller is continued towards the backend
+    let traceparent = upstream.lock().unwrap().pop().unwrap();
+    assert!(traceparent.starts_with(&format!("00-{trace_id}-")));
+    assert!(traceparent.ends_with("-01"));
+    assert!(!traceparent.contains("00f067aa0ba902b7"));
+
+    sleep(Duration::from_millis(500)).await;
+    let spans = collector
+        .lock()
+        .unwrap()
+        .iter()
+        .flat_map(|body| {
+            let body: Value = serde_json::from_str(body).unwrap();
+            assert_eq!(body["resourceSpans"][0]["resource"]["attributes"][0]["value"]["stringValue"], "test_gateway");
+            body["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap().clone()
+        })
+        .collect::<Vec<_>>();
+    for name in ["request", "route_match", "upstream"] {
+        let span = spans.iter().find(|span| span["name"] == name).unwrap_or_else(|| panic!("span {name} not exported"));
+        assert_eq!(span["traceId"], trace_id);
+    }
+    let request_span = spans.iter().find(|span| span["name"] == "request").unwrap();
+    assert_eq!(request_span["parentSpanId"], "00f067aa0ba902b7");
+    let upstream_span = spans.iter().find(|span| span["name"] == "upstream").unwrap();
+    assert_eq!(upstream_span["parentSpanId"], request_span["spanId"]);
+    assert!(traceparent.contains(upstream_span["spanId"].as_str().unwrap()));
+    Ok(())
+}